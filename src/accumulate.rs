@@ -0,0 +1,483 @@
+use ndarray::{Array1, Array2, s};
+
+use crate::daq::Spectrum;
+
+type Ftype = f32;
+
+/// How the spectra of one integration are combined into an output product.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AccumMode {
+    Sum,
+    Mean,
+    MaxHold,
+    MinHold,
+    Median,
+    /// `y = k * y + (1 - k) * x`, reported once per integration and never reset
+    Exponential(Ftype),
+}
+
+impl std::str::FromStr for AccumMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sum" => Ok(AccumMode::Sum),
+            "mean" => Ok(AccumMode::Mean),
+            "max" => Ok(AccumMode::MaxHold),
+            "min" => Ok(AccumMode::MinHold),
+            "median" => Ok(AccumMode::Median),
+            _ => match s.strip_prefix("exp:").map(|k| k.parse::<Ftype>()) {
+                Some(Ok(k)) if (0.0..1.0).contains(&k) => Ok(AccumMode::Exponential(k)),
+                _ => Err(format!(
+                    "unknown accumulation mode '{s}', can be sum, mean, max, min, median or exp:<k> with 0<=k<1"
+                )),
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for AccumMode {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            AccumMode::Sum => write!(fmt, "sum"),
+            AccumMode::Mean => write!(fmt, "mean"),
+            AccumMode::MaxHold => write!(fmt, "max"),
+            AccumMode::MinHold => write!(fmt, "min"),
+            AccumMode::Median => write!(fmt, "median"),
+            AccumMode::Exponential(k) => write!(fmt, "exp:{k}"),
+        }
+    }
+}
+
+pub struct Accumulator {
+    mode: AccumMode,
+    acc: Array1<Ftype>,
+    /// spectra of the current integration, only kept for the median
+    history: Array2<Ftype>,
+    /// one channel of `history`, sorted in place for the median
+    column: Vec<Ftype>,
+    n: usize,
+    nifs: usize,
+    tainted: bool,
+    primed: bool,
+    sample_index: u64,
+    nsamples: u64,
+    time_ns: i64,
+}
+
+impl Accumulator {
+    /// `len` is the length of the pushed spectra, i.e. `nch * nifs`
+    pub fn new(mode: AccumMode, len: usize, n_average: usize) -> Accumulator {
+        Accumulator {
+            mode,
+            acc: Array1::zeros(len),
+            history: if mode == AccumMode::Median {
+                Array2::zeros((n_average, len))
+            } else {
+                Array2::zeros((0, len))
+            },
+            column: Vec::with_capacity(n_average),
+            n: 0,
+            nifs: 1,
+            tainted: false,
+            primed: false,
+            sample_index: 0,
+            nsamples: 0,
+            time_ns: 0,
+        }
+    }
+
+    pub fn mode(&self) -> AccumMode {
+        self.mode
+    }
+
+    /// number of spectra in the current integration
+    pub fn count(&self) -> usize {
+        self.n
+    }
+
+    pub fn push(&mut self, x: &Spectrum) {
+        let first = self.n == 0;
+        if first {
+            self.sample_index = x.sample_index;
+            self.time_ns = x.time_ns;
+            self.nsamples = 0;
+        }
+        match self.mode {
+            AccumMode::Sum | AccumMode::Mean => self.acc += &x.data,
+            AccumMode::MaxHold => self.acc.zip_mut_with(&x.data, |a, &b| {
+                if first || b > *a {
+                    *a = b
+                }
+            }),
+            AccumMode::MinHold => self.acc.zip_mut_with(&x.data, |a, &b| {
+                if first || b < *a {
+                    *a = b
+                }
+            }),
+            AccumMode::Median => {
+                if self.n >= self.history.nrows() {
+                    self.history.push_row(x.data.view()).unwrap();
+                } else {
+                    self.history.row_mut(self.n).assign(&x.data);
+                }
+            }
+            AccumMode::Exponential(k) => {
+                if self.primed {
+                    self.acc
+                        .zip_mut_with(&x.data, |a, &b| *a = *a * k + b * (1.0 - k));
+                } else {
+                    self.acc.assign(&x.data);
+                    self.primed = true;
+                }
+            }
+        }
+        self.tainted |= x.tainted;
+        self.nsamples += x.nsamples;
+        self.nifs = x.nifs;
+        self.n += 1;
+    }
+
+    /// Finish the current integration, `None` if nothing was pushed since the last one.
+    pub fn take(&mut self) -> Option<Spectrum> {
+        let mut out = Spectrum::default();
+        self.take_into(&mut out).then_some(out)
+    }
+
+    /// As `take`, reusing the buffer of `out`, whose `flags` are left for the caller.
+    /// `false`, with `out` untouched, if nothing was pushed since the last integration.
+    pub fn take_into(&mut self, out: &mut Spectrum) -> bool {
+        if self.n == 0 {
+            return false;
+        }
+        let n = self.n;
+        let data = &mut out.data;
+        if data.len() != self.acc.len() {
+            *data = Array1::zeros(self.acc.len());
+        }
+        match self.mode {
+            AccumMode::Sum | AccumMode::MaxHold | AccumMode::MinHold => {
+                data.assign(&self.acc);
+                self.acc.fill(0.0);
+            }
+            AccumMode::Mean => {
+                data.zip_mut_with(&self.acc, |y, &a| *y = a / n as Ftype);
+                self.acc.fill(0.0);
+            }
+            AccumMode::Median => {
+                let history = self.history.slice(s![..n, ..]);
+                for (y, col) in data.iter_mut().zip(history.columns()) {
+                    self.column.clear();
+                    self.column.extend(col);
+                    let (_, &mut m, _) = self
+                        .column
+                        .select_nth_unstable_by(n / 2, |a, b| a.total_cmp(b));
+                    *y = m;
+                }
+            }
+            AccumMode::Exponential(_) => data.assign(&self.acc),
+        }
+        out.nifs = self.nifs;
+        out.tainted = self.tainted;
+        out.sample_index = self.sample_index;
+        out.nsamples = self.nsamples;
+        out.time_ns = self.time_ns;
+        out.unit = None;
+        self.n = 0;
+        self.tainted = false;
+        true
+    }
+}
+
+/// Channels whose generalized spectral kurtosis leaves these bounds are flagged.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SkThreshold {
+    /// `1 ± n * 2 / sqrt(M)`, i.e. n times the standard deviation for M spectra
+    Sigma(Ftype),
+    Range(Ftype, Ftype),
+}
+
+impl std::str::FromStr for SkThreshold {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("bad SK threshold '{s}', can be <nsigma> or <low>:<high> around 1");
+        match s.split_once(':') {
+            Some((lo, hi)) => match (lo.parse::<Ftype>(), hi.parse::<Ftype>()) {
+                (Ok(lo), Ok(hi)) if lo < 1.0 && hi > 1.0 => Ok(SkThreshold::Range(lo, hi)),
+                _ => Err(err()),
+            },
+            None => match s.parse::<Ftype>() {
+                Ok(n) if n > 0.0 => Ok(SkThreshold::Sigma(n)),
+                _ => Err(err()),
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for SkThreshold {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SkThreshold::Sigma(n) => write!(fmt, "{n}"),
+            SkThreshold::Range(lo, hi) => write!(fmt, "{lo}:{hi}"),
+        }
+    }
+}
+
+impl SkThreshold {
+    /// lower and upper bound for an estimate from `m` spectra of shape `d`
+    pub fn bounds(&self, m: usize, d: Ftype) -> (Ftype, Ftype) {
+        match *self {
+            SkThreshold::Sigma(n) => {
+                let m = m as Ftype;
+                let md = m * d;
+                let var = 2.0 * d * (d + 1.0) * m * m / ((m - 1.0) * (md + 2.0) * (md + 3.0));
+                let sigma = var.sqrt();
+                (1.0 - n * sigma, 1.0 + n * sigma)
+            }
+            SkThreshold::Range(lo, hi) => (lo, hi),
+        }
+    }
+}
+
+/// What happens to flagged channels of an averaged output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlagAction {
+    Keep,
+    Zero,
+    /// interpolated from the nearest unflagged channels
+    Replace,
+}
+
+impl std::str::FromStr for FlagAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(FlagAction::Keep),
+            "zero" => Ok(FlagAction::Zero),
+            "replace" => Ok(FlagAction::Replace),
+            _ => Err(format!(
+                "unknown flag action '{s}', can be keep, zero or replace"
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for FlagAction {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            FlagAction::Keep => write!(fmt, "keep"),
+            FlagAction::Zero => write!(fmt, "zero"),
+            FlagAction::Replace => write!(fmt, "replace"),
+        }
+    }
+}
+
+impl FlagAction {
+    /// Apply to every IF of `x` according to `x.flags`.
+    pub fn apply(&self, x: &mut Spectrum) {
+        let Some(ref flags) = x.flags else {
+            return;
+        };
+        let nch = flags.len();
+        for mut block in x.data.exact_chunks_mut(nch) {
+            let mut ch = 0;
+            while ch < nch {
+                if !flags[ch] {
+                    ch += 1;
+                    continue;
+                }
+                // a run of flagged channels between the unflagged `a` and `b`
+                let a = ch.checked_sub(1);
+                let b = (ch..nch).find(|&c| !flags[c]);
+                for c in ch..b.unwrap_or(nch) {
+                    block[c] = match self {
+                        FlagAction::Keep => continue,
+                        FlagAction::Zero => 0.0,
+                        FlagAction::Replace => match (a, b) {
+                            (Some(a), Some(b)) => {
+                                let w = (c - a) as Ftype / (b - a) as Ftype;
+                                block[a] * (1.0 - w) + block[b] * w
+                            }
+                            (Some(a), None) => block[a],
+                            (None, Some(b)) => block[b],
+                            (None, None) => continue,
+                        },
+                    };
+                }
+                ch = b.unwrap_or(nch);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SkConfig {
+    pub threshold: SkThreshold,
+    /// the whole integration is flagged if more than this fraction of channels is
+    pub max_fraction: Ftype,
+    pub action: FlagAction,
+}
+
+impl SkConfig {
+    pub fn new(threshold: SkThreshold) -> SkConfig {
+        SkConfig {
+            threshold,
+            max_fraction: 0.5,
+            action: FlagAction::Keep,
+        }
+    }
+}
+
+/// Generalized spectral kurtosis (Nita & Gary 2010, with N = 1) of the first IF
+/// of the spectra pushed during one integration.
+///
+/// `d` is the number of `|x|^2` terms in each value, 2 for Stokes I. Only every
+/// `stride`th spectrum is used, so that an oversampled PFB, whose neighbouring
+/// spectra share input samples, still gives independent estimates.
+pub struct SpectralKurtosis {
+    s1: Array1<Ftype>,
+    s2: Array1<Ftype>,
+    m: usize,
+    d: Ftype,
+    stride: usize,
+    skipped: usize,
+}
+
+impl SpectralKurtosis {
+    pub fn new(nch: usize, d: usize, stride: usize) -> SpectralKurtosis {
+        SpectralKurtosis {
+            s1: Array1::zeros(nch),
+            s2: Array1::zeros(nch),
+            m: 0,
+            d: d as Ftype,
+            stride: stride.max(1),
+            skipped: 0,
+        }
+    }
+
+    pub fn push(&mut self, x: &Spectrum) {
+        if self.skipped > 0 {
+            self.skipped = (self.skipped + 1) % self.stride;
+            return;
+        }
+        self.skipped = 1 % self.stride;
+        let p = x.data.slice(s![..self.s1.len()]);
+        self.s1 += &p;
+        self.s2.zip_mut_with(&p, |a, &b| *a += b * b);
+        self.m += 1;
+    }
+
+    /// SK per channel of the current integration, `None` for fewer than two spectra
+    pub fn take(&mut self) -> Option<Array1<Ftype>> {
+        let m = std::mem::replace(&mut self.m, 0) as Ftype;
+        let nch = self.s1.len();
+        let s1 = std::mem::replace(&mut self.s1, Array1::zeros(nch));
+        let s2 = std::mem::replace(&mut self.s2, Array1::zeros(nch));
+        if m < 2.0 {
+            return None;
+        }
+        Some((s2 * m / (&s1 * &s1) - 1.0) * ((m * self.d + 1.0) / (m - 1.0)))
+    }
+
+    /// Flags of the current integration, every channel if too many are out of bounds.
+    pub fn take_flags(&mut self, config: &SkConfig) -> Option<Array1<bool>> {
+        let mut flags = None;
+        self.take_flags_into(config, &mut flags);
+        flags
+    }
+
+    /// As `take_flags`, reusing the array already in `flags`.
+    pub fn take_flags_into(&mut self, config: &SkConfig, flags: &mut Option<Array1<bool>>) {
+        let m = std::mem::replace(&mut self.m, 0);
+        if m < 2 {
+            *flags = None;
+        } else {
+            let (lo, hi) = config.threshold.bounds(m, self.d);
+            let m = m as Ftype;
+            let scale = (m * self.d + 1.0) / (m - 1.0);
+            let nch = self.s1.len();
+            let f = flags.get_or_insert_with(|| Array1::from_elem(nch, false));
+            for ((f, &s1), &s2) in f.iter_mut().zip(&self.s1).zip(&self.s2) {
+                let sk = (s2 * m / (s1 * s1) - 1.0) * scale;
+                *f = !(lo..=hi).contains(&sk);
+            }
+            let nflagged = f.iter().filter(|&&x| x).count();
+            if nflagged as Ftype > config.max_fraction * nch as Ftype {
+                f.fill(true);
+            }
+        }
+        self.s1.fill(0.0);
+        self.s2.fill(0.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spectrum(data: &[Ftype], sample_index: u64, tainted: bool) -> Spectrum {
+        Spectrum {
+            data: Array1::from(data.to_vec()),
+            nifs: 1,
+            tainted,
+            sample_index,
+            nsamples: 8,
+            time_ns: sample_index as i64 * 100,
+            flags: None,
+            unit: None,
+        }
+    }
+
+    fn integrate(mode: AccumMode, spectra: &[[Ftype; 2]]) -> Spectrum {
+        let mut acc = Accumulator::new(mode, 2, spectra.len());
+        for (i, x) in spectra.iter().enumerate() {
+            acc.push(&spectrum(x, i as u64 * 8, false));
+        }
+        acc.take().unwrap()
+    }
+
+    #[test]
+    fn modes() {
+        let spectra = [[1.0, 6.0], [4.0, 2.0], [2.0, 4.0]];
+        let data = |mode| integrate(mode, &spectra).data.to_vec();
+        assert_eq!(data(AccumMode::Sum), [7.0, 12.0]);
+        assert_eq!(data(AccumMode::Mean), [7.0 / 3.0, 4.0]);
+        assert_eq!(data(AccumMode::MaxHold), [4.0, 6.0]);
+        assert_eq!(data(AccumMode::MinHold), [1.0, 2.0]);
+        assert_eq!(data(AccumMode::Median), [2.0, 4.0]);
+        // primed with the first spectrum, then halfway towards each following one
+        assert_eq!(data(AccumMode::Exponential(0.5)), [2.25, 4.0]);
+    }
+
+    #[test]
+    fn integrations() {
+        let mut acc = Accumulator::new(AccumMode::Sum, 2, 2);
+        assert!(acc.take().is_none());
+        acc.push(&spectrum(&[1.0, 1.0], 16, true));
+        acc.push(&spectrum(&[2.0, 2.0], 24, false));
+        assert_eq!(acc.count(), 2);
+        let x = acc.take().unwrap();
+        assert!(x.tainted);
+        assert_eq!((x.sample_index, x.nsamples, x.time_ns), (16, 16, 1600));
+        assert!(acc.take().is_none());
+        // nothing is carried over into the next integration
+        acc.push(&spectrum(&[3.0, 3.0], 32, false));
+        let mut x = spectrum(&[9.0; 4], 0, true);
+        assert!(acc.take_into(&mut x));
+        assert_eq!(x.data.to_vec(), [3.0, 3.0]);
+        assert!(!x.tainted);
+        assert_eq!((x.sample_index, x.nsamples), (32, 8));
+        assert!(!acc.take_into(&mut x));
+    }
+
+    #[test]
+    fn parse_modes() {
+        for s in ["sum", "mean", "max", "min", "median", "exp:0.9"] {
+            assert_eq!(s.parse::<AccumMode>().unwrap().to_string(), s);
+        }
+        assert!("exp:1".parse::<AccumMode>().is_err());
+        assert!("avg".parse::<AccumMode>().is_err());
+    }
+}
@@ -0,0 +1,97 @@
+use crate::source::RawBuffers;
+
+type Ftype = f32;
+
+/// Levels of one buffer, relative to an ADC full scale of 1.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AdcLevels {
+    /// largest `|I|` or `|Q|`
+    pub peak: Ftype,
+    /// fraction of `I` and `Q` values at or beyond the clip level
+    pub clip_fraction: Ftype,
+}
+
+/// Watches the raw samples for saturation, fed by the PFB thread of `run_daq` once
+/// they are converted to floats.
+#[derive(Debug, Clone)]
+pub struct AdcMonitor {
+    pub clip_level: Ftype,
+    /// clip fraction above which a `DaqEvent::Clipping` is reported
+    pub warn_fraction: Ftype,
+    histogram: Vec<u64>,
+}
+
+impl AdcMonitor {
+    pub fn new(clip_level: Ftype, warn_fraction: Ftype, nbins: usize) -> AdcMonitor {
+        assert!(nbins > 0, "the ADC histogram needs at least one bin");
+        AdcMonitor {
+            clip_level,
+            warn_fraction,
+            histogram: vec![0; nbins],
+        }
+    }
+
+    pub fn update(&mut self, buf: &RawBuffers) -> AdcLevels {
+        let nbins = self.histogram.len();
+        let mut peak: Ftype = 0.0;
+        let mut nclip = 0;
+        let mut n = 0;
+        for x in buf.iter().flatten() {
+            for v in [x.re, x.im] {
+                let a = v.abs();
+                peak = peak.max(a);
+                if a >= self.clip_level {
+                    nclip += 1;
+                }
+                let bin = ((v + 1.0) / 2.0 * nbins as Ftype).clamp(0.0, (nbins - 1) as Ftype);
+                self.histogram[bin as usize] += 1;
+                n += 1;
+            }
+        }
+        AdcLevels {
+            peak,
+            clip_fraction: nclip as Ftype / n.max(1) as Ftype,
+        }
+    }
+
+    /// Histogram of `I` and `Q` over `-1..1` since the last `clear_histogram`.
+    pub fn histogram(&self) -> &[u64] {
+        &self.histogram
+    }
+
+    pub fn clear_histogram(&mut self) {
+        self.histogram.fill(0);
+    }
+}
+
+impl Default for AdcMonitor {
+    fn default() -> Self {
+        AdcMonitor::new(0.99, 1e-3, 64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use num::Complex;
+
+    #[test]
+    fn levels_and_histogram() {
+        let mut adc = AdcMonitor::new(0.99, 1e-3, 4);
+        let buf = vec![
+            vec![Complex::new(0.5, 0.0), Complex::new(-1.0, 0.2)],
+            vec![Complex::new(0.99, -0.99), Complex::new(0.0, 0.0)],
+        ];
+        let levels = adc.update(&buf);
+        assert_eq!(levels.peak, 1.0);
+        // -1, 0.99 and -0.99 clip
+        assert_eq!(levels.clip_fraction, 3.0 / 8.0);
+        assert_eq!(adc.histogram(), [2, 0, 4, 2]);
+        adc.clear_histogram();
+        assert_eq!(adc.histogram(), [0; 4]);
+
+        let levels = adc.update(&vec![vec![Complex::new(0.1, -0.2); 10]]);
+        assert_eq!((levels.peak, levels.clip_fraction), (0.2, 0.0));
+        assert_eq!(adc.histogram(), [0, 10, 10, 0]);
+    }
+}
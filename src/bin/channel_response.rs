@@ -0,0 +1,99 @@
+use clap::Parser;
+use ndarray::Axis;
+use num::Complex;
+
+use soapy_spec_acc::channelizer::{Backend, Channelizer};
+
+/// Feeds tones stepped across one channel through each backend and prints the response
+/// of that channel (scalloping) and the strongest response two or more channels away (leakage).
+#[derive(Debug, Parser)]
+#[clap(author, about, version)]
+struct Args {
+    #[clap(
+        short('n'),
+        long("nch"),
+        value_name("num of channels"),
+        default_value("512")
+    )]
+    nch: usize,
+
+    #[clap(
+        short('t'),
+        long("tap"),
+        value_name("pfb tap per ch"),
+        default_value("4")
+    )]
+    ntap: usize,
+
+    #[clap(
+        long("steps"),
+        value_name("tone offsets per channel width"),
+        default_value("8")
+    )]
+    nsteps: usize,
+
+    #[clap(
+        value_name("backends"),
+        default_values(["pfb", "welch:hann", "welch:blackman-harris", "welch:flattop"])
+    )]
+    backends: Vec<Backend>,
+}
+
+fn db(x: f64) -> f64 {
+    x.log10() * 10.0
+}
+
+fn main() {
+    let args = Args::parse();
+    let nch = args.nch;
+    // a channel well away from DC and the band edges, in FFT order
+    let ch0 = nch / 4;
+    let nsamples = nch * (args.ntap + 64);
+
+    for backend in &args.backends {
+        println!("{backend}");
+        println!("{:>8} {:>10} {:>10}", "offset", "gain/dB", "leak/dB");
+        let response = (0..=args.nsteps)
+            .map(|step| {
+                let offset = step as f64 / args.nsteps as f64 - 0.5;
+                let freq = (ch0 as f64 + offset) / nch as f64;
+                let x = (0..nsamples)
+                    .map(|i| {
+                        let phase = 2.0 * std::f64::consts::PI * freq * i as f64;
+                        Complex::new(phase.cos() as f32, phase.sin() as f32)
+                    })
+                    .collect::<Vec<_>>();
+                let mut channelizer = Channelizer::new(*backend, nch, args.ntap);
+                let y = channelizer.analyze(&x);
+                // skip the rows still filling the filter history
+                let skip = channelizer.memory().div_ceil(channelizer.hop());
+                let power = y
+                    .slice_axis(Axis(0), (skip..).into())
+                    .map(|x| x.norm_sqr() as f64)
+                    .mean_axis(Axis(0))
+                    .unwrap();
+                let leak = (0..nch)
+                    .filter(|&ch| {
+                        let d = (ch as isize - ch0 as isize).rem_euclid(nch as isize) as usize;
+                        d.min(nch - d) >= 2
+                    })
+                    .map(|ch| power[ch])
+                    .fold(0.0, f64::max);
+                (offset, power[ch0], leak)
+            })
+            .collect::<Vec<_>>();
+        // relative to a tone at the channel centre
+        let reference = response
+            .iter()
+            .min_by(|a, b| a.0.abs().total_cmp(&b.0.abs()))
+            .unwrap()
+            .1;
+        for (offset, gain, leak) in response {
+            println!(
+                "{offset:>8.3} {:>10.2} {:>10.2}",
+                db(gain / reference),
+                db(leak / reference)
+            );
+        }
+    }
+}
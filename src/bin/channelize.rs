@@ -1,3 +1,4 @@
+use chrono::Utc;
 use clap::Parser;
 
 use egui::ViewportBuilder;
@@ -6,12 +7,34 @@ use ndarray::{Array1, Array2, s};
 
 //use rayon::prelude::*;
 
-use num::complex::Complex;
-use soapy_spec_acc::{daq::run_daq, utils::write_data};
+use soapy_spec_acc::{
+    accumulate::{AccumMode, FlagAction, SkConfig, SkThreshold},
+    adc::AdcMonitor,
+    calibration::{CalMethod, CalSetup, CalSolution, Calibrator},
+    channelizer::Backend,
+    correlator::Products,
+    daq::{DaqConfig, run_daq},
+    dedisperse::{CandidateWriter, SearchConfig, SearchSetup},
+    iq_correct::{CorrectionSwitch, IqCorrection},
+    iq_file::{IqFileSource, SampleFormat},
+    meta::SpectrumMeta,
+    queue::{Policy, QueueSetting, Queues},
+    radiometer::{ChannelRange, PowerSample, PowerWriter, RadiometerConfig},
+    recorder::RawRecorder,
+    source::{SampleSource, SoapySource},
+    stats::DaqStats,
+    sweep::SweepPlan,
+    synth::{Signal, SynthSource},
+    utils::write_data,
+    velocity::{Convention, Frame, GridAverage, Pointing, Site, VelocityFrame, VelocityGrid},
+    zoom::{ZoomConfig, ZoomSpectrum},
+};
 use soapysdr::{Device, Direction};
 use std::{
+    collections::VecDeque,
     fs::OpenOptions,
     sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
 use eframe::{
@@ -23,7 +46,12 @@ use plotters::prelude::*;
 
 use crossbeam::channel::bounded;
 
-type Ftype = f32;
+/// radiometer samples plotted
+const POWER_HISTORY: usize = 1000;
+/// latest radiometer samples, filled by the thread receiving them
+type PowerHistory = Arc<Mutex<VecDeque<PowerSample>>>;
+/// first IF of an average, or a panorama, its RFI flags and its unit if calibrated
+type Displayed = (Array1<f32>, Option<Array1<bool>>, Option<&'static str>);
 
 #[derive(Debug, Parser)]
 #[clap(author, about, version)]
@@ -47,6 +75,13 @@ struct Args {
     )]
     ntap: usize,
 
+    #[clap(
+        long("backend"),
+        value_name("pfb[:<bandwidth>[:<window>]] or welch:<window>[:<overlap>]"),
+        default_value("pfb")
+    )]
+    backend: Backend,
+
     #[clap(
         short('y'),
         value_name("num of time points displayed"),
@@ -54,9 +89,6 @@ struct Args {
     )]
     ntime: usize,
 
-    #[clap(short('k'), value_name("filter param k"), default_value("0.9"))]
-    k: f32,
-
     #[clap(
         short('a'),
         value_name("number of time points to calculate mean"),
@@ -64,6 +96,235 @@ struct Args {
     )]
     n_average: usize,
 
+    #[clap(
+        long("accum"),
+        value_name("displayed product: sum, mean, max, min, median or exp:<k>"),
+        default_value("mean")
+    )]
+    accum: AccumMode,
+
+    #[clap(
+        long("out-accum"),
+        value_name("product written to the out file: sum, mean, max, min, median or exp:<k>"),
+        default_value("mean")
+    )]
+    out_accum: AccumMode,
+
+    #[clap(
+        long("sk"),
+        value_name("flag RFI by spectral kurtosis outside <nsigma> or <low>:<high>")
+    )]
+    sk: Option<SkThreshold>,
+
+    #[clap(
+        long("sk-max-fraction"),
+        value_name("flag the whole average above this fraction of flagged channels"),
+        default_value("0.5")
+    )]
+    sk_max_fraction: f32,
+
+    #[clap(
+        long("flag-action"),
+        value_name("what to do with flagged channels: keep, zero or replace"),
+        default_value("keep")
+    )]
+    flag_action: FlagAction,
+
+    #[clap(
+        long("products"),
+        value_name("total, corr or stokes; corr and stokes use two input channels"),
+        default_value("total")
+    )]
+    products: Products,
+
+    #[clap(
+        long("clip-warn"),
+        value_name("warn when this fraction of samples is at ADC full scale"),
+        default_value("1e-3")
+    )]
+    clip_warn: f32,
+
+    #[clap(
+        long("queue"),
+        value_name("<stage>=<policy>[:<capacity>]"),
+        help(
+            "queue between pipeline stages, repeatable: stage raw, record, spectrum, averaged, power, zoom, search or candidates, policy drop-newest, drop-oldest or block; averaged blocks by default with -o"
+        )
+    )]
+    queue: Vec<QueueSetting>,
+
+    #[clap(
+        long("dc"),
+        help("remove the DC offset before channelizing, toggled by the DC box")
+    )]
+    dc: bool,
+
+    #[clap(
+        long("iq-balance"),
+        help("correct the IQ gain and phase imbalance, toggled by the IQ box")
+    )]
+    iq_balance: bool,
+
+    #[clap(
+        long("iq-time"),
+        value_name("time constant of the DC and IQ estimates in s"),
+        default_value("1")
+    )]
+    iq_time: f64,
+
+    #[clap(
+        long("power"),
+        value_name("<first>:<last>"),
+        help(
+            "channel range of the total-power radiometer, repeatable; the whole band with only --power-out"
+        )
+    )]
+    power: Vec<ChannelRange>,
+
+    #[clap(
+        long("power-interval"),
+        value_name("time per radiometer sample in s"),
+        default_value("0.01")
+    )]
+    power_interval: f64,
+
+    #[clap(long("power-out"), value_name("radiometer time series file"))]
+    power_out: Option<String>,
+
+    #[clap(
+        long("zoom"),
+        value_name("<first>:<last>"),
+        help("channelize these channels further from the start, or drag over the spectrum")
+    )]
+    zoom: Option<ChannelRange>,
+
+    #[clap(
+        long("zoom-nch"),
+        value_name("fine channels per zoomed channel, half of them kept"),
+        default_value("1024")
+    )]
+    zoom_nch: usize,
+
+    #[clap(
+        long("zoom-avg"),
+        value_name("fine spectra per zoomed average"),
+        default_value("16")
+    )]
+    zoom_avg: usize,
+
+    #[clap(
+        long("search"),
+        help("dedisperse the first IF and search it for single pulses")
+    )]
+    search: bool,
+
+    #[clap(long("dm-min"), value_name("lowest trial DM"), default_value("0"))]
+    dm_min: f64,
+
+    #[clap(
+        long("dm-max"),
+        value_name("highest trial DM, derived from the band and --max-width if not given")
+    )]
+    dm_max: Option<f64>,
+
+    #[clap(
+        long("search-downsample"),
+        value_name("channelizer outputs per search sample"),
+        default_value("16")
+    )]
+    search_downsample: usize,
+
+    #[clap(
+        long("max-width"),
+        value_name("widest boxcar in search samples"),
+        default_value("64")
+    )]
+    max_width: usize,
+
+    #[clap(long("snr"), value_name("candidate S/N threshold"), default_value("7"))]
+    snr: f32,
+
+    #[clap(
+        long("cand-out"),
+        value_name("candidate file, snapshots written next to it"),
+        default_value("candidates.txt")
+    )]
+    cand_out: String,
+
+    #[clap(
+        long("velocity"),
+        help("label the frequency axes with velocities from the start, toggled by the km/s box")
+    )]
+    velocity: bool,
+
+    #[clap(
+        long("rest-freq"),
+        value_name("rest frequency of the line in Hz"),
+        default_value("1420405751.768")
+    )]
+    rest_freq: f64,
+
+    #[clap(
+        long("convention"),
+        value_name("velocity convention, radio or optical"),
+        default_value("radio")
+    )]
+    convention: Convention,
+
+    #[clap(
+        long("frame"),
+        value_name("velocity frame, topo, bary or lsr"),
+        default_value("topo")
+    )]
+    frame: Frame,
+
+    #[clap(long("site"), value_name("<lat>:<lon>[:<height>] in deg and m"))]
+    site: Option<Site>,
+
+    #[clap(
+        long("pointing"),
+        value_name("radec:<ra>:<dec> in J2000 deg or azel:<az>:<el> in deg")
+    )]
+    pointing: Option<Pointing>,
+
+    #[clap(
+        long("vgrid"),
+        value_name("<start>:<stop>:<step>"),
+        help("average the spectra onto this velocity grid in km/s")
+    )]
+    vgrid: Option<VelocityGrid>,
+
+    #[clap(
+        long("vgrid-out"),
+        value_name("velocity grid average file, added to if it exists"),
+        default_value("vgrid.txt")
+    )]
+    vgrid_out: String,
+
+    #[clap(long("cal"), value_name("calibration file to apply from the start"))]
+    cal: Option<String>,
+
+    #[clap(
+        long("cal-method"),
+        value_name("hotcold:<t_hot>:<t_cold>, diode:<t_cal> or bandpass, recorded from the GUI"),
+        default_value("hotcold:290:10")
+    )]
+    cal_method: CalMethod,
+
+    #[clap(
+        long("cal-avg"),
+        value_name("averages recorded per calibration reference"),
+        default_value("16")
+    )]
+    cal_avg: usize,
+
+    #[clap(
+        long("cal-out"),
+        value_name("file the recorded calibration is saved to"),
+        default_value("cal.txt")
+    )]
+    cal_out: String,
+
     #[clap(long("lna"), value_name("lna gain"), default_value("5"))]
     lna: f64,
 
@@ -74,7 +335,7 @@ struct Args {
     vga: f64,
 
     #[clap(short('s'), value_name("sampling rate in MHz"), default_value("6"))]
-    sampling_rate: u32,
+    sampling_rate: f64,
 
     #[clap(
         short('o'),
@@ -91,6 +352,86 @@ struct Args {
         default_value("glow")
     )]
     renderer: String,
+
+    #[clap(
+        long("synth"),
+        value_name("synthetic signal"),
+        help(
+            "use a synthetic source instead of the SDR, repeatable: noise:<sigma>, tone:<freq>:<ampl>, chirp:<f_start>:<f_stop>:<period>:<ampl>, pulse:<period>:<width>:<dm>:<ampl>, rfi:<rate>:<duration>:<ampl>"
+        )
+    )]
+    synth: Vec<Signal>,
+
+    #[clap(long("replay"), value_name("IQ file to replay instead of the SDR"))]
+    replay: Option<String>,
+
+    #[clap(
+        long("replay-format"),
+        value_name("cf32, cs16, cs8 or cu8"),
+        default_value("cs16")
+    )]
+    replay_format: SampleFormat,
+
+    #[clap(
+        long("replay-start"),
+        value_name("offset into the replay file in s"),
+        default_value("0")
+    )]
+    replay_start: f64,
+
+    #[clap(long("replay-duration"), value_name("replayed duration in s"))]
+    replay_duration: Option<f64>,
+
+    #[clap(long("loop"), help("restart the replayed segment when it ends"))]
+    replay_loop: bool,
+
+    #[clap(
+        long("sweep-to"),
+        value_name("sweep the LO from freq up to this freq in Hz and show the panorama")
+    )]
+    sweep_to: Option<f64>,
+
+    #[clap(
+        long("dwell"),
+        value_name("integration per sweep step in s"),
+        default_value("0.5")
+    )]
+    dwell: f64,
+
+    #[clap(
+        long("settle"),
+        value_name("time discarded after retuning in s"),
+        default_value("0.05")
+    )]
+    settle: f64,
+
+    #[clap(
+        long("stream-format"),
+        value_name("cf32, cs16 or cs8 samples from the SDR, default its native format")
+    )]
+    stream_format: Option<SampleFormat>,
+
+    #[clap(long("record"), value_name("prefix of raw IQ record files"))]
+    record: Option<String>,
+
+    #[clap(
+        long("record-format"),
+        value_name("cf32, cs16, cs8 or cu8"),
+        default_value("cs16")
+    )]
+    record_format: SampleFormat,
+
+    #[clap(
+        long("record-max-mb"),
+        value_name("start a new record file after this many MB")
+    )]
+    record_max_mb: Option<u64>,
+
+    #[clap(
+        long("record-max-secs"),
+        value_name("start a new record file after this many s")
+    )]
+    record_max_secs: Option<f64>,
 }
 
 #[derive(Clone)]
@@ -103,8 +444,30 @@ struct State {
     yscale_max: f64,
     ntime: usize,
     nch: usize,
-    device: Device,
+    device: Option<Device>,
+    channels: Vec<usize>,
     floor: Option<Array1<f32>>,
+    stats: Arc<DaqStats>,
+    clip_warn: f64,
+    correction: Arc<CorrectionSwitch>,
+    calibration: Arc<Mutex<Option<CalSolution>>>,
+    /// records new calibrations and drops the current one, not while sweeping, writing
+    /// an out file or for multi-IF products
+    calibrator: Option<Arc<Mutex<Calibrator>>>,
+    /// latest radiometer samples and the channel ranges they are of
+    power: Option<(PowerHistory, Vec<ChannelRange>)>,
+    /// coarse channels zoomed into, not while sweeping
+    zoom: Option<Arc<Mutex<Option<ZoomConfig>>>>,
+    /// all but the range of a zoom selected with the mouse
+    zoom_template: ZoomConfig,
+    zoom_buf: Arc<Mutex<Option<ZoomSpectrum>>>,
+    /// channel a mouse selection started at
+    drag_from: Option<usize>,
+    velocity: VelocityFrame,
+    /// label the frequency axes with velocities
+    show_velocity: bool,
+    /// velocity grid average, stopped when retuning, and the file it is saved to
+    vgrid: Option<(Arc<Mutex<Option<GridAverage>>>, String)>,
     //outname: Option<String>,
 }
 
@@ -116,53 +479,304 @@ fn db(x: f64) -> f64 {
 fn main() {
     let args = Args::parse();
 
-    if args.sampling_rate != 3 && args.sampling_rate != 6 {
+    let use_sdr = args.synth.is_empty() && args.replay.is_none();
+    if use_sdr && args.sampling_rate != 3.0 && args.sampling_rate != 6.0 {
         eprintln!("Sampling rate can only be either 3 or 6 MSps");
         return;
     }
 
-    let sampling_rate = args.sampling_rate as f64 * 1e6;
+    let sampling_rate = args.sampling_rate * 1e6;
     assert_eq!(args.nch & (args.nch - 1), 0);
 
-    let device = Device::new("driver=airspy").unwrap();
+    let channels = (0..args.products.ninputs()).collect::<Vec<_>>();
+    let (device, source): (_, Box<dyn SampleSource>) = if use_sdr {
+        let device = Device::new("driver=airspy").unwrap();
 
-    for g in device.list_gains(Direction::Rx, 0).unwrap() {
-        println!("{g}");
-    }
+        for g in device.list_gains(Direction::Rx, 0).unwrap() {
+            println!("{g}");
+        }
 
-    device.set_antenna(Direction::Rx, 0, "RX").unwrap();
-    device
-        .set_sample_rate(Direction::Rx, 0, sampling_rate)
-        .unwrap();
-    device
-        .set_gain_element(Direction::Rx, 0, "LNA", args.lna)
-        .unwrap();
-    device
-        .set_gain_element(Direction::Rx, 0, "MIX", args.mix)
-        .unwrap();
-    device
-        .set_gain_element(Direction::Rx, 0, "VGA", args.vga)
+        for &ch in &channels {
+            device.set_antenna(Direction::Rx, ch, "RX").unwrap();
+            device
+                .set_sample_rate(Direction::Rx, ch, sampling_rate)
+                .unwrap();
+            device
+                .set_gain_element(Direction::Rx, ch, "LNA", args.lna)
+                .unwrap();
+            device
+                .set_gain_element(Direction::Rx, ch, "MIX", args.mix)
+                .unwrap();
+            device
+                .set_gain_element(Direction::Rx, ch, "VGA", args.vga)
+                .unwrap();
+
+            device
+                .set_frequency(Direction::Rx, ch, args.f0, ())
+                .unwrap();
+        }
+        let source = match args.stream_format {
+            Some(format) => SoapySource::open_as(&device, &channels, sampling_rate, format),
+            None => SoapySource::open(&device, &channels, sampling_rate),
+        }
         .unwrap();
+        println!(
+            "streaming {} samples, full scale {}",
+            source.format(),
+            source.full_scale()
+        );
+        (Some(device), Box::new(source))
+    } else if let Some(ref replay) = args.replay {
+        assert_eq!(
+            channels.len(),
+            1,
+            "replayed files only have a single channel"
+        );
+        let source = IqFileSource::open(replay, args.replay_format, sampling_rate)
+            .unwrap()
+            .with_segment(args.replay_start, args.replay_duration)
+            .unwrap()
+            .looping(args.replay_loop)
+            .throttled(true);
+        (None, Box::new(source))
+    } else {
+        let source = SynthSource::new(args.synth.clone(), sampling_rate, args.f0)
+            .with_channels(channels.len())
+            .throttled(true);
+        (None, Box::new(source))
+    };
 
-    device.set_frequency(Direction::Rx, 0, args.f0, ()).unwrap();
-    let sdr_stream = device.rx_stream::<Complex<Ftype>>(&[0]).unwrap();
+    let setup = CalSetup {
+        freq: args.f0,
+        sample_rate: sampling_rate,
+        nch: args.nch,
+        gains: format!("lna={} mix={} vga={}", args.lna, args.mix, args.vga),
+    };
+    let calibration = args.cal.as_ref().map(|path| {
+        let c = CalSolution::load(path).unwrap();
+        c.setup.check(&setup).unwrap();
+        if let Some(t) = c.median_t_rx() {
+            println!("{path}: median T_rx {t:.1} K");
+        }
+        c
+    });
+    assert!(
+        args.sweep_to.is_none() || calibration.is_none(),
+        "a calibration is only valid at a single frequency"
+    );
+    assert!(
+        args.products.nifs() == 1 || calibration.is_none(),
+        "only single-IF products can be calibrated"
+    );
+    let zoom_template = ZoomConfig {
+        backend: args.backend,
+        tap_per_ch: args.ntap,
+        ..ZoomConfig::new(
+            ChannelRange { first: 0, last: 0 },
+            args.zoom_nch,
+            args.zoom_avg,
+        )
+    };
+    assert!(
+        args.sweep_to.is_none() || args.zoom.is_none(),
+        "cannot zoom while sweeping"
+    );
+
+    let search = args.search.then(|| SearchConfig {
+        downsample: args.search_downsample,
+        dm_min: args.dm_min,
+        dm_max: args.dm_max,
+        max_width: args.max_width,
+        threshold: args.snr,
+        ..SearchConfig::new(args.f0)
+    });
+    assert!(
+        args.sweep_to.is_none() || search.is_none(),
+        "cannot search while sweeping"
+    );
+
+    let velocity = VelocityFrame {
+        convention: args.convention,
+        frame: args.frame,
+        site: args.site,
+        pointing: args.pointing,
+        ..VelocityFrame::new(args.rest_freq)
+    };
+    velocity.check().unwrap();
+    assert!(
+        args.sweep_to.is_none() || args.vgrid.is_none(),
+        "cannot average onto a velocity grid while sweeping"
+    );
+
+    let sweep = args.sweep_to.map(|f_stop| SweepPlan {
+        settle: args.settle,
+        dwell: args.dwell,
+        ..SweepPlan::new(args.f0, f_stop, sampling_rate)
+    });
+    if let Some(Err(e)) = sweep.as_ref().map(|plan| plan.check()) {
+        eprintln!("{e}");
+        return;
+    }
+    if let Some(Err(e)) = search.as_ref().map(|s| s.check(sampling_rate)) {
+        eprintln!("{e}");
+        return;
+    }
+    // displayed bins and band, the whole panorama when sweeping
+    let (ndisp, disp_freq, disp_rate) = match sweep {
+        Some(ref plan) => {
+            let nbins = plan.nbins(args.nch);
+            let df = sampling_rate / args.nch as f64;
+            (nbins, args.f0 + nbins as f64 * df / 2.0, nbins as f64 * df)
+        }
+        None => (args.nch, args.f0, sampling_rate),
+    };
 
     let ctx = Arc::new(Mutex::new(Option::<Context>::default()));
     let ctx1 = Arc::clone(&ctx);
 
     //let waterfall_img_buf = Arc::new(Mutex::new(vec![0_u8; (args.ntime * args.nch * 3)]));
-    let waterfall_img_buf = Arc::new(Mutex::new(Array2::<f32>::zeros((args.ntime, args.nch))));
-    let spectrum_buf = Arc::new(Mutex::new(Array1::<f32>::zeros(args.nch)));
+    let waterfall_img_buf = Arc::new(Mutex::new(Array2::<f32>::zeros((args.ntime, ndisp))));
+    let spectrum_buf = Arc::new(Mutex::new(Array1::<f32>::zeros(ndisp)));
+    let mask_buf = Arc::new(Mutex::new(Array1::<bool>::from_elem(ndisp, false)));
 
     let wimg = waterfall_img_buf.clone();
     let sbuf = spectrum_buf.clone();
+    let mbuf = mask_buf.clone();
 
     let (tx_repaint, rx_repaint) = bounded(1);
 
-    let rx_averaged = run_daq(sdr_stream, args.nch, args.ntap, args.n_average);
-    device.set_frequency(Direction::Rx, 0, args.f0, ()).unwrap();
+    let recorder = args.record.as_ref().map(|prefix| RawRecorder {
+        max_bytes: args.record_max_mb.map(|x| x * 1_000_000),
+        max_secs: args.record_max_secs,
+        ..RawRecorder::new(prefix, args.record_format)
+    });
+    if let Some(ref r) = recorder {
+        println!("recording to {}_<UTC time>_<seq>.{}", r.prefix, r.format);
+    }
+    let mut queues = Queues::new(args.n_average);
+    if args.outname.is_some() {
+        // nothing written to the out file may be lost
+        queues.averaged.policy = Policy::Block;
+    }
+    args.queue.iter().for_each(|q| queues.apply(q));
+    let daq = run_daq(
+        source,
+        DaqConfig {
+            outputs: if args.outname.is_some() {
+                vec![args.accum, args.out_accum]
+            } else {
+                vec![args.accum]
+            },
+            backend: args.backend,
+            products: args.products,
+            recorder,
+            sk: args.sk.map(|threshold| SkConfig {
+                max_fraction: args.sk_max_fraction,
+                action: args.flag_action,
+                ..SkConfig::new(threshold)
+            }),
+            adc: AdcMonitor::new(0.99, args.clip_warn, 64),
+            correction: IqCorrection {
+                dc: args.dc,
+                imbalance: args.iq_balance,
+                time_constant: args.iq_time,
+            },
+            queues,
+            calibration,
+            radiometer: (!args.power.is_empty() || args.power_out.is_some())
+                .then(|| RadiometerConfig::new(args.power.clone(), args.power_interval)),
+            zoom: args.zoom.map(|range| ZoomConfig {
+                range,
+                ..zoom_template.clone()
+            }),
+            search: search.clone(),
+            ..DaqConfig::new(args.nch, args.ntap, args.n_average)
+        },
+    )
+    .expect("failed to activate the source");
+    let rx_events = daq.rx_events.clone();
+    std::thread::spawn(move || {
+        while let Ok(e) = rx_events.recv() {
+            eprintln!("WARNING: {e}");
+        }
+    });
+    let rx_stats = daq.stats.subscribe();
+    std::thread::spawn(move || {
+        while let Ok(s) = rx_stats.recv() {
+            println!("{s}");
+        }
+    });
+    if let Some(ref device) = device {
+        for &ch in &channels {
+            device
+                .set_frequency(Direction::Rx, ch, args.f0, ())
+                .unwrap();
+        }
+    }
 
     let running = Arc::new(Mutex::new(true));
+    let power = daq.rx_power.clone().map(|rx_power| {
+        let ranges =
+            RadiometerConfig::new(args.power.clone(), args.power_interval).ranges_for(args.nch);
+        let mut writer = args
+            .power_out
+            .as_ref()
+            .map(|path| PowerWriter::new(std::fs::File::create(path).unwrap(), &ranges).unwrap());
+        let buf = Arc::new(Mutex::new(VecDeque::<PowerSample>::new()));
+        let buf1 = buf.clone();
+        std::thread::spawn(move || {
+            while let Ok(p) = rx_power.recv() {
+                if let Some(ref mut w) = writer {
+                    w.write(&p).unwrap();
+                }
+                let mut buf = buf1.lock().unwrap();
+                if buf.len() == POWER_HISTORY {
+                    buf.pop_front();
+                }
+                buf.push_back(p);
+            }
+        });
+        (buf, ranges)
+    });
+
+    // finishes once the pipeline is joined, after writing every candidate
+    let th_candidates =
+        if let (Some(config), Some(rx_candidates)) = (search, daq.rx_candidates.clone()) {
+            let setup = SearchSetup::new(&config, &daq.meta);
+            let dms = setup.dms(&config);
+            println!(
+                "searching {} DMs from {:.2} to {:.2}, {:.3} ms per sample",
+                dms.len(),
+                dms[0],
+                dms[dms.len() - 1],
+                setup.tsamp * 1e3
+            );
+            let mut writer = CandidateWriter::new(&args.cand_out, setup).unwrap();
+            Some(std::thread::spawn(move || {
+                while let Ok(c) = rx_candidates.recv() {
+                    println!(
+                        "candidate at MJD {:.9}: DM {:.2} width {} S/N {:.1}",
+                        c.mjd(),
+                        c.dm,
+                        c.width,
+                        c.snr
+                    );
+                    writer.write(&c).unwrap();
+                }
+            }))
+        } else {
+            None
+        };
+
+    let zoom_buf = Arc::new(Mutex::new(None));
+    let zoom_buf1 = zoom_buf.clone();
+    let rx_zoom = daq.rx_zoom.clone();
+    std::thread::spawn(move || {
+        while let Ok(z) = rx_zoom.recv() {
+            *zoom_buf1.lock().unwrap() = Some(z);
+        }
+    });
+
     let running1 = running.clone();
     ctrlc::set_handler(move || {
         println!("bye!");
@@ -170,38 +784,173 @@ fn main() {
     })
     .unwrap();
 
+    // finishes once the pipeline is joined, after writing everything it was sent
+    let th_out = args.outname.clone().map(|outname| {
+        let rx_out = daq.rx_averaged[1].clone();
+        let pool = daq.averaged_pool.clone();
+        let mut meta = daq.meta.clone();
+        meta.velocity = (args.velocity || args.vgrid.is_some()).then(|| velocity.clone());
+        std::thread::spawn(move || {
+            let mut outfile = None;
+            while let Ok(averaged) = rx_out.recv() {
+                let outfile = outfile.get_or_insert_with(|| {
+                    println!("{outname} starts at MJD {:.9}", averaged.mjd());
+                    meta.tstart = Some(averaged.mjd());
+                    meta.unit = averaged.unit.map(String::from);
+                    meta.save(&SpectrumMeta::path_for(&outname)).unwrap();
+                    OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(&outname)
+                        .unwrap()
+                });
+                write_data(outfile, averaged.data.as_slice().unwrap());
+                pool.put(averaged);
+            }
+        })
+    });
+
+    // the calibration gain only holds for the first IF, and the unit of the out file is
+    // that of its first average
+    let calibrator =
+        (sweep.is_none() && args.products.nifs() == 1 && args.outname.is_none()).then(|| {
+            Arc::new(Mutex::new(Calibrator::new(
+                args.cal_method,
+                setup,
+                args.cal_avg,
+            )))
+        });
+    let calibrator1 = calibrator.clone();
+    let calibration1 = daq.calibration.clone();
+    let cal_out = args.cal_out.clone();
+
+    let vgrid = args.vgrid.map(|grid| {
+        let mut avg = GridAverage::new(velocity.clone(), grid);
+        if std::path::Path::new(&args.vgrid_out).exists() {
+            avg.resume(&args.vgrid_out).unwrap();
+            println!("{}: carrying on from {:.1} s", args.vgrid_out, avg.weight());
+        }
+        Arc::new(Mutex::new(Some(avg)))
+    });
+    let vgrid1 = vgrid.clone();
+    let vgrid_out = args.vgrid_out.clone();
+    let tsamp = daq.meta.tsamp();
+
     let running1 = running.clone();
+    let rx_averaged = daq.rx_averaged[0].clone();
+    let pool = daq.averaged_pool.clone();
+    let mut next_spectrum: Box<dyn FnMut() -> Option<Displayed> + Send> = match sweep {
+        Some(plan) => {
+            let device = device.clone();
+            let channels = channels.clone();
+            let stats = daq.stats.clone();
+            Box::new(move || {
+                plan.run(&rx_averaged, &stats, &pool, |f| {
+                    if let Some(ref device) = device {
+                        for &ch in &channels {
+                            device.set_frequency(Direction::Rx, ch, f, ()).unwrap();
+                        }
+                    }
+                })
+                .map(|p| (p.spectrum(), None, None))
+            })
+        }
+        // only the first IF, i.e. XX or I, is displayed
+        None => {
+            let mut saved = Instant::now();
+            Box::new(move || {
+                let x = rx_averaged.recv().ok()?;
+                if let Some(ref vgrid) = vgrid1 {
+                    let mut vgrid = vgrid.lock().unwrap();
+                    if let Some(ref mut avg) = *vgrid {
+                        match avg.add(&x, args.f0, sampling_rate, tsamp) {
+                            Ok(()) if saved.elapsed() >= Duration::from_secs(1) => {
+                                avg.save(&vgrid_out).unwrap();
+                                saved = Instant::now();
+                            }
+                            Ok(()) => {}
+                            Err(e) => {
+                                println!("{e}, no longer averaging onto the velocity grid");
+                                avg.save(&vgrid_out).unwrap();
+                                *vgrid = None;
+                            }
+                        }
+                    }
+                }
+                let displayed = (
+                    x.data.slice(s![..args.nch]).to_owned(),
+                    x.flags.clone(),
+                    x.unit,
+                );
+                pool.put(x);
+                Some(displayed)
+            })
+        }
+    };
     let th_display = std::thread::spawn(move || {
         let spectrum_buf = sbuf;
 
-        let mut waterfall_buf = Array2::<f32>::ones((args.ntime, args.nch));
-        let mut waterfall_buf_tmp = Array2::<f32>::ones((args.ntime, args.nch));
+        let mut waterfall_buf = Array2::<f32>::ones((args.ntime, ndisp));
+        let mut waterfall_buf_tmp = Array2::<f32>::ones((args.ntime, ndisp));
         //let averaged = rx_averaged.recv().unwrap();
         //let mut filtered_result = averaged.clone();
-        let mut filtered_result = Array1::<f32>::zeros(args.nch);
-        loop {
-            let averaged = rx_averaged.recv().unwrap();
+        let mut filtered_result = Array1::<f32>::zeros(ndisp);
+        let mut last_unit = None;
+        while let Some((averaged, flags, unit)) = next_spectrum() {
             if !*running1.lock().unwrap() {
                 return;
             }
-            if let Some(ref outname) = args.outname {
-                //let mut outfile = File::create(outname).unwrap();
-                let mut outfile = OpenOptions::new()
-                    .create(true)
-                    .append(true)
-                    .open(outname)
-                    .unwrap();
-                write_data(&mut outfile, averaged.as_slice().unwrap());
+            if unit != last_unit {
+                // don't smooth across calibrating and not
+                filtered_result.fill(0.0);
+                last_unit = unit;
             }
+            if unit.is_none()
+                && let Some(ref c) = calibrator1
+                && let Some(result) = c.lock().unwrap().push(&averaged)
+            {
+                match result {
+                    Ok(solution) => {
+                        match solution.save(&cal_out) {
+                            Ok(()) => println!("calibration saved to {cal_out}"),
+                            Err(e) => eprintln!("failed to save {cal_out}: {e}"),
+                        }
+                        if let Some(t) = solution.median_t_rx() {
+                            println!("median T_rx {t:.1} K");
+                        }
+                        *calibration1.lock().unwrap() = Some(solution);
+                    }
+                    Err(e) => eprintln!("calibration failed: {e}"),
+                }
+            }
+            let flags = flags.unwrap_or_else(|| Array1::from_elem(ndisp, false));
+            // smoothing is up to `--accum exp:<k>`, flagged channels keep their last value
+            filtered_result
+                .iter_mut()
+                .zip(&averaged)
+                .zip(&flags)
+                .for_each(|((f, &a), &flagged)| {
+                    if !flagged || *f == 0.0 {
+                        *f = a;
+                    }
+                });
 
-            filtered_result = filtered_result * args.k + &averaged * (1 as Ftype - args.k);
-
-            assert!(filtered_result.iter().all(|&x| { x > 0.0 }));
+            assert!(
+                filtered_result
+                    .iter()
+                    .zip(&flags)
+                    .all(|(&x, &flagged)| { x > 0.0 || flagged || x.is_nan() })
+            );
 
             waterfall_buf_tmp
                 .slice_mut(s![..-1, ..])
                 .assign(&waterfall_buf.slice(s![1.., ..]));
-            waterfall_buf_tmp.slice_mut(s![-1, ..]).assign(&averaged);
+            // NaN marks flagged channels in the waterfall
+            waterfall_buf_tmp.slice_mut(s![-1, ..]).assign(
+                &ndarray::Zip::from(&averaged)
+                    .and(&flags)
+                    .map_collect(|&a, &flagged| if flagged { f32::NAN } else { a }),
+            );
             std::mem::swap(&mut waterfall_buf, &mut waterfall_buf_tmp);
 
             {
@@ -212,6 +961,10 @@ fn main() {
                 if let Ok(mut g) = wimg.try_lock() {
                     g.assign(&waterfall_buf);
                 }
+
+                if let Ok(mut g) = mbuf.try_lock() {
+                    g.assign(&flags);
+                }
             }
             if tx_repaint.is_empty() {
                 tx_repaint.send(()).unwrap();
@@ -260,25 +1013,49 @@ fn main() {
 
     let wimg = waterfall_img_buf.clone();
     let sbuf = spectrum_buf.clone();
+    let mbuf = mask_buf.clone();
     //let fmin = args.f0 - sampling_rate / 2.0;
     //let fmax = args.f0 + sampling_rate / 2.0;
     let state = State {
-        freq: args.f0,
-        samp_rate: sampling_rate,
+        freq: disp_freq,
+        samp_rate: disp_rate,
         min_ch: 0,
-        max_ch: args.nch - 1,
+        max_ch: ndisp - 1,
         yscale_max: 1.0,
         yscale_min: 0.0,
         ntime: args.ntime,
-        nch: args.nch,
-        device,
+        nch: ndisp,
+        // the sweep owns the LO, and a calibrated out file and the dispersion delays of
+        // the search have to stay at their frequency
+        device: if args.sweep_to.is_some()
+            || (args.outname.is_some() && args.cal.is_some())
+            || args.search
+        {
+            None
+        } else {
+            device
+        },
+        channels,
         floor: None,
+        stats: daq.stats.clone(),
+        clip_warn: args.clip_warn as f64,
+        correction: daq.correction.clone(),
+        calibration: daq.calibration.clone(),
+        calibrator,
+        power,
+        zoom: args.sweep_to.is_none().then(|| daq.zoom.clone()),
+        zoom_template,
+        zoom_buf,
+        drag_from: None,
+        show_velocity: args.velocity,
+        velocity,
+        vgrid: vgrid.map(|v| (v, args.vgrid_out.clone())),
         //outname: args.outname.clone(),
     };
     match eframe::run_native(
         "Waterfall",
         native_options,
-        Box::new(move |cc| Ok(Box::new(PlotWindow::new(cc, ctx1, wimg, sbuf, state)))),
+        Box::new(move |cc| Ok(Box::new(PlotWindow::new(cc, ctx1, wimg, sbuf, mbuf, state)))),
     ) {
         Ok(_) => {}
         Err(e) => {
@@ -289,20 +1066,25 @@ fn main() {
 
     println!("exit!");
     *running.lock().unwrap() = false;
+    if let Err(panics) = daq.join() {
+        for p in panics {
+            eprintln!("{p}");
+        }
+    }
+    if let Some(th) = th_out {
+        th.join().unwrap();
+    }
+    if let Some(th) = th_candidates {
+        th.join().unwrap();
+    }
     th_display.join().unwrap();
-
-    /*
-    th_daq.join().unwrap();
-    th_filter.join().unwrap();
-    th_channelize.join().unwrap();
-    th_update_display.join().unwrap();
-    */
-    //sdr_stream.deactivate(None).expect("failed to deactivate");
 }
 
 struct PlotWindow {
     pub waterfall_img: Arc<Mutex<Array2<f32>>>,
     pub spectrum_buf: Arc<Mutex<Array1<f32>>>,
+    /// channels flagged in the latest average
+    pub mask_buf: Arc<Mutex<Array1<bool>>>,
     pub state: State,
 }
 
@@ -312,6 +1094,7 @@ impl PlotWindow {
         ctx_holder: Arc<Mutex<Option<Context>>>,
         wimg: Arc<Mutex<Array2<f32>>>,
         sbuf: Arc<Mutex<Array1<f32>>>,
+        mbuf: Arc<Mutex<Array1<bool>>>,
         state: State,
     ) -> Self {
         // Disable feathering as it causes artifacts
@@ -328,6 +1111,7 @@ impl PlotWindow {
         Self {
             waterfall_img: wimg,
             spectrum_buf: sbuf,
+            mask_buf: mbuf,
             state,
         }
     }
@@ -341,6 +1125,7 @@ impl eframe::App for PlotWindow {
             .unwrap()
             .slice(s![.., self.state.min_ch..=self.state.max_ch])
             .iter()
+            .filter(|v| !v.is_nan())
             .fold((1e99, -1e99), |a, &v| {
                 let v = v as f64;
                 (if a.0 < v { a.0 } else { v }, if a.1 > v { a.1 } else { v })
@@ -409,11 +1194,113 @@ impl eframe::App for PlotWindow {
                 if ui.button("excl").clicked() {
                     self.state.floor = Some(self.spectrum_buf.lock().unwrap().clone());
                 }
+
+                let stats = self.state.stats.snapshot();
+                ui.label(format!(
+                    "{:.3} Msps pwr={:.1} dB lost={}/{}/{}",
+                    stats.sample_rate / 1e6,
+                    stats.power_db,
+                    stats.dropped_raw,
+                    stats.dropped_spectra,
+                    stats.dropped_averages + stats.rejected_averages
+                ));
+                if stats.clip_fraction > self.state.clip_warn {
+                    ui.colored_label(
+                        egui::Color32::RED,
+                        format!("CLIP {:.2}%", stats.clip_fraction * 100.0),
+                    );
+                } else {
+                    ui.label(format!("peak {:.2}", stats.adc_peak));
+                }
+
+                let mut dc = self.state.correction.dc();
+                if ui.checkbox(&mut dc, "DC").changed() {
+                    self.state.correction.set_dc(dc);
+                }
+                let mut iq = self.state.correction.imbalance();
+                if ui.checkbox(&mut iq, "IQ").changed() {
+                    self.state.correction.set_imbalance(iq);
+                }
+                ui.label(format!(
+                    "{:.2} dB {:.1} deg",
+                    stats.iq_gain_db, stats.iq_phase_deg
+                ));
+
+                if let Some(ref calibrator) = self.state.calibrator {
+                    let mut calibrator = calibrator.lock().unwrap();
+                    let recording = calibrator.recording();
+                    for (i, &name) in calibrator.method.references().iter().enumerate() {
+                        let label = match recording {
+                            Some((j, n)) if j == i => {
+                                format!("{name} {n}/{}", calibrator.n_average)
+                            }
+                            _ if calibrator.has(i) => format!("{name} ok"),
+                            _ => name.to_string(),
+                        };
+                        if ui.button(label).clicked() {
+                            // references are recorded uncalibrated
+                            *self.state.calibration.lock().unwrap() = None;
+                            calibrator.start(i);
+                        }
+                    }
+                }
+                let unit = self
+                    .state
+                    .calibration
+                    .lock()
+                    .unwrap()
+                    .as_ref()
+                    .map(|c| c.unit());
+                match unit {
+                    Some(unit) => {
+                        ui.label(format!("cal {unit}"));
+                        if self.state.calibrator.is_some() && ui.button("uncal").clicked() {
+                            *self.state.calibration.lock().unwrap() = None;
+                        }
+                    }
+                    None => {
+                        ui.label("uncal");
+                    }
+                }
+
+                ui.checkbox(&mut self.state.show_velocity, "km/s");
+                if self.state.show_velocity {
+                    ui.label(format!(
+                        "{} {:+.2}",
+                        self.state.velocity.unit(),
+                        self.state
+                            .velocity
+                            .correction(Utc::now().timestamp_nanos_opt().unwrap())
+                    ));
+                }
+
+                let zoomed = self
+                    .state
+                    .zoom
+                    .as_ref()
+                    .is_some_and(|z| z.lock().unwrap().is_some());
+                if zoomed && ui.button("unzoom").clicked() {
+                    if let Some(ref zoom) = self.state.zoom {
+                        *zoom.lock().unwrap() = None;
+                    }
+                    *self.state.zoom_buf.lock().unwrap() = None;
+                }
             })
         });
 
         CentralPanel::default().show(ctx, |ui| {
+            // frequency axes are in MHz, labelled with velocities if asked to
+            let velocity = self.state.velocity.clone();
+            let correction = velocity.correction(Utc::now().timestamp_nanos_opt().unwrap());
+            let to_velocity =
+                |mhz: &f64| format!("{:.1}", velocity.velocity(mhz * 1e6, correction));
             //println!("{}", ".");
+            let response = ui.interact(
+                ui.max_rect(),
+                ui.id().with("zoom selection"),
+                egui::Sense::drag(),
+            );
+            let origin = ui.max_rect().min;
             let root_area = EguiBackend::new(ui).into_drawing_area();
             root_area.fill(&WHITE).unwrap();
 
@@ -426,6 +1313,22 @@ impl eframe::App for PlotWindow {
                 let a = root_area.split_evenly((2, 1));
                 (a[0].clone(), a[1].clone())
             };
+            // ADC histogram right of the spectrum
+            let (lower, hist_area) = {
+                let (w, _) = lower.dim_in_pixel();
+                lower.split_horizontally(w * 4 / 5)
+            };
+            // radiometer and zoom below the spectrum
+            let zoom_range = self
+                .state
+                .zoom
+                .as_ref()
+                .and_then(|z| z.lock().unwrap().as_ref().map(|c| c.range));
+            let nextra = self.state.power.is_some() as usize + zoom_range.is_some() as usize;
+            let mut areas = lower.split_evenly((1 + nextra, 1)).into_iter();
+            let lower = areas.next().unwrap();
+            let power_area = self.state.power.as_ref().and_then(|_| areas.next());
+            let zoom_area = zoom_range.and_then(|_| areas.next());
 
             let (w, h) = upper.dim_in_pixel();
 
@@ -442,6 +1345,10 @@ impl eframe::App for PlotWindow {
                 .unwrap()
                 .iter()
                 .flat_map(|&v| {
+                    if v.is_nan() {
+                        // flagged as RFI
+                        return [255, 0, 0];
+                    }
                     let v = v as f64;
                     let v = v.max(min_value);
                     let v = v.min(max_value);
@@ -485,7 +1392,11 @@ impl eframe::App for PlotWindow {
                 )
                 .unwrap();
 
-            cc.configure_mesh().draw().unwrap();
+            let mut mesh = cc.configure_mesh();
+            if self.state.show_velocity {
+                mesh.x_label_formatter(&to_velocity);
+            }
+            mesh.draw().unwrap();
             cc.draw_series(std::iter::once(bmp)).unwrap();
 
             let spec = self.spectrum_buf.lock().unwrap();
@@ -529,7 +1440,11 @@ impl eframe::App for PlotWindow {
 
             //println!("{} {}", min_value, max_value);
 
-            cc.configure_mesh().draw().unwrap();
+            let mut mesh = cc.configure_mesh();
+            if self.state.show_velocity {
+                mesh.x_label_formatter(&to_velocity);
+            }
+            mesh.draw().unwrap();
             cc.draw_series(LineSeries::new(
                 (0..self.state.nch).map(|ich| {
                     (
@@ -542,6 +1457,168 @@ impl eframe::App for PlotWindow {
             ))
             .unwrap();
 
+            let mask = self.mask_buf.lock().unwrap();
+            cc.draw_series((0..self.state.nch).filter(|&ich| mask[ich]).map(|ich| {
+                Circle::new(
+                    (
+                        (ich as f64 / self.state.nch as f64 * self.state.samp_rate + fmin_raw)
+                            / 1e6,
+                        ys1,
+                    ),
+                    2,
+                    RED.filled(),
+                )
+            }))
+            .unwrap();
+
+            if let Some(r) = zoom_range {
+                let f = |ich: f64| (ich * df + fmin_raw) / 1e6;
+                cc.draw_series(std::iter::once(Rectangle::new(
+                    [
+                        (f(r.first as f64 - 0.5), ys1),
+                        (f(r.last as f64 + 0.5), ys2),
+                    ],
+                    GREEN.mix(0.2).filled(),
+                )))
+                .unwrap();
+            }
+
+            // dragging over the spectrum selects the channels to zoom into
+            if let Some(ref zoom) = self.state.zoom {
+                let (xs, ys) = lower.get_pixel_range();
+                let coord = cc.into_coord_trans();
+                let nch = self.state.nch;
+                let pixel_ch = |p: egui::Pos2| {
+                    let p = ((p.x - origin.x) as i32, (p.y - origin.y) as i32);
+                    if !xs.contains(&p.0) || !ys.contains(&p.1) {
+                        return None;
+                    }
+                    coord(p).map(|(f, _)| {
+                        ((f * 1e6 - fmin_raw) / df)
+                            .round()
+                            .clamp(0.0, (nch - 1) as f64) as usize
+                    })
+                };
+                if response.drag_started() {
+                    self.state.drag_from = response.interact_pointer_pos().and_then(pixel_ch);
+                }
+                if response.drag_stopped()
+                    && let Some(a) = self.state.drag_from.take()
+                    && let Some(b) = response.interact_pointer_pos().and_then(pixel_ch)
+                {
+                    let range = ChannelRange {
+                        first: a.min(b),
+                        last: a.max(b),
+                    };
+                    println!("zooming into channels {range}");
+                    *zoom.lock().unwrap() = Some(ZoomConfig {
+                        range,
+                        ..self.state.zoom_template.clone()
+                    });
+                    *self.state.zoom_buf.lock().unwrap() = None;
+                }
+            }
+
+            if let (Some(area), Some(z)) = (zoom_area, &*self.state.zoom_buf.lock().unwrap()) {
+                let f0 = self.state.freq + z.freq_offset;
+                let x = &z.spectrum.data;
+                let f = |i: usize| (f0 + i as f64 * z.channel_bandwidth) / 1e6;
+                let (lo, hi) = x
+                    .iter()
+                    .filter(|&&v| v > 0.0)
+                    .fold((1e99, -1e99), |a: (f64, f64), &v| {
+                        (a.0.min(db(v as f64)), a.1.max(db(v as f64)))
+                    });
+                if lo <= hi && !x.is_empty() {
+                    let mut zc = ChartBuilder::on(&area)
+                        .margin_left(20)
+                        .margin_right(20)
+                        .set_label_area_size(LabelAreaPosition::Left, 5)
+                        .set_label_area_size(LabelAreaPosition::Right, 5)
+                        .set_label_area_size(LabelAreaPosition::Bottom, 25)
+                        .build_cartesian_2d(f(0)..f(x.len() - 1), (lo - 0.5)..(hi + 0.5))
+                        .unwrap();
+                    let mut mesh = zc.configure_mesh();
+                    if self.state.show_velocity {
+                        mesh.x_label_formatter(&to_velocity);
+                    }
+                    mesh.draw().unwrap();
+                    zc.draw_series(LineSeries::new(
+                        x.iter()
+                            .enumerate()
+                            .filter(|&(_, &v)| v > 0.0)
+                            .map(|(i, &v)| (f(i), db(v as f64))),
+                        &GREEN,
+                    ))
+                    .unwrap();
+                }
+            }
+
+            if let (Some(area), Some((buf, ranges))) = (power_area, &self.state.power) {
+                let buf = buf.lock().unwrap();
+                if let (Some(first), Some(last)) = (buf.front(), buf.back()) {
+                    // in s before the latest sample
+                    let t = |p: &PowerSample| (p.time_ns - last.time_ns) as f64 / 1e9;
+                    let (lo, hi) = buf
+                        .iter()
+                        .flat_map(|p| &p.power)
+                        .filter(|&&x| x > 0.0)
+                        .fold((1e99, -1e99), |a: (f64, f64), &v| {
+                            (a.0.min(db(v)), a.1.max(db(v)))
+                        });
+                    if lo <= hi {
+                        let mut pc = ChartBuilder::on(&area)
+                            .margin_left(20)
+                            .margin_right(20)
+                            .set_label_area_size(LabelAreaPosition::Left, 5)
+                            .set_label_area_size(LabelAreaPosition::Right, 5)
+                            .set_label_area_size(LabelAreaPosition::Bottom, 25)
+                            .build_cartesian_2d(t(first).min(-1e-3)..0.0, (lo - 0.1)..(hi + 0.1))
+                            .unwrap();
+                        pc.configure_mesh().draw().unwrap();
+                        for (i, r) in ranges.iter().enumerate() {
+                            let color = Palette99::pick(i).to_rgba();
+                            pc.draw_series(LineSeries::new(
+                                buf.iter()
+                                    .filter(|p| p.power[i] > 0.0)
+                                    .map(|p| (t(p), db(p.power[i]))),
+                                color,
+                            ))
+                            .unwrap()
+                            .label(r.to_string())
+                            .legend(move |(x, y)| {
+                                PathElement::new(vec![(x, y), (x + 10, y)], color)
+                            });
+                        }
+                        pc.configure_series_labels()
+                            .background_style(WHITE.mix(0.8))
+                            .draw()
+                            .unwrap();
+                    }
+                }
+            }
+
+            let hist = self.state.stats.snapshot().adc_histogram;
+            if !hist.is_empty() {
+                let nbins = hist.len();
+                let ymax = (*hist.iter().max().unwrap() as f64 + 1.0).log10();
+                let mut hc = ChartBuilder::on(&hist_area)
+                    .margin_right(20)
+                    .set_label_area_size(LabelAreaPosition::Left, 5)
+                    .set_label_area_size(LabelAreaPosition::Bottom, 25)
+                    .build_cartesian_2d(-1.0..1.0, 0.0..(ymax + 0.5))
+                    .unwrap();
+                hc.configure_mesh().draw().unwrap();
+                hc.draw_series(hist.iter().enumerate().map(|(i, &c)| {
+                    let x0 = i as f64 / nbins as f64 * 2.0 - 1.0;
+                    let x1 = x0 + 2.0 / nbins as f64;
+                    // the outermost bins include the clipped samples
+                    let color = if i == 0 || i == nbins - 1 { RED } else { BLUE };
+                    Rectangle::new([(x0, 0.0), (x1, (c as f64 + 1.0).log10())], color.filled())
+                }))
+                .unwrap();
+            }
+
             root_area.present().unwrap();
             let df = if ctx
                 .input(|input| input.key_pressed(Key::D) | input.key_pressed(Key::ArrowUp))
@@ -565,15 +1642,39 @@ impl eframe::App for PlotWindow {
                 0.0
             };
 
-            if df != 0.0_f64 {
-                let f = self.state.device.frequency(Direction::Rx, 0).unwrap();
-                self.state
-                    .device
-                    .set_frequency(Direction::Rx, 0, f + df, ())
-                    .unwrap();
-                let f = f + df;
+            if df != 0.0_f64
+                && let Some(ref device) = self.state.device
+            {
+                let f = device.frequency(Direction::Rx, 0).unwrap() + df;
+                for &ch in &self.state.channels {
+                    device.set_frequency(Direction::Rx, ch, f, ()).unwrap();
+                }
                 self.state.freq = f;
                 self.state.floor = None;
+                if let Some(ref calibrator) = self.state.calibrator {
+                    let mut calibrator = calibrator.lock().unwrap();
+                    let setup = CalSetup {
+                        freq: f,
+                        ..calibrator.setup.clone()
+                    };
+                    // references taken at the old frequency are of no use
+                    let fresh = Calibrator::new(calibrator.method, setup, calibrator.n_average);
+                    *calibrator = fresh;
+                    let mut calibration = self.state.calibration.lock().unwrap();
+                    if let Some(Err(e)) = calibration
+                        .as_ref()
+                        .map(|c| c.setup.check(&calibrator.setup))
+                    {
+                        println!("{e}, no longer calibrating");
+                        *calibration = None;
+                    }
+                }
+                if let Some((ref vgrid, ref path)) = self.state.vgrid
+                    && let Some(avg) = vgrid.lock().unwrap().take()
+                {
+                    avg.save(path).unwrap();
+                    println!("no longer averaging onto the velocity grid, saved to {path}");
+                }
                 println!("freq changed to {f}");
             }
         });
@@ -13,6 +13,11 @@ use ndarray::{Array1, Axis, s};
 
 use num::complex::Complex;
 use rsdsp::{ospfb2::Analyzer, windowed_fir::pfb_coeff};
+use soapy_spec_acc::{
+    iq_file::{IqFileSource, SampleFormat},
+    source::{SampleSource, SoapySource, SourceError},
+    synth::{Signal, SynthSource},
+};
 use soapysdr::{Device, Direction};
 
 type Ftype = f32;
@@ -66,62 +71,114 @@ struct Args {
     vga: f64,
 
     #[clap(short('s'), value_name("sampling rate in MHz"), default_value("6"))]
-    sampling_rate: u32,
+    sampling_rate: f64,
+
+    #[clap(
+        long("synth"),
+        value_name("synthetic signal"),
+        help(
+            "use a synthetic source instead of the SDR, repeatable: noise:<sigma>, tone:<freq>:<ampl>, chirp:<f_start>:<f_stop>:<period>:<ampl>, pulse:<period>:<width>:<dm>:<ampl>, rfi:<rate>:<duration>:<ampl>"
+        )
+    )]
+    synth: Vec<Signal>,
+
+    #[clap(long("replay"), value_name("IQ file to replay instead of the SDR"))]
+    replay: Option<String>,
+
+    #[clap(
+        long("replay-format"),
+        value_name("cf32, cs16, cs8 or cu8"),
+        default_value("cs16")
+    )]
+    replay_format: SampleFormat,
+
+    #[clap(
+        long("replay-start"),
+        value_name("offset into the replay file in s"),
+        default_value("0")
+    )]
+    replay_start: f64,
+
+    #[clap(long("replay-duration"), value_name("replayed duration in s"))]
+    replay_duration: Option<f64>,
+
+    #[clap(long("loop"), help("restart the replayed segment when it ends"))]
+    replay_loop: bool,
+
+    #[clap(
+        long("realtime"),
+        help("throttle synthetic or replayed data to the sampling rate")
+    )]
+    realtime: bool,
 }
 
 #[tokio::main]
 async fn main() {
     let args = Args::parse();
 
-    if args.sampling_rate != 3 && args.sampling_rate != 6 {
+    let use_sdr = args.synth.is_empty() && args.replay.is_none();
+    if use_sdr && args.sampling_rate != 3.0 && args.sampling_rate != 6.0 {
         eprintln!("Sampling rate can only be either 3 or 6 MSps");
         return;
     }
 
-    let sampling_rate = args.sampling_rate as f64 * 1e6;
+    let sampling_rate = args.sampling_rate * 1e6;
     assert_eq!(args.nch & (args.nch - 1), 0);
 
     let coeff = pfb_coeff::<Ftype>(args.nch / 2, args.ntap, 1.1 as Ftype);
     let mut pfb = Analyzer::<Complex<Ftype>, Ftype>::new(args.nch, coeff.as_slice().unwrap());
 
-    let device = Device::new("driver=airspy").unwrap();
+    let mut source: Box<dyn SampleSource> = if use_sdr {
+        let device = Device::new("driver=airspy").unwrap();
 
-    for g in device.list_gains(Direction::Rx, 0).unwrap() {
-        println!("{g}");
-    }
+        for g in device.list_gains(Direction::Rx, 0).unwrap() {
+            println!("{g}");
+        }
 
-    device.set_antenna(Direction::Rx, 0, "RX").unwrap();
-    device
-        .set_sample_rate(Direction::Rx, 0, sampling_rate)
-        .unwrap();
-    device
-        .set_gain_element(Direction::Rx, 0, "LNA", args.lna)
-        .unwrap();
-    device
-        .set_gain_element(Direction::Rx, 0, "MIX", args.mix)
-        .unwrap();
-    device
-        .set_gain_element(Direction::Rx, 0, "VGA", args.vga)
-        .unwrap();
-
-    device.set_frequency(Direction::Rx, 0, args.f0, ()).unwrap();
-    let mut sdr_stream = device.rx_stream::<Complex<Ftype>>(&[0]).unwrap();
-    sdr_stream
-        .activate(None)
-        .expect("failed to activate stream");
+        device.set_antenna(Direction::Rx, 0, "RX").unwrap();
+        device
+            .set_sample_rate(Direction::Rx, 0, sampling_rate)
+            .unwrap();
+        device
+            .set_gain_element(Direction::Rx, 0, "LNA", args.lna)
+            .unwrap();
+        device
+            .set_gain_element(Direction::Rx, 0, "MIX", args.mix)
+            .unwrap();
+        device
+            .set_gain_element(Direction::Rx, 0, "VGA", args.vga)
+            .unwrap();
+
+        device.set_frequency(Direction::Rx, 0, args.f0, ()).unwrap();
+        Box::new(SoapySource::open(&device, &[0], sampling_rate).unwrap())
+    } else if let Some(ref replay) = args.replay {
+        Box::new(
+            IqFileSource::open(replay, args.replay_format, sampling_rate)
+                .unwrap()
+                .with_segment(args.replay_start, args.replay_duration)
+                .unwrap()
+                .looping(args.replay_loop)
+                .throttled(args.realtime),
+        )
+    } else {
+        Box::new(
+            SynthSource::new(args.synth.clone(), sampling_rate, args.f0).throttled(args.realtime),
+        )
+    };
+    source.activate().expect("failed to activate stream");
 
     let mut num = 0;
     let mut cnt = 0;
+    let mut failures = 0;
 
     let t0 = Utc::now().timestamp_millis(); // e.g. `2014-11-28T12:45:59.324310806Z`
     let daq_stream = stream! {
         loop{
-            let mut buf = Vec::with_capacity(sdr_stream.mtu().unwrap());
-            buf.resize(sdr_stream.mtu().unwrap(), Complex::default());
-            match sdr_stream
-                .read(&mut [&mut buf], 1_000_000)
+            let mut buf = vec![Complex::<Ftype>::default(); source.mtu()];
+            match source.read(&mut [&mut buf])
             {
                 Ok(len)=>{
+                failures = 0;
                 buf.resize(len, Complex::default());
                 yield buf;
                 cnt += 1;
@@ -135,8 +192,19 @@ async fn main() {
                     println!("{} Msps", sps / 1e6);
                 }
                 }
-                otherwise =>{
-                    println!("{otherwise:?}");
+                Err(SourceError::Eof) => {
+                    break;
+                }
+                Err(e) => {
+                    eprintln!("WARNING: {e}");
+                    failures += 1;
+                    if failures >= 10 {
+                        failures = 0;
+                        match source.reopen() {
+                            Ok(()) => eprintln!("source reopened"),
+                            Err(e) => eprintln!("failed to reopen source: {e}"),
+                        }
+                    }
                 }
             }
 
@@ -147,8 +215,7 @@ async fn main() {
     pin_mut!(daq_stream); // needed for iteration
 
     let spectrum_stream = stream! {
-        loop{
-            let raw_data=daq_stream.next().await.unwrap();
+        while let Some(raw_data)=daq_stream.next().await {
             for x in pfb.analyze_raw_par(&raw_data).axis_iter(Axis(0)){
                 let x1 = Array1::from_iter(
                     x.slice(s![args.nch / 2..args.nch])
@@ -168,7 +235,8 @@ async fn main() {
             let mut temp = Array1::<Ftype>::zeros(args.nch);
             for _i in 0..args.n_average {
                 //temp = temp + //rx_spectrum.recv().unwrap();
-                temp=temp+spectrum_stream.next().await.unwrap();
+                let Some(x)=spectrum_stream.next().await else { return; };
+                temp=temp+x;
             }
             temp /= args.n_average as Ftype;
             if temp.iter().all(|&x| x>0_f32){
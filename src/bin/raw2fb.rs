@@ -2,10 +2,12 @@
 use binrw::BinWrite;
 use clap::Parser;
 use soapy_spec_acc::{
+    meta::SpectrumMeta,
     sigproc_io::Header,
     utils::{read_data, write_data},
 };
 
+/// Options also found in the `.meta` file written next to the input must agree with it.
 #[derive(Debug, Parser)]
 #[clap(author, about, version)]
 struct Args {
@@ -13,17 +15,16 @@ struct Args {
     f0_Hz: f64,
 
     #[clap(short('n'), long("nch"), value_name("num of channels"))]
-    nch: usize,
+    nch: Option<usize>,
 
     #[clap(
         short('a'),
-        value_name("number of time points to calculate mean"),
-        default_value("1")
+        value_name("number of time points to calculate mean, default 1")
     )]
-    n_average: usize,
+    n_average: Option<usize>,
 
-    #[clap(short('s'), value_name("sampling rate in MHz"), default_value("6"))]
-    sampling_rate: u32,
+    #[clap(short('s'), value_name("sampling rate in MHz, default 6"))]
+    sampling_rate: Option<f64>,
 
     #[clap(
         short('i'),
@@ -41,35 +42,115 @@ struct Args {
     )]
     outname: String,
 
-    #[clap(long("osr"), value_name("oversampling ratio"), default_value("2"))]
-    osr: usize,
+    #[clap(long("meta"), value_name("metadata of the input, default <in>.meta"))]
+    meta: Option<String>,
+
+    #[clap(long("osr"), value_name("oversampling ratio, default 2"))]
+    osr: Option<f64>,
+
+    #[clap(
+        long("nifs"),
+        value_name("number of IFs per spectrum, 4 for corr or stokes products, default 1")
+    )]
+    nifs: Option<usize>,
+
+    #[clap(
+        long("tstart"),
+        value_name("MJD of the first spectrum, default 51544.0")
+    )]
+    tstart: Option<f64>,
+}
+
+/// Take `name` from the metadata if there is any, from the command line otherwise.
+fn resolve<T: PartialEq + std::fmt::Display + Copy>(
+    name: &str,
+    arg: Option<T>,
+    meta: Option<T>,
+    default: T,
+) -> T {
+    match (arg, meta) {
+        (Some(a), Some(m)) if a != m => {
+            eprintln!("{name}={a} given, but the input was written with {name}={m}");
+            std::process::exit(1);
+        }
+        (_, Some(m)) => m,
+        (Some(a), None) => a,
+        (None, None) => default,
+    }
 }
 
 pub fn main() -> Result<(), std::io::Error> {
     let args = Args::parse();
-    let fs_MHz = args.sampling_rate as f64;
+
+    let meta_path = args
+        .meta
+        .clone()
+        .unwrap_or_else(|| SpectrumMeta::path_for(&args.inname));
+    let meta = if std::path::Path::new(&meta_path).exists() {
+        match SpectrumMeta::load(&meta_path) {
+            Ok(m) => Some(m),
+            Err(e) => {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+        }
+    } else if args.meta.is_some() {
+        eprintln!("{meta_path} not found");
+        std::process::exit(1);
+    } else {
+        eprintln!("no {meta_path}, relying on the command line");
+        None
+    };
+    let m = meta.as_ref();
+
+    let nch = resolve("nch", args.nch, m.map(|m| m.nch), 0);
+    if nch == 0 {
+        eprintln!("nch is needed without a meta file");
+        std::process::exit(1);
+    }
+    let n_average = resolve("n_average", args.n_average, m.map(|m| m.n_average), 1);
+    let fs_MHz = resolve(
+        "sampling_rate",
+        args.sampling_rate,
+        m.map(|m| m.sample_rate / 1e6),
+        6.0,
+    );
+    let osr = resolve("osr", args.osr, m.map(|m| m.osr), 2.0);
+    let nifs = resolve("nifs", args.nifs, m.map(|m| m.nifs), 1);
+    let tstart = resolve("tstart", args.tstart, m.and_then(|m| m.tstart), 51544.0);
+
+    let spectra = SpectrumMeta {
+        nch,
+        nifs,
+        sample_rate: fs_MHz * 1e6,
+        osr,
+        n_average,
+        tstart: Some(tstart),
+        unit: m.and_then(|m| m.unit.clone()),
+        velocity: m.and_then(|m| m.velocity.clone()),
+    };
     println!("fs={fs_MHz:e}");
-    let nch = args.nch;
-    let dt = 1.0 / (fs_MHz * 1e6) * nch as f64 / args.osr as f64 * args.n_average as f64;
-    let foff_MHz = -fs_MHz / nch as f64;
+    let dt = spectra.tsamp();
+    let foff_MHz = -spectra.channel_bandwidth() / 1e6;
 
     println!("dt={} us", dt * 1e6);
     let fc_MHz = args.f0_Hz / 1e6;
     let fch1_MHz = fc_MHz + fs_MHz / 2.0 + foff_MHz / 2.0;
     println!("fch1: {fch1_MHz} MHz");
 
-    let header = Header::new(fch1_MHz, nch, foff_MHz, 51544.0, dt);
+    let mut header = Header::new(fch1_MHz, nch, foff_MHz, tstart, dt);
+    header.set_nifs(nifs);
     let mut outfile = std::fs::File::create(&args.outname)?;
 
     header.write_le(&mut outfile).unwrap();
 
     let mut infile = std::fs::File::open(&args.inname)?;
-    let mut buf = vec![0_f32; nch];
-    let mut buf1 = vec![0_f32; nch];
+    let mut buf = vec![0_f32; nch * nifs];
+    let mut buf1 = vec![0_f32; nch * nifs];
     while let Ok(()) = read_data(&mut infile, &mut buf) {
-        buf1.iter_mut().zip(buf.iter().rev()).for_each(|(a, &b)| {
-            *a = b;
-        });
+        buf1.chunks_mut(nch)
+            .zip(buf.chunks(nch))
+            .for_each(|(a, b)| a.iter_mut().zip(b.iter().rev()).for_each(|(a, &b)| *a = b));
         write_data(&mut outfile, &buf1);
     }
     Ok(())
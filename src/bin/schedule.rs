@@ -0,0 +1,276 @@
+use chrono::Utc;
+use clap::Parser;
+
+use soapy_spec_acc::{
+    daq::{DaqConfig, Spectrum, run_daq},
+    meta::SpectrumMeta,
+    queue::{Policy, QueueSetting, Queues},
+    schedule::Schedule,
+    source::{SampleSource, SoapySource},
+    synth::{Signal, SynthSource},
+    utils::write_data,
+};
+use soapysdr::{Device, Direction};
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+#[derive(Debug, Parser)]
+#[clap(author, about, version)]
+struct Args {
+    #[clap(value_name("schedule file"))]
+    schedule: String,
+
+    #[clap(
+        short('t'),
+        long("tap"),
+        value_name("pfb tap per ch"),
+        default_value("4")
+    )]
+    ntap: usize,
+
+    #[clap(short('s'), value_name("sampling rate in MHz"), default_value("6"))]
+    sampling_rate: f64,
+
+    #[clap(
+        long("log"),
+        value_name("log of what was done"),
+        default_value("schedule.log")
+    )]
+    log: String,
+
+    #[clap(long("check"), help("only validate the schedule"))]
+    check: bool,
+
+    #[clap(
+        long("synth"),
+        value_name("synthetic signal"),
+        help(
+            "use a synthetic source instead of the SDR, repeatable: noise:<sigma>, tone:<freq>:<ampl>, chirp:<f_start>:<f_stop>:<period>:<ampl>, pulse:<period>:<width>:<dm>:<ampl>, rfi:<rate>:<duration>:<ampl>"
+        )
+    )]
+    synth: Vec<Signal>,
+
+    #[clap(
+        long("queue"),
+        value_name("<stage>=<policy>[:<capacity>]"),
+        help(
+            "queue between pipeline stages, repeatable: stage raw, record, spectrum or averaged, policy drop-newest, drop-oldest or block; averaged blocks by default"
+        )
+    )]
+    queue: Vec<QueueSetting>,
+}
+
+struct Log(File);
+
+impl Log {
+    fn write(&mut self, msg: &str) {
+        let line = format!("{} {msg}", Utc::now().to_rfc3339());
+        println!("{line}");
+        writeln!(self.0, "{line}").unwrap();
+        self.0.flush().unwrap();
+    }
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let use_sdr = args.synth.is_empty();
+    if use_sdr && args.sampling_rate != 3.0 && args.sampling_rate != 6.0 {
+        eprintln!("Sampling rate can only be either 3 or 6 MSps");
+        return;
+    }
+    let sampling_rate = args.sampling_rate * 1e6;
+
+    let schedule = match Schedule::load(&args.schedule) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+    let times = match schedule.validate(Utc::now()) {
+        Ok(t) => t,
+        Err(errors) => {
+            for e in errors {
+                eprintln!("{e}");
+            }
+            std::process::exit(1);
+        }
+    };
+    for (e, (begin, end)) in schedule.entries.iter().zip(&times) {
+        println!(
+            "{begin} - {end}: {} MHz lna/mix/vga={}/{}/{} nch={} avg={} out={}",
+            e.freq / 1e6,
+            e.lna,
+            e.mix,
+            e.vga,
+            e.nch,
+            e.n_average,
+            e.out.as_deref().unwrap_or("-")
+        );
+    }
+    if args.check {
+        return;
+    }
+
+    let running = Arc::new(AtomicBool::new(true));
+    let running1 = running.clone();
+    ctrlc::set_handler(move || {
+        println!("bye!");
+        running1.store(false, Ordering::Relaxed);
+    })
+    .unwrap();
+
+    let mut log = Log(OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&args.log)
+        .unwrap());
+    log.write(&format!("schedule {} started", args.schedule));
+
+    let device = use_sdr.then(|| {
+        let device = Device::new("driver=airspy").unwrap();
+        device.set_antenna(Direction::Rx, 0, "RX").unwrap();
+        device
+            .set_sample_rate(Direction::Rx, 0, sampling_rate)
+            .unwrap();
+        device
+    });
+
+    for (e, &(begin, end)) in schedule.entries.iter().zip(&times) {
+        if end <= Utc::now() {
+            log.write(&format!("line {}: skipped, already over", e.line));
+            continue;
+        }
+        while Utc::now() < begin && running.load(Ordering::Relaxed) {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        if !running.load(Ordering::Relaxed) {
+            break;
+        }
+        let late = (Utc::now() - begin).as_seconds_f64();
+        if late >= 1.0 {
+            log.write(&format!(
+                "line {}: starting {late:.1} s late, its begin {begin} was in the past",
+                e.line
+            ));
+        }
+
+        let source: Box<dyn SampleSource> = if let Some(ref device) = device {
+            device
+                .set_gain_element(Direction::Rx, 0, "LNA", e.lna)
+                .unwrap();
+            device
+                .set_gain_element(Direction::Rx, 0, "MIX", e.mix)
+                .unwrap();
+            device
+                .set_gain_element(Direction::Rx, 0, "VGA", e.vga)
+                .unwrap();
+            device.set_frequency(Direction::Rx, 0, e.freq, ()).unwrap();
+            // what the hardware made of the requested settings
+            let gain = |name| device.gain_element(Direction::Rx, 0, name).unwrap();
+            log.write(&format!(
+                "line {}: tuned to {} MHz lna/mix/vga={}/{}/{}",
+                e.line,
+                device.frequency(Direction::Rx, 0).unwrap() / 1e6,
+                gain("LNA"),
+                gain("MIX"),
+                gain("VGA")
+            ));
+            Box::new(SoapySource::open(device, &[0], sampling_rate).unwrap())
+        } else {
+            Box::new(SynthSource::new(args.synth.clone(), sampling_rate, e.freq).throttled(true))
+        };
+        log.write(&format!(
+            "line {}: started {} MHz lna/mix/vga={}/{}/{} nch={} avg={} out={}",
+            e.line,
+            e.freq / 1e6,
+            e.lna,
+            e.mix,
+            e.vga,
+            e.nch,
+            e.n_average,
+            e.out.as_deref().unwrap_or("-")
+        ));
+
+        // nothing written to the out file may be lost
+        let mut queues = Queues::new(e.n_average);
+        queues.averaged.policy = Policy::Block;
+        args.queue.iter().for_each(|q| queues.apply(q));
+        let daq = match run_daq(
+            source,
+            DaqConfig {
+                queues,
+                ..DaqConfig::new(e.nch, args.ntap, e.n_average)
+            },
+        ) {
+            Ok(daq) => daq,
+            Err(err) => {
+                log.write(&format!(
+                    "line {}: failed to activate the source: {err}",
+                    e.line
+                ));
+                continue;
+            }
+        };
+        let mut outfile = e.out.as_ref().map(|out| File::create_new(out).unwrap());
+        let mut nspectra = 0;
+        let mut first_mjd = None;
+        let mut events = 0;
+        let mut save = |x: Spectrum| {
+            if first_mjd.is_none()
+                && let Some(ref out) = e.out
+            {
+                let meta = SpectrumMeta {
+                    tstart: Some(x.mjd()),
+                    ..daq.meta.clone()
+                };
+                meta.save(&SpectrumMeta::path_for(out)).unwrap();
+            }
+            first_mjd.get_or_insert(x.mjd());
+            if let Some(ref mut f) = outfile {
+                write_data(f, x.data.as_slice().unwrap());
+            }
+            daq.averaged_pool.put(x);
+            nspectra += 1;
+        };
+        let mut log_events = || {
+            while let Ok(ev) = daq.rx_events.try_recv() {
+                events += 1;
+                eprintln!("WARNING: {ev}");
+            }
+        };
+        while Utc::now() < end && running.load(Ordering::Relaxed) {
+            log_events();
+            if let Ok(x) = daq.rx_averaged[0].recv_timeout(Duration::from_millis(100)) {
+                save(x);
+            }
+        }
+        // what is still queued or integrating belongs to this entry, the averaging
+        // thread emits it and disconnects once the reader has stopped
+        daq.stop();
+        while let Ok(x) = daq.rx_averaged[0].recv() {
+            save(x);
+        }
+        log_events();
+        let stats = daq.stats.snapshot();
+        if let Err(panics) = daq.join() {
+            for p in panics {
+                log.write(&format!("line {}: {p}", e.line));
+            }
+        }
+        log.write(&format!(
+            "line {}: finished, {nspectra} spectra from MJD {:.9}, {events} events, {stats}",
+            e.line,
+            first_mjd.unwrap_or(0.0)
+        ));
+    }
+    log.write(&format!("schedule {} finished", args.schedule));
+}
@@ -0,0 +1,233 @@
+use clap::Parser;
+
+use soapy_spec_acc::{
+    daq::{DaqConfig, run_daq},
+    queue::{Policy, QueueSetting, Queues},
+    source::{SampleSource, SoapySource},
+    sweep::SweepPlan,
+    synth::{Signal, SynthSource},
+    utils::write_data,
+};
+use soapysdr::{Device, Direction};
+use std::fs::OpenOptions;
+
+#[derive(Debug, Parser)]
+#[clap(author, about, version)]
+struct Args {
+    #[clap(short('f'), long("from"), value_name("lowest freq in Hz"))]
+    f_start: f64,
+
+    #[clap(long("to"), value_name("highest freq in Hz"))]
+    f_stop: f64,
+
+    #[clap(
+        short('n'),
+        long("nch"),
+        value_name("num of channels, must <=8192"),
+        default_value("512")
+    )]
+    nch: usize,
+
+    #[clap(
+        short('t'),
+        long("tap"),
+        value_name("pfb tap per ch"),
+        default_value("4")
+    )]
+    ntap: usize,
+
+    #[clap(
+        short('a'),
+        value_name("number of time points to calculate mean"),
+        default_value("128")
+    )]
+    n_average: usize,
+
+    #[clap(long("lna"), value_name("lna gain"), default_value("5"))]
+    lna: f64,
+
+    #[clap(long("mix"), value_name("mix gain"), default_value("5"))]
+    mix: f64,
+
+    #[clap(long("vga"), value_name("vga gain"), default_value("5"))]
+    vga: f64,
+
+    #[clap(short('s'), value_name("sampling rate in MHz"), default_value("6"))]
+    sampling_rate: f64,
+
+    #[clap(
+        long("dwell"),
+        value_name("integration per step in s"),
+        default_value("0.5")
+    )]
+    dwell: f64,
+
+    #[clap(
+        long("settle"),
+        value_name("time discarded after retuning in s"),
+        default_value("0.05")
+    )]
+    settle: f64,
+
+    #[clap(
+        long("edge"),
+        value_name("fraction of the band trimmed on each side"),
+        default_value("0.125")
+    )]
+    edge: f64,
+
+    #[clap(
+        long("overlap"),
+        value_name("fraction of the trimmed band shared by neighbouring steps"),
+        default_value("0.1")
+    )]
+    overlap: f64,
+
+    #[clap(
+        long("sweeps"),
+        value_name("number of sweeps, 0 to run until interrupted"),
+        default_value("0")
+    )]
+    nsweeps: usize,
+
+    #[clap(
+        short('o'),
+        long("out"),
+        value_name("out file name, one panorama per sweep")
+    )]
+    outname: Option<String>,
+
+    #[clap(
+        long("synth"),
+        value_name("synthetic signal"),
+        help(
+            "use a synthetic source instead of the SDR, repeatable: noise:<sigma>, tone:<freq>:<ampl>, chirp:<f_start>:<f_stop>:<period>:<ampl>, pulse:<period>:<width>:<dm>:<ampl>, rfi:<rate>:<duration>:<ampl>"
+        )
+    )]
+    synth: Vec<Signal>,
+
+    #[clap(
+        long("queue"),
+        value_name("<stage>=<policy>[:<capacity>]"),
+        help(
+            "queue between pipeline stages, repeatable: stage raw, record, spectrum or averaged, policy drop-newest, drop-oldest or block; averaged blocks by default with -o"
+        )
+    )]
+    queue: Vec<QueueSetting>,
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let use_sdr = args.synth.is_empty();
+    if use_sdr && args.sampling_rate != 3.0 && args.sampling_rate != 6.0 {
+        eprintln!("Sampling rate can only be either 3 or 6 MSps");
+        return;
+    }
+    let sampling_rate = args.sampling_rate * 1e6;
+    assert_eq!(args.nch & (args.nch - 1), 0);
+
+    let plan = SweepPlan {
+        edge: args.edge,
+        overlap: args.overlap,
+        settle: args.settle,
+        dwell: args.dwell,
+        ..SweepPlan::new(args.f_start, args.f_stop, sampling_rate)
+    };
+    if let Err(e) = plan.check() {
+        eprintln!("{e}");
+        return;
+    }
+    let steps = plan.steps();
+    println!(
+        "{} steps, {} bins of {} Hz from {} Hz",
+        steps.len(),
+        plan.nbins(args.nch),
+        sampling_rate / args.nch as f64,
+        args.f_start
+    );
+
+    let (device, source): (_, Box<dyn SampleSource>) = if use_sdr {
+        let device = Device::new("driver=airspy").unwrap();
+        device.set_antenna(Direction::Rx, 0, "RX").unwrap();
+        device
+            .set_sample_rate(Direction::Rx, 0, sampling_rate)
+            .unwrap();
+        device
+            .set_gain_element(Direction::Rx, 0, "LNA", args.lna)
+            .unwrap();
+        device
+            .set_gain_element(Direction::Rx, 0, "MIX", args.mix)
+            .unwrap();
+        device
+            .set_gain_element(Direction::Rx, 0, "VGA", args.vga)
+            .unwrap();
+        device
+            .set_frequency(Direction::Rx, 0, steps[0], ())
+            .unwrap();
+        let source = SoapySource::open(&device, &[0], sampling_rate).unwrap();
+        (Some(device), Box::new(source))
+    } else {
+        // a synthetic source cannot be retuned, every step sees the same band
+        let source = SynthSource::new(args.synth.clone(), sampling_rate, steps[0]).throttled(true);
+        (None, Box::new(source))
+    };
+
+    let mut queues = Queues::new(args.n_average);
+    if args.outname.is_some() {
+        queues.averaged.policy = Policy::Block;
+    }
+    args.queue.iter().for_each(|q| queues.apply(q));
+    let daq = run_daq(
+        source,
+        DaqConfig {
+            queues,
+            ..DaqConfig::new(args.nch, args.ntap, args.n_average)
+        },
+    )
+    .expect("failed to activate the source");
+    let rx_events = daq.rx_events.clone();
+    std::thread::spawn(move || {
+        while let Ok(e) = rx_events.recv() {
+            eprintln!("WARNING: {e}");
+        }
+    });
+
+    let rx = daq.rx_averaged[0].clone();
+    let mut isweep = 0;
+    while args.nsweeps == 0 || isweep < args.nsweeps {
+        let Some(panorama) = plan.run(&rx, &daq.stats, &daq.averaged_pool, |f| {
+            if let Some(ref device) = device {
+                device.set_frequency(Direction::Rx, 0, f, ()).unwrap();
+            }
+        }) else {
+            break;
+        };
+        let spectrum = panorama.spectrum();
+        let peak = spectrum
+            .iter()
+            .enumerate()
+            .fold((0, 0.0_f32), |a, (i, &v)| if v > a.1 { (i, v) } else { a });
+        println!(
+            "sweep {isweep} done, peak {:.2} dB at {:.6} MHz, {}",
+            peak.1.log10() * 10.0,
+            (panorama.f_start + peak.0 as f64 * panorama.df) / 1e6,
+            daq.stats.snapshot()
+        );
+        if let Some(ref outname) = args.outname {
+            let mut outfile = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(outname)
+                .unwrap();
+            write_data(&mut outfile, spectrum.as_slice().unwrap());
+        }
+        isweep += 1;
+    }
+
+    if let Err(panics) = daq.join() {
+        for p in panics {
+            eprintln!("{p}");
+        }
+    }
+}
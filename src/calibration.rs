@@ -0,0 +1,474 @@
+//! Hot/cold, noise-diode and bandpass calibration of the averaged spectra.
+//!
+//! `channelize` offers one button per reference of the method: `hot` and `cold` for
+//! `HotCold`, `on` and `off` for `NoiseDiode`, `ref` for `Bandpass`. Once all references
+//! are recorded the solution is saved and applied through `DaqHandle::calibration`,
+//! including to the out file.
+//!
+//! A solution only holds for the setup it was measured with: the frequency, sampling
+//! rate, number of channels and gains. A saved one is refused otherwise, and retuning
+//! stops calibrating. The gain is measured on the first IF, so only single-IF products
+//! are calibrated. While an out file is written the calibration stays as it was at the
+//! start, and with one applied from the start the LO stays put, so that the unit recorded
+//! in its metadata holds for the whole file.
+
+use ndarray::{Array1, s};
+use std::io::Write;
+
+type Ftype = f32;
+
+/// What the reference spectra of a calibration are.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CalMethod {
+    /// loads at two known temperatures in K, e.g. an absorber and the cold sky
+    HotCold { t_hot: f64, t_cold: f64 },
+    /// a noise diode adding `t_cal` K, switched on and off
+    NoiseDiode { t_cal: f64 },
+    /// a single reference, the output is relative to its mean power
+    Bandpass,
+}
+
+impl std::str::FromStr for CalMethod {
+    type Err = String;
+
+    /// `hotcold:<t_hot>:<t_cold>`, `diode:<t_cal>` or `bandpass`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields = s.split(':').collect::<Vec<_>>();
+        let num = |x: &str| {
+            x.parse::<f64>()
+                .map_err(|e| format!("bad temperature '{x}' in '{s}': {e}"))
+        };
+        match fields[..] {
+            ["hotcold", t_hot, t_cold] => {
+                let (t_hot, t_cold) = (num(t_hot)?, num(t_cold)?);
+                if t_hot <= t_cold {
+                    return Err(format!(
+                        "the hot load must be hotter than the cold one in '{s}'"
+                    ));
+                }
+                Ok(CalMethod::HotCold { t_hot, t_cold })
+            }
+            ["diode", t_cal] => match num(t_cal)? {
+                t_cal if t_cal > 0.0 => Ok(CalMethod::NoiseDiode { t_cal }),
+                _ => Err(format!("the diode temperature must be positive in '{s}'")),
+            },
+            ["bandpass"] => Ok(CalMethod::Bandpass),
+            _ => Err(format!(
+                "unknown calibration '{s}', can be hotcold:<t_hot>:<t_cold>, diode:<t_cal> or bandpass"
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for CalMethod {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CalMethod::HotCold { t_hot, t_cold } => write!(fmt, "hotcold:{t_hot}:{t_cold}"),
+            CalMethod::NoiseDiode { t_cal } => write!(fmt, "diode:{t_cal}"),
+            CalMethod::Bandpass => write!(fmt, "bandpass"),
+        }
+    }
+}
+
+impl CalMethod {
+    /// names of the reference spectra to record, in the order `CalSolution::solve` takes them
+    pub fn references(&self) -> &'static [&'static str] {
+        match self {
+            CalMethod::HotCold { .. } => &["hot", "cold"],
+            CalMethod::NoiseDiode { .. } => &["on", "off"],
+            CalMethod::Bandpass => &["ref"],
+        }
+    }
+
+    /// unit of the calibrated spectra
+    pub fn unit(&self) -> &'static str {
+        match self {
+            CalMethod::HotCold { .. } | CalMethod::NoiseDiode { .. } => "K",
+            CalMethod::Bandpass => "ref",
+        }
+    }
+}
+
+/// Receiver settings a calibration is only valid for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CalSetup {
+    /// centre frequency in Hz
+    pub freq: f64,
+    pub sample_rate: f64,
+    pub nch: usize,
+    /// gain settings as free text, e.g. `lna=5 mix=5 vga=5`
+    pub gains: String,
+}
+
+impl CalSetup {
+    /// Why spectra taken with `other` cannot be calibrated with this, if they can't.
+    pub fn check(&self, other: &CalSetup) -> Result<(), String> {
+        let mut diffs = vec![];
+        if (self.freq - other.freq).abs() > 1.0 {
+            diffs.push(format!("freq {} != {}", other.freq, self.freq));
+        }
+        if self.sample_rate != other.sample_rate {
+            diffs.push(format!(
+                "sample_rate {} != {}",
+                other.sample_rate, self.sample_rate
+            ));
+        }
+        if self.nch != other.nch {
+            diffs.push(format!("nch {} != {}", other.nch, self.nch));
+        }
+        if self.gains != other.gains {
+            diffs.push(format!("gains '{}' != '{}'", other.gains, self.gains));
+        }
+        if diffs.is_empty() {
+            Ok(())
+        } else {
+            Err(format!(
+                "calibration was measured with another setup: {}",
+                diffs.join(", ")
+            ))
+        }
+    }
+}
+
+/// Per channel gain, and receiver temperature for hot/cold, derived from reference spectra.
+///
+/// Calibrated spectra are the measured power over the gain, i.e. the system temperature
+/// in K for `HotCold` and `NoiseDiode`. There is a single gain, derived from the first IF,
+/// so only single-IF products can be calibrated; channels without a usable gain come out
+/// as NaN.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CalSolution {
+    pub method: CalMethod,
+    pub setup: CalSetup,
+    /// power per K, or per mean reference power for `Bandpass`
+    pub gain: Array1<Ftype>,
+    /// receiver temperature in K, only known for `HotCold`
+    pub t_rx: Option<Array1<Ftype>>,
+}
+
+impl CalSolution {
+    /// `refs` are the first IFs of the spectra named by `method.references()`, in that order.
+    pub fn solve(
+        method: CalMethod,
+        setup: CalSetup,
+        refs: &[Array1<Ftype>],
+    ) -> Result<CalSolution, String> {
+        let n = method.references().len();
+        if refs.len() != n || refs.iter().any(|r| r.len() != setup.nch) {
+            return Err(format!(
+                "{method} needs {n} references of {} channels",
+                setup.nch
+            ));
+        }
+        let usable = |g: Ftype| if g > 0.0 { g } else { Ftype::NAN };
+        let (gain, t_rx) = match method {
+            CalMethod::HotCold { t_hot, t_cold } => {
+                let gain = ((&refs[0] - &refs[1]) / (t_hot - t_cold) as Ftype).mapv(usable);
+                let t_rx = &refs[1] / &gain - t_cold as Ftype;
+                (gain, Some(t_rx))
+            }
+            CalMethod::NoiseDiode { t_cal } => {
+                let gain = ((&refs[0] - &refs[1]) / t_cal as Ftype).mapv(usable);
+                (gain, None)
+            }
+            CalMethod::Bandpass => {
+                let mean = refs[0].mean().unwrap_or(0.0);
+                ((&refs[0] / mean).mapv(usable), None)
+            }
+        };
+        Ok(CalSolution {
+            method,
+            setup,
+            gain,
+            t_rx,
+        })
+    }
+
+    /// Calibrate a single IF of `nch` channels in place.
+    pub fn apply(&self, data: &mut Array1<Ftype>) {
+        assert_eq!(
+            data.len(),
+            self.setup.nch,
+            "only single-IF spectra can be calibrated"
+        );
+        *data /= &self.gain;
+    }
+
+    pub fn unit(&self) -> &'static str {
+        self.method.unit()
+    }
+
+    /// median receiver temperature over the channels with a usable gain
+    pub fn median_t_rx(&self) -> Option<Ftype> {
+        let mut t = self
+            .t_rx
+            .as_ref()?
+            .iter()
+            .copied()
+            .filter(|x| x.is_finite())
+            .collect::<Vec<_>>();
+        if t.is_empty() {
+            return None;
+        }
+        let mid = t.len() / 2;
+        let (_, &mut m, _) = t.select_nth_unstable_by(mid, |a, b| a.total_cmp(b));
+        Some(m)
+    }
+
+    /// `key = value` lines describing the setup, then one line of `gain [t_rx]` per channel.
+    pub fn save(&self, path: &str) -> std::io::Result<()> {
+        let mut f = std::io::BufWriter::new(std::fs::File::create(path)?);
+        writeln!(f, "method = {}", self.method)?;
+        writeln!(f, "freq = {}", self.setup.freq)?;
+        writeln!(f, "sample_rate = {}", self.setup.sample_rate)?;
+        writeln!(f, "nch = {}", self.setup.nch)?;
+        writeln!(f, "gains = {}", self.setup.gains)?;
+        for (i, g) in self.gain.iter().enumerate() {
+            match self.t_rx {
+                Some(ref t) => writeln!(f, "{g:e} {}", t[i])?,
+                None => writeln!(f, "{g:e}")?,
+            }
+        }
+        f.flush()
+    }
+
+    pub fn load(path: &str) -> Result<CalSolution, String> {
+        std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {path}: {e}"))?
+            .parse()
+            .map_err(|e| format!("{path}: {e}"))
+    }
+}
+
+impl std::str::FromStr for CalSolution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut values = std::collections::HashMap::new();
+        let mut gain = vec![];
+        let mut t_rx = vec![];
+        for (i, line) in s.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some((k, v)) = line.split_once('=') {
+                values.insert(k.trim(), v.trim());
+                continue;
+            }
+            let bad = |x: &str| format!("line {}: bad number '{x}'", i + 1);
+            let mut it = line.split_whitespace();
+            let g = it.next().unwrap();
+            gain.push(g.parse::<Ftype>().map_err(|_| bad(g))?);
+            if let Some(t) = it.next() {
+                t_rx.push(t.parse::<Ftype>().map_err(|_| bad(t))?);
+            }
+        }
+        let get = |k: &str| {
+            values
+                .get(k)
+                .copied()
+                .ok_or_else(|| format!("{k} is missing"))
+        };
+        let num = |k: &str| {
+            get(k)?
+                .parse::<f64>()
+                .map_err(|_| format!("bad value for {k}"))
+        };
+        let setup = CalSetup {
+            freq: num("freq")?,
+            sample_rate: num("sample_rate")?,
+            nch: get("nch")?
+                .parse()
+                .map_err(|_| "bad value for nch".to_string())?,
+            gains: get("gains")?.to_string(),
+        };
+        if gain.len() != setup.nch || !(t_rx.is_empty() || t_rx.len() == setup.nch) {
+            return Err(format!(
+                "expected {} channels, got {}",
+                setup.nch,
+                gain.len()
+            ));
+        }
+        Ok(CalSolution {
+            method: get("method")?.parse()?,
+            setup,
+            gain: Array1::from(gain),
+            t_rx: (!t_rx.is_empty()).then(|| Array1::from(t_rx)),
+        })
+    }
+}
+
+/// Averages the first IF of successive spectra into one reference.
+#[derive(Debug, Clone)]
+pub struct Reference {
+    sum: Array1<f64>,
+    n: usize,
+}
+
+impl Reference {
+    pub fn new(nch: usize) -> Reference {
+        Reference {
+            sum: Array1::zeros(nch),
+            n: 0,
+        }
+    }
+
+    pub fn push(&mut self, x: &Array1<Ftype>) {
+        let nch = self.sum.len();
+        self.sum
+            .zip_mut_with(&x.slice(s![..nch]), |a, &b| *a += b as f64);
+        self.n += 1;
+    }
+
+    pub fn count(&self) -> usize {
+        self.n
+    }
+
+    pub fn mean(&self) -> Array1<Ftype> {
+        self.sum.mapv(|x| (x / self.n.max(1) as f64) as Ftype)
+    }
+}
+
+/// Records the references of a calibration one at a time, solving once all are there.
+#[derive(Debug, Clone)]
+pub struct Calibrator {
+    pub method: CalMethod,
+    pub setup: CalSetup,
+    /// averages per reference
+    pub n_average: usize,
+    refs: Vec<Option<Array1<Ftype>>>,
+    current: Option<(usize, Reference)>,
+}
+
+impl Calibrator {
+    pub fn new(method: CalMethod, setup: CalSetup, n_average: usize) -> Calibrator {
+        Calibrator {
+            refs: vec![None; method.references().len()],
+            current: None,
+            method,
+            setup,
+            n_average,
+        }
+    }
+
+    /// Start recording the `i`th of `method.references()`, replacing it if it was recorded.
+    pub fn start(&mut self, i: usize) {
+        self.refs[i] = None;
+        self.current = Some((i, Reference::new(self.setup.nch)));
+    }
+
+    /// the reference being recorded and the averages it has so far
+    pub fn recording(&self) -> Option<(usize, usize)> {
+        self.current.as_ref().map(|(i, r)| (*i, r.count()))
+    }
+
+    pub fn has(&self, i: usize) -> bool {
+        self.refs[i].is_some()
+    }
+
+    /// Add an uncalibrated average to the reference being recorded. Returns the solution
+    /// when this completes the last missing reference.
+    pub fn push(&mut self, x: &Array1<Ftype>) -> Option<Result<CalSolution, String>> {
+        let (i, r) = self.current.as_mut()?;
+        r.push(x);
+        if r.count() < self.n_average {
+            return None;
+        }
+        self.refs[*i] = Some(r.mean());
+        self.current = None;
+        if self.refs.iter().any(|r| r.is_none()) {
+            return None;
+        }
+        let refs = self.refs.iter().flatten().cloned().collect::<Vec<_>>();
+        Some(CalSolution::solve(self.method, self.setup.clone(), &refs))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup() -> CalSetup {
+        CalSetup {
+            freq: 1420e6,
+            sample_rate: 6e6,
+            nch: 3,
+            gains: "lna=5 mix=5 vga=5".to_string(),
+        }
+    }
+
+    #[test]
+    fn hot_cold() {
+        let gain = Array1::from(vec![2.0, 1.0, 0.5]);
+        let t_rx = 50.0;
+        let hot = &gain * (300.0 + t_rx);
+        let cold = &gain * (10.0 + t_rx);
+        let method = CalMethod::HotCold {
+            t_hot: 300.0,
+            t_cold: 10.0,
+        };
+        let c = CalSolution::solve(method, setup(), &[hot, cold.clone()]).unwrap();
+        for (a, b) in c.gain.iter().zip(&gain) {
+            assert!((a - b).abs() < 1e-5);
+        }
+        assert!((c.median_t_rx().unwrap() - t_rx).abs() < 1e-3);
+        let mut x = cold;
+        c.apply(&mut x);
+        assert!(x.iter().all(|&t| (t - 60.0).abs() < 1e-3));
+        assert_eq!(c.unit(), "K");
+    }
+
+    #[test]
+    fn diode_and_bandpass() {
+        let off = Array1::from(vec![100.0, 200.0, 50.0]);
+        let on = Array1::from(vec![110.0, 220.0, 50.0]);
+        let c = CalSolution::solve(
+            CalMethod::NoiseDiode { t_cal: 5.0 },
+            setup(),
+            &[on, off.clone()],
+        )
+        .unwrap();
+        assert_eq!(&c.gain.to_vec()[..2], [2.0, 4.0]);
+        // no difference between on and off leaves no usable gain
+        assert!(c.gain[2].is_nan());
+        assert!(c.t_rx.is_none());
+
+        let c = CalSolution::solve(CalMethod::Bandpass, setup(), &[off]).unwrap();
+        let mean = 350.0 / 3.0;
+        assert_eq!(c.gain.to_vec(), [100.0 / mean, 200.0 / mean, 50.0 / mean]);
+        assert_eq!(c.unit(), "ref");
+    }
+
+    #[test]
+    fn bad_references() {
+        let r = Array1::from(vec![1.0, 2.0, 3.0]);
+        let method = CalMethod::NoiseDiode { t_cal: 5.0 };
+        assert!(CalSolution::solve(method, setup(), std::slice::from_ref(&r)).is_err());
+        let short = Array1::from(vec![1.0, 2.0]);
+        assert!(CalSolution::solve(method, setup(), &[r, short]).is_err());
+    }
+
+    #[test]
+    fn save_and_load() {
+        let method = CalMethod::HotCold {
+            t_hot: 290.0,
+            t_cold: 10.0,
+        };
+        let hot = Array1::from(vec![600.0, 700.0, 800.0]);
+        let cold = Array1::from(vec![100.0, 120.0, 140.0]);
+        let c = CalSolution::solve(method, setup(), &[hot, cold]).unwrap();
+        let path = std::env::temp_dir().join(format!("cal_test_{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+        c.save(path).unwrap();
+        let loaded = CalSolution::load(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(loaded.method, c.method);
+        assert_eq!(loaded.setup, c.setup);
+        assert!(loaded.setup.check(&setup()).is_ok());
+        for (a, b) in loaded.gain.iter().zip(&c.gain) {
+            assert!((a - b).abs() <= 1e-6 * b.abs());
+        }
+    }
+}
@@ -0,0 +1,371 @@
+use ndarray::{Array1, Array2};
+use num::Complex;
+use rsdsp::{ospfb2::Analyzer, windowed_fir::pfb_coeff};
+use rustfft::{Fft, FftPlanner};
+use std::{f64::consts::PI, sync::Arc};
+
+type Ftype = f32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Window {
+    Rect,
+    Hann,
+    Hamming,
+    Blackman,
+    BlackmanHarris,
+    FlatTop,
+}
+
+impl std::str::FromStr for Window {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rect" => Ok(Window::Rect),
+            "hann" => Ok(Window::Hann),
+            "hamming" => Ok(Window::Hamming),
+            "blackman" => Ok(Window::Blackman),
+            "bh" | "blackman-harris" => Ok(Window::BlackmanHarris),
+            "flattop" => Ok(Window::FlatTop),
+            _ => Err(format!(
+                "unknown window '{s}', can be rect, hann, hamming, blackman, blackman-harris or flattop"
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Window {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Window::Rect => write!(fmt, "rect"),
+            Window::Hann => write!(fmt, "hann"),
+            Window::Hamming => write!(fmt, "hamming"),
+            Window::Blackman => write!(fmt, "blackman"),
+            Window::BlackmanHarris => write!(fmt, "blackman-harris"),
+            Window::FlatTop => write!(fmt, "flattop"),
+        }
+    }
+}
+
+impl Window {
+    /// `n` points, periodic for spectral analysis, symmetric for filter design
+    pub fn coefficients(&self, n: usize, periodic: bool) -> Vec<f64> {
+        let a: &[f64] = match self {
+            Window::Rect => &[1.0],
+            Window::Hann => &[0.5, 0.5],
+            Window::Hamming => &[0.54, 0.46],
+            Window::Blackman => &[0.42, 0.5, 0.08],
+            Window::BlackmanHarris => &[0.35875, 0.48829, 0.14128, 0.01168],
+            Window::FlatTop => &[
+                0.21557895,
+                0.41663158,
+                0.277263158,
+                0.083578947,
+                0.006947368,
+            ],
+        };
+        let m = if periodic { n } else { n.max(2) - 1 } as f64;
+        (0..n)
+            .map(|i| {
+                a.iter()
+                    .enumerate()
+                    .map(|(k, &ak)| {
+                        let sign = if k % 2 == 0 { 1.0 } else { -1.0 };
+                        sign * ak * (2.0 * PI * k as f64 * i as f64 / m).cos()
+                    })
+                    .sum()
+            })
+            .collect()
+    }
+}
+
+/// How `run_daq` turns samples into spectra.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    /// 2x oversampled PFB; the prototype filter passes `bandwidth` channel widths and
+    /// `window: None` keeps the default rsdsp design
+    Pfb {
+        bandwidth: Ftype,
+        window: Option<Window>,
+    },
+    /// windowed FFT, successive frames sharing `overlap` of their samples
+    Welch { window: Window, overlap: Ftype },
+}
+
+impl Default for Backend {
+    fn default() -> Self {
+        Backend::Pfb {
+            bandwidth: 1.1,
+            window: None,
+        }
+    }
+}
+
+impl std::str::FromStr for Backend {
+    type Err = String;
+
+    /// `pfb[:<bandwidth>[:<window>]]` or `welch:<window>[:<overlap>]`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut it = s.split(':');
+        let num = |x: &str| {
+            x.parse::<Ftype>()
+                .map_err(|e| format!("bad number '{x}' in '{s}': {e}"))
+        };
+        let backend = match it.next() {
+            Some("pfb") => Backend::Pfb {
+                bandwidth: it.next().map(num).transpose()?.unwrap_or(1.1),
+                window: it.next().map(|w| w.parse()).transpose()?,
+            },
+            Some("welch") => Backend::Welch {
+                window: it.next().unwrap_or("hann").parse()?,
+                overlap: it.next().map(num).transpose()?.unwrap_or(0.5),
+            },
+            _ => {
+                return Err(format!(
+                    "unknown backend '{s}', can be pfb[:<bandwidth>[:<window>]] or welch:<window>[:<overlap>]"
+                ));
+            }
+        };
+        if it.next().is_some() {
+            return Err(format!("too many fields in '{s}'"));
+        }
+        if let Backend::Welch { overlap, .. } = backend
+            && !(0.0..1.0).contains(&overlap)
+        {
+            return Err(format!("overlap must be in 0..1, got {overlap}"));
+        }
+        Ok(backend)
+    }
+}
+
+impl std::fmt::Display for Backend {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Backend::Pfb {
+                bandwidth,
+                window: None,
+            } => write!(fmt, "pfb:{bandwidth}"),
+            Backend::Pfb {
+                bandwidth,
+                window: Some(w),
+            } => write!(fmt, "pfb:{bandwidth}:{w}"),
+            Backend::Welch { window, overlap } => write!(fmt, "welch:{window}:{overlap}"),
+        }
+    }
+}
+
+/// Prototype filter of the PFB, a windowed sinc of the same length and DC gain as
+/// the default `pfb_coeff` design when a window is given.
+pub fn prototype_filter(
+    nch: usize,
+    tap_per_ch: usize,
+    bandwidth: Ftype,
+    window: Option<Window>,
+) -> Array1<Ftype> {
+    let coeff = pfb_coeff::<Ftype>(nch / 2, tap_per_ch, bandwidth);
+    let Some(window) = window else {
+        return coeff;
+    };
+    let len = coeff.len();
+    let gain = coeff.sum() as f64;
+    // one-sided cutoff in cycles per sample, channels of the prototype are 2/nch wide
+    let fc = bandwidth as f64 / nch as f64;
+    let centre = (len - 1) as f64 / 2.0;
+    let h = window
+        .coefficients(len, false)
+        .into_iter()
+        .enumerate()
+        .map(|(i, w)| {
+            let x = 2.0 * fc * (i as f64 - centre);
+            let sinc = if x == 0.0 {
+                1.0
+            } else {
+                (PI * x).sin() / (PI * x)
+            };
+            sinc * w
+        })
+        .collect::<Vec<_>>();
+    let sum = h.iter().sum::<f64>();
+    h.into_iter().map(|x| (x * gain / sum) as Ftype).collect()
+}
+
+enum Inner {
+    Pfb {
+        analyzer: Analyzer<Complex<Ftype>, Ftype>,
+        pending: usize,
+    },
+    Welch {
+        fft: Arc<dyn Fft<Ftype>>,
+        /// scaled to unit power
+        window: Vec<Ftype>,
+        buf: Vec<Complex<Ftype>>,
+        /// working space of `fft`, kept between calls
+        scratch: Vec<Complex<Ftype>>,
+    },
+}
+
+/// One input channel's worth of `Backend`, producing rows of `nch` channels in FFT order.
+pub struct Channelizer {
+    inner: Inner,
+    nch: usize,
+    hop: usize,
+    memory: usize,
+}
+
+impl Channelizer {
+    pub fn new(backend: Backend, nch: usize, tap_per_ch: usize) -> Channelizer {
+        match backend {
+            Backend::Pfb { bandwidth, window } => {
+                let coeff = prototype_filter(nch, tap_per_ch, bandwidth, window);
+                Channelizer {
+                    inner: Inner::Pfb {
+                        analyzer: Analyzer::new(nch, coeff.as_slice().unwrap()),
+                        pending: 0,
+                    },
+                    nch,
+                    hop: nch / 2,
+                    memory: nch * tap_per_ch,
+                }
+            }
+            Backend::Welch { window, overlap } => {
+                let w = window.coefficients(nch, true);
+                let norm = w.iter().map(|x| x * x).sum::<f64>().sqrt();
+                let fft = FftPlanner::new().plan_fft_forward(nch);
+                let scratch = vec![Complex::default(); fft.get_inplace_scratch_len()];
+                Channelizer {
+                    inner: Inner::Welch {
+                        fft,
+                        window: w.into_iter().map(|x| (x / norm) as Ftype).collect(),
+                        buf: vec![],
+                        scratch,
+                    },
+                    nch,
+                    hop: ((nch as Ftype * (1.0 - overlap)).round() as usize).max(1),
+                    memory: nch,
+                }
+            }
+        }
+    }
+
+    /// new input samples per output row
+    pub fn hop(&self) -> usize {
+        self.hop
+    }
+
+    /// `nch` over `hop`, 2 for the PFB
+    pub fn oversampling(&self) -> f64 {
+        self.nch as f64 / self.hop as f64
+    }
+
+    /// past input samples that affect an output row
+    pub fn memory(&self) -> usize {
+        self.memory
+    }
+
+    /// samples already fed that the next row will start from
+    pub fn buffered(&self) -> usize {
+        match self.inner {
+            Inner::Pfb { pending, .. } => pending,
+            Inner::Welch { ref buf, .. } => buf.len(),
+        }
+    }
+
+    /// Rows are time, columns are channels in FFT order.
+    pub fn analyze(&mut self, x: &[Complex<Ftype>]) -> Array2<Complex<Ftype>> {
+        let mut out = Array2::zeros((0, self.nch));
+        self.analyze_into(x, &mut out);
+        out
+    }
+
+    /// As `analyze`, refilling the allocation of `out`. The PFB backend still allocates
+    /// its own output on every call, which is then moved into `out`.
+    pub fn analyze_into(&mut self, x: &[Complex<Ftype>], out: &mut Array2<Complex<Ftype>>) {
+        let nch = self.nch;
+        let hop = self.hop;
+        match self.inner {
+            Inner::Pfb {
+                ref mut analyzer,
+                ref mut pending,
+            } => {
+                *pending = (*pending + x.len()) % hop;
+                *out = analyzer.analyze_raw_par(x);
+            }
+            Inner::Welch {
+                ref fft,
+                ref window,
+                ref mut buf,
+                ref mut scratch,
+            } => {
+                buf.extend_from_slice(x);
+                let nrows = if buf.len() >= nch {
+                    (buf.len() - nch) / hop + 1
+                } else {
+                    0
+                };
+                let (mut rows, _) = std::mem::take(out).into_raw_vec_and_offset();
+                rows.clear();
+                for i in 0..nrows {
+                    rows.extend(
+                        buf[i * hop..i * hop + nch]
+                            .iter()
+                            .zip(window)
+                            .map(|(&x, &w)| x * w),
+                    );
+                }
+                if !rows.is_empty() {
+                    fft.process_with_scratch(&mut rows, scratch);
+                }
+                buf.drain(..nrows * hop);
+                *out = Array2::from_shape_vec((nrows, nch), rows).unwrap();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::Axis;
+
+    /// Mean power per channel, in FFT order, of a unit tone `offset` channels above
+    /// the centre of channel `nch / 4`, once the filter history is full.
+    fn response(backend: &str, nch: usize, offset: f64) -> Vec<f64> {
+        let freq = (nch as f64 / 4.0 + offset) / nch as f64;
+        let x = (0..nch * 64)
+            .map(|i| Complex::from_polar(1.0, (2.0 * PI * freq * i as f64) as Ftype))
+            .collect::<Vec<_>>();
+        let mut channelizer = Channelizer::new(backend.parse().unwrap(), nch, 4);
+        let y = channelizer.analyze(&x);
+        let skip = channelizer.memory().div_ceil(channelizer.hop());
+        y.slice_axis(Axis(0), (skip..).into())
+            .map(|x| x.norm_sqr() as f64)
+            .mean_axis(Axis(0))
+            .unwrap()
+            .to_vec()
+    }
+
+    /// strongest response two or more channels away from the tone, relative to its own
+    fn leakage_db(power: &[f64], ch0: usize) -> f64 {
+        let leak = (0..power.len())
+            .filter(|&ch| ch.abs_diff(ch0) >= 2)
+            .map(|ch| power[ch])
+            .fold(0.0, f64::max);
+        10.0 * (leak / power[ch0]).log10()
+    }
+
+    #[test]
+    fn peak_and_leakage() {
+        let nch = 64;
+        let leakage = ["pfb", "welch:rect", "welch:hann"].map(|backend| {
+            let power = response(backend, nch, 0.25);
+            let peak = (0..nch).max_by(|&a, &b| power[a].total_cmp(&power[b]));
+            assert_eq!(peak, Some(nch / 4), "{backend}");
+            leakage_db(&power, nch / 4)
+        });
+        let [pfb, rect, hann] = leakage;
+        // the PFB prototype confines the tone to its own and the neighbouring channels,
+        // a windowed FFT leaks through its sidelobes, the more so without a window
+        assert!(pfb < -50.0, "{leakage:?}");
+        assert!(hann < rect && pfb < hann - 20.0, "{leakage:?}");
+    }
+}
@@ -0,0 +1,160 @@
+use ndarray::{Array1, ArrayView1};
+use num::Complex;
+
+type Ftype = f32;
+
+/// What the PFB thread makes out of one output row per input channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Products {
+    /// `|x|^2` of a single input
+    TotalPower,
+    /// `XX, YY, Re(XY*), Im(XY*)` of two inputs
+    Correlation,
+    /// `I, Q, U, V` of two linearly polarised inputs
+    Stokes,
+}
+
+impl std::str::FromStr for Products {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "total" => Ok(Products::TotalPower),
+            "corr" => Ok(Products::Correlation),
+            "stokes" => Ok(Products::Stokes),
+            _ => Err(format!(
+                "unknown products '{s}', can be total, corr or stokes"
+            )),
+        }
+    }
+}
+
+impl Products {
+    /// number of input channels needed
+    pub fn ninputs(&self) -> usize {
+        match self {
+            Products::TotalPower => 1,
+            Products::Correlation | Products::Stokes => 2,
+        }
+    }
+
+    /// number of IFs in the output, stored one after another, `nch` values each
+    pub fn nifs(&self) -> usize {
+        match self {
+            Products::TotalPower => 1,
+            Products::Correlation | Products::Stokes => 4,
+        }
+    }
+
+    /// number of `|x|^2` terms summed into each value of the first IF
+    pub fn first_if_terms(&self) -> usize {
+        match self {
+            Products::TotalPower | Products::Correlation => 1,
+            Products::Stokes => 2,
+        }
+    }
+
+    /// the leading IFs that can never be negative
+    pub fn npositive(&self) -> usize {
+        match self {
+            Products::TotalPower | Products::Stokes => 1,
+            Products::Correlation => 2,
+        }
+    }
+
+    /// Products of one PFB output row per input, reordered from FFT order
+    /// to increasing frequency.
+    pub fn compute(&self, rows: &[ArrayView1<Complex<Ftype>>]) -> Array1<Ftype> {
+        let mut out = Array1::zeros(rows[0].len() * self.nifs());
+        self.compute_into(rows, &mut out);
+        out
+    }
+
+    /// As `compute`, overwriting `out` of length `nch * nifs`.
+    pub fn compute_into(&self, rows: &[ArrayView1<Complex<Ftype>>], out: &mut Array1<Ftype>) {
+        let nch = rows[0].len();
+        let shifted = |row: &ArrayView1<Complex<Ftype>>, ch: usize| row[(ch + nch / 2) % nch];
+        for ch in 0..nch {
+            match self {
+                Products::TotalPower => {
+                    out[ch] = shifted(&rows[0], ch).norm_sqr();
+                }
+                Products::Correlation | Products::Stokes => {
+                    let x = shifted(&rows[0], ch);
+                    let y = shifted(&rows[1], ch);
+                    let xx = x.norm_sqr();
+                    let yy = y.norm_sqr();
+                    let xy = x * y.conj();
+                    let v = if *self == Products::Correlation {
+                        [xx, yy, xy.re, xy.im]
+                    } else {
+                        [xx + yy, xx - yy, 2.0 * xy.re, -2.0 * xy.im]
+                    };
+                    for (i, v) in v.into_iter().enumerate() {
+                        out[i * nch + ch] = v;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Stokes products of a tone in FFT bin 1 of 8, with `y` the `x` input times `ratio`
+    fn stokes(ratio: Complex<Ftype>) -> [[Ftype; 8]; 4] {
+        let x = Complex::new(0.6, 0.8);
+        let mut rows = [Array1::zeros(8), Array1::zeros(8)];
+        rows[0][1] = x;
+        rows[1][1] = x * ratio;
+        let out = Products::Stokes.compute(&[rows[0].view(), rows[1].view()]);
+        let mut iquv = [[0.0; 8]; 4];
+        for (i, v) in out.iter().enumerate() {
+            iquv[i / 8][i % 8] = *v;
+        }
+        iquv
+    }
+
+    fn assert_tone(iquv: [[Ftype; 8]; 4], expected: [Ftype; 4]) {
+        for (stokes, e) in iquv.iter().zip(expected) {
+            for (ch, v) in stokes.iter().enumerate() {
+                // bin 1 in FFT order is channel 5 in increasing frequency
+                let e = if ch == 5 { e } else { 0.0 };
+                assert!((v - e).abs() < 1e-6, "{iquv:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn polarized_tone() {
+        let h = std::f32::consts::FRAC_1_SQRT_2;
+        // x alone, then linear at +45 and -45 degrees, circular and elliptical
+        let cases = [
+            (Complex::new(0.0, 0.0), [1.0, 1.0, 0.0, 0.0]),
+            (Complex::new(1.0, 0.0), [2.0, 0.0, 2.0, 0.0]),
+            (Complex::new(-1.0, 0.0), [2.0, 0.0, -2.0, 0.0]),
+            (Complex::new(0.0, 1.0), [2.0, 0.0, 0.0, 2.0]),
+            (Complex::new(h, h), [2.0, 0.0, 2.0 * h, 2.0 * h]),
+        ];
+        for (ratio, expected) in cases {
+            assert_tone(stokes(ratio), expected);
+        }
+    }
+
+    #[test]
+    fn correlation_of_two_inputs() {
+        let mut rows = [Array1::zeros(4), Array1::zeros(4)];
+        rows[0][0] = Complex::new(1.0, 0.0);
+        rows[1][0] = Complex::new(0.0, 2.0);
+        let out = Products::Correlation.compute(&[rows[0].view(), rows[1].view()]);
+        // bin 0 is channel 2, the IFs are XX, YY, Re(XY*), Im(XY*)
+        assert_eq!(
+            out.to_vec(),
+            [
+                0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 4.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, -2.0, 0.0
+            ]
+        );
+    }
+}
@@ -1,136 +1,826 @@
 use chrono::Utc;
 use crossbeam::channel::{Receiver, bounded};
-use ndarray::{Array1, Axis, s};
-use num::Complex;
-use rsdsp::{ospfb2::Analyzer, windowed_fir::pfb_coeff};
-use soapysdr::RxStream;
+use ndarray::{Array1, Array2, s};
+use std::{
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::JoinHandle,
+};
+
+use crate::{
+    accumulate::{AccumMode, Accumulator, SkConfig, SpectralKurtosis},
+    adc::AdcMonitor,
+    calibration::CalSolution,
+    channelizer::{Backend, Channelizer},
+    correlator::Products,
+    dedisperse::{Candidate, Downsampler, Search, SearchConfig, SearchSetup},
+    iq_correct::{CorrectionSwitch, IqCorrection, IqCorrector},
+    meta::SpectrumMeta,
+    pool::Pool,
+    queue::Queues,
+    radiometer::{PowerSample, Radiometer, RadiometerConfig},
+    recorder::RawRecorder,
+    source::{RawBuffers, RawSamples, SampleSource, SourceError},
+    stats::DaqStats,
+    zoom::{Zoom, ZoomConfig, ZoomSpectrum},
+};
 
 type Ftype = f32;
 
-pub fn run_daq(
-    mut sdr_stream: RxStream<Complex<Ftype>>,
-    nch: usize,
-    tap_per_ch: usize,
-    n_average: usize,
-) -> Receiver<Array1<f32>> {
-    match sdr_stream.activate(None) {
-        Ok(()) => {
-            println!("activated")
+pub struct DaqConfig {
+    pub nch: usize,
+    pub tap_per_ch: usize,
+    pub n_average: usize,
+    pub backend: Backend,
+    pub products: Products,
+    /// one averaged product per entry, each delivered to its own receiver
+    pub outputs: Vec<AccumMode>,
+    pub recorder: Option<RawRecorder>,
+    /// flag channels by spectral kurtosis, `None` to disable
+    pub sk: Option<SkConfig>,
+    pub adc: AdcMonitor,
+    /// consecutive failed reads after which the source is reopened, `None` to never try
+    pub recover_after: Option<usize>,
+    pub queues: Queues,
+    pub correction: IqCorrection,
+    /// applied to the averaged spectra, see `DaqHandle::calibration`; `run_daq` panics if
+    /// it does not fit the channels, products or sample rate
+    pub calibration: Option<CalSolution>,
+    /// band-integrated power from the un-averaged spectra, `None` to disable
+    pub radiometer: Option<RadiometerConfig>,
+    /// second-stage channelization of some coarse channels, see `DaqHandle::zoom`
+    pub zoom: Option<ZoomConfig>,
+    /// dedispersion and single-pulse search of the first IF, `None` to disable; `run_daq`
+    /// panics if it fails `SearchConfig::check`
+    pub search: Option<SearchConfig>,
+}
+
+impl DaqConfig {
+    pub fn new(nch: usize, tap_per_ch: usize, n_average: usize) -> DaqConfig {
+        DaqConfig {
+            nch,
+            tap_per_ch,
+            n_average,
+            backend: Backend::default(),
+            products: Products::TotalPower,
+            outputs: vec![AccumMode::Mean],
+            recorder: None,
+            sk: None,
+            adc: AdcMonitor::default(),
+            recover_after: Some(10),
+            queues: Queues::new(n_average),
+            correction: IqCorrection::default(),
+            calibration: None,
+            radiometer: None,
+            zoom: None,
+            search: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Spectrum {
+    /// `nifs` blocks of `nch` channels in increasing frequency
+    pub data: Array1<Ftype>,
+    pub nifs: usize,
+    /// input samples were lost while the PFB was filling, e.g. by an overflow
+    pub tainted: bool,
+    /// index of the first input sample, counted per channel since the start of acquisition
+    pub sample_index: u64,
+    /// input samples per channel that went into this spectrum
+    pub nsamples: u64,
+    /// time of the first input sample, in ns since the unix epoch
+    pub time_ns: i64,
+    /// per channel RFI flags of an average, if spectral kurtosis is enabled
+    pub flags: Option<Array1<bool>>,
+    /// unit of a calibrated average, `None` for uncalibrated power
+    pub unit: Option<&'static str>,
+}
+
+impl Spectrum {
+    pub fn mjd(&self) -> f64 {
+        self.time_ns as f64 / 86400e9 + 40587.0
+    }
+}
+
+/// One read from the source, as passed from the reader to the PFB thread.
+struct RawChunk {
+    data: RawSamples,
+    tainted: bool,
+    sample_index: u64,
+    time_ns: i64,
+}
+
+/// Things that happened to the source, in the order they happened.
+#[derive(Debug, Clone)]
+pub enum DaqEvent {
+    Timeout,
+    Overflow,
+    StreamError(String),
+    Reopened,
+    ReopenFailed(String),
+    EndOfStream,
+    /// the raw recorder hit an I/O error and stopped, acquisition goes on
+    RecorderFailed(String),
+    DeactivateFailed(String),
+    /// the clip fraction went above `AdcMonitor::warn_fraction`
+    Clipping(f32),
+    /// a calibration set through `DaqHandle::calibration` did not fit and was dropped
+    CalibrationDropped(String),
+}
+
+impl std::fmt::Display for DaqEvent {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DaqEvent::Timeout => write!(fmt, "read timeout"),
+            DaqEvent::Overflow => write!(fmt, "overflow, samples lost"),
+            DaqEvent::StreamError(e) => write!(fmt, "stream error: {e}"),
+            DaqEvent::Reopened => write!(fmt, "source reopened"),
+            DaqEvent::ReopenFailed(e) => write!(fmt, "failed to reopen source: {e}"),
+            DaqEvent::EndOfStream => write!(fmt, "end of stream"),
+            DaqEvent::RecorderFailed(e) => write!(fmt, "raw recorder stopped: {e}"),
+            DaqEvent::DeactivateFailed(e) => write!(fmt, "failed to deactivate source: {e}"),
+            DaqEvent::Clipping(x) => write!(
+                fmt,
+                "ADC clipping, {:.3}% of samples at full scale, reduce the gain",
+                x * 100.0
+            ),
+            DaqEvent::CalibrationDropped(e) => write!(fmt, "calibration dropped: {e}"),
         }
-        Err(e) => {
-            println!("{e:?}");
+    }
+}
+
+#[derive(Debug)]
+pub struct ThreadPanic {
+    pub thread: &'static str,
+    pub message: String,
+}
+
+impl std::fmt::Display for ThreadPanic {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(fmt, "{} thread panicked: {}", self.thread, self.message)
+    }
+}
+
+/// Running acquisition started by `run_daq`.
+///
+/// Dropping the handle leaves the threads running, call `join` to shut them down.
+pub struct DaqHandle {
+    /// one receiver per entry of `DaqConfig::outputs`
+    pub rx_averaged: Vec<Receiver<Spectrum>>,
+    /// averages put here once consumed are refilled instead of allocating new ones
+    pub averaged_pool: Pool<Spectrum>,
+    pub rx_events: Receiver<DaqEvent>,
+    /// power samples, if `DaqConfig::radiometer` was given
+    pub rx_power: Option<Receiver<PowerSample>>,
+    /// zoomed spectra while `zoom` is set
+    pub rx_zoom: Receiver<ZoomSpectrum>,
+    /// single-pulse candidates, if `DaqConfig::search` was given
+    pub rx_candidates: Option<Receiver<Candidate>>,
+    pub stats: Arc<DaqStats>,
+    /// layout and timing of the averaged spectra, `tstart` is left unset
+    pub meta: SpectrumMeta,
+    /// turn the DC and IQ imbalance correction on and off
+    pub correction: Arc<CorrectionSwitch>,
+    /// calibration applied to the averages, replaceable while running, single-IF products
+    /// only; one that does not fit is dropped with a `DaqEvent::CalibrationDropped`
+    pub calibration: Arc<Mutex<Option<CalSolution>>>,
+    /// coarse channels to zoom into, replaceable while running
+    pub zoom: Arc<Mutex<Option<ZoomConfig>>>,
+    running: Arc<AtomicBool>,
+    threads: Vec<(&'static str, JoinHandle<()>)>,
+}
+
+impl DaqHandle {
+    /// Ask the reader thread to stop; the downstream threads finish what is queued,
+    /// emit the partial average and exit.
+    pub fn stop(&self) {
+        self.running.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Relaxed)
+    }
+
+    /// Stop the pipeline and wait for all its threads.
+    pub fn join(self) -> Result<(), Vec<ThreadPanic>> {
+        self.stop();
+        // threads blocked on a full queue only return once its receiver is gone, the
+        // fields are spelled out so that no receiver added later can be missed here
+        let DaqHandle {
+            rx_averaged,
+            rx_events,
+            rx_power,
+            rx_zoom,
+            rx_candidates,
+            averaged_pool: _,
+            correction: _,
+            calibration: _,
+            zoom: _,
+            stats: _,
+            meta: _,
+            running: _,
+            threads,
+        } = self;
+        drop((rx_averaged, rx_events, rx_power, rx_zoom, rx_candidates));
+        let panics = threads
+            .into_iter()
+            .filter_map(|(thread, th)| {
+                th.join().err().map(|e| ThreadPanic {
+                    thread,
+                    message: e
+                        .downcast_ref::<&str>()
+                        .map(|s| s.to_string())
+                        .or_else(|| e.downcast_ref::<String>().cloned())
+                        .unwrap_or_default(),
+                })
+            })
+            .collect::<Vec<_>>();
+        if panics.is_empty() {
+            Ok(())
+        } else {
+            Err(panics)
         }
     }
+}
+
+/// Why `c` cannot be applied to the averages of `nch` channels of `products` taken at
+/// `sample_rate`, if it can't.
+fn check_calibration(
+    c: &CalSolution,
+    nch: usize,
+    products: Products,
+    sample_rate: f64,
+) -> Result<(), String> {
+    if c.gain.len() != nch {
+        Err(format!(
+            "calibration is for {} channels, not {nch}",
+            c.gain.len()
+        ))
+    } else if products.nifs() != 1 {
+        Err(format!(
+            "{products:?} cannot be calibrated, only single-IF products can"
+        ))
+    } else if c.setup.sample_rate != sample_rate {
+        Err(format!(
+            "calibration is for a sample rate of {}, not {sample_rate}",
+            c.setup.sample_rate
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Activate `source` and start the pipeline on it.
+///
+/// Fails without starting any thread if the source cannot be activated, errors after
+/// that are reported through `DaqHandle::rx_events`. Panics on an invalid config.
+pub fn run_daq<S: SampleSource + 'static>(
+    mut source: S,
+    config: DaqConfig,
+) -> Result<DaqHandle, SourceError> {
+    let DaqConfig {
+        nch,
+        tap_per_ch,
+        n_average,
+        backend,
+        products,
+        outputs,
+        recorder,
+        sk,
+        mut adc,
+        recover_after,
+        queues,
+        correction,
+        calibration,
+        radiometer,
+        zoom,
+        search,
+    } = config;
+
+    if let Err(e) = queues.check() {
+        panic!("{e}");
+    }
+    let ninputs = products.ninputs();
+    assert!(
+        source.channels() >= ninputs,
+        "{products:?} needs {ninputs} input channels, the source has {}",
+        source.channels()
+    );
+    if let Some(Err(e)) = calibration
+        .as_ref()
+        .map(|c| check_calibration(c, nch, products, source.sample_rate()))
+    {
+        panic!("{e}");
+    }
+    if let Some(Err(e)) = search.as_ref().map(|s| s.check(source.sample_rate())) {
+        panic!("{e}");
+    }
+
+    source.activate()?;
+
+    let mut pfbs = (0..ninputs)
+        .map(|_| Channelizer::new(backend, nch, tap_per_ch))
+        .collect::<Vec<_>>();
+    // consecutive spectra of an oversampled backend overlap, SK only takes one per frame
+    let sk_stride = nch.div_ceil(pfbs[0].hop());
+    let meta = SpectrumMeta {
+        nch,
+        nifs: products.nifs(),
+        sample_rate: source.sample_rate(),
+        osr: pfbs[0].oversampling(),
+        n_average,
+        tstart: None,
+        unit: calibration.as_ref().map(|c| c.unit().to_string()),
+        velocity: None,
+    };
+
+    let (tx_raw, rx_raw) = queues.raw.channel::<RawChunk>();
+    // buffers travel with the queues and come back once consumed, a few more are in flight
+    let raw_pool = Pool::<RawSamples>::new(queues.raw.capacity + 4);
+    let record_pool = Pool::<RawSamples>::new(queues.record.capacity + 4);
+    let spectrum_pool = Pool::<Array1<Ftype>>::new(queues.spectrum.capacity + 4);
+    let (tx_events, rx_events) = bounded(64);
+
+    let mut threads = vec![];
 
-    let coeff = pfb_coeff::<Ftype>(nch / 2, tap_per_ch, 1.1 as Ftype);
-    let mut pfb = Analyzer::<Complex<Ftype>, Ftype>::new(nch, coeff.as_slice().unwrap());
+    let stats = Arc::new(DaqStats::default());
+    let running = Arc::new(AtomicBool::new(true));
 
-    let (tx_raw, rx_raw) = bounded(64);
+    let mut tx_record = recorder.map(|r| {
+        let stats1 = stats.clone();
+        let tx_events = tx_events.clone();
+        let (tx, th) = r.spawn(
+            queues.record,
+            record_pool.clone(),
+            source.sample_rate(),
+            source.channels(),
+            stats.clone(),
+            move |e| {
+                stats1.fail_recorder();
+                let _ = tx_events.try_send(DaqEvent::RecorderFailed(e.to_string()));
+            },
+        );
+        threads.push(("recorder", th));
+        tx
+    });
 
-    let mut num = 0;
     let mut cnt = 0;
+    let sample_rate = source.sample_rate();
 
-    std::thread::spawn(move || {
+    let stats1 = stats.clone();
+    let running1 = running.clone();
+    let raw_pool1 = raw_pool.clone();
+    let tx_events1 = tx_events.clone();
+    let th_reader = std::thread::spawn(move || {
+        let stats = stats1;
         let t0 = Utc::now().timestamp_millis(); // e.g. `2014-11-28T12:45:59.324310806Z`
+        let mut tainted = false;
+        let mut failures = 0;
+        // sources without a clock of their own are timed by counting samples from here
+        let anchor_ns = Utc::now().timestamp_nanos_opt().unwrap();
+        let mut sample_index = 0_u64;
+        let report = |e: DaqEvent| {
+            let _ = tx_events1.try_send(e);
+        };
+        while running1.load(Ordering::Relaxed) {
+            let mut buf = raw_pool1.take().unwrap_or_else(|| {
+                stats.allocate_buffer();
+                source.new_samples()
+            });
+            buf.resize(source.channels(), source.mtu());
+            let len = match source.read_samples(&mut buf) {
+                Ok(len) => {
+                    failures = 0;
+                    len
+                }
+                Err(SourceError::Eof) => {
+                    report(DaqEvent::EndOfStream);
+                    break;
+                }
+                Err(e) => {
+                    tainted = true;
+                    failures += 1;
+                    match e {
+                        SourceError::Timeout => {
+                            stats.timeout();
+                            report(DaqEvent::Timeout);
+                        }
+                        SourceError::Overflow => {
+                            stats.overflow();
+                            report(DaqEvent::Overflow);
+                        }
+                        e => {
+                            stats.stream_error();
+                            report(DaqEvent::StreamError(e.to_string()));
+                        }
+                    }
+                    if recover_after.is_some_and(|n| failures >= n) {
+                        failures = 0;
+                        match source.reopen() {
+                            Ok(()) => {
+                                stats.reopen();
+                                report(DaqEvent::Reopened);
+                            }
+                            Err(e) => report(DaqEvent::ReopenFailed(e.to_string())),
+                        }
+                    }
+                    raw_pool1.put(buf);
+                    continue;
+                }
+            };
+            if len == 0 {
+                raw_pool1.put(buf);
+                continue;
+            }
+            buf.resize(source.channels(), len);
+
+            if let Some(ref tx) = tx_record {
+                let mut copy = record_pool.take().unwrap_or_else(|| {
+                    stats.allocate_buffer();
+                    buf.empty()
+                });
+                copy.clone_from(&buf);
+                match tx.send(copy) {
+                    Ok(Some(x)) => {
+                        stats.drop_record();
+                        record_pool.put(x);
+                    }
+                    Ok(None) => {}
+                    // the recorder has reported why it stopped, never the case with
+                    // a drop-oldest queue
+                    Err(_) => tx_record = None,
+                }
+            }
+
+            let time_ns = source
+                .timestamp_ns()
+                .unwrap_or_else(|| anchor_ns + (sample_index as f64 * 1e9 / sample_rate) as i64);
+            let chunk = RawChunk {
+                data: buf,
+                tainted,
+                sample_index,
+                time_ns,
+            };
+            sample_index += len as u64;
+            // the channelizer notices dropped chunks by the gap in `sample_index`
+            match tx_raw.send(chunk) {
+                Ok(Some(x)) => {
+                    stats.drop_raw();
+                    raw_pool1.put(x.data);
+                }
+                Ok(None) => {}
+                Err(_) => break,
+            }
+            tainted = false;
+
+            //pfb.analyze_par(&buf[..len]);
+            cnt += 1;
+            let num = stats.add_samples(len as u64);
+            stats.set_raw_queue(tx_raw.len());
+            if cnt % 100 == 0 {
+                let t1 = Utc::now().timestamp_millis();
+                let dt_sec = (t1 - t0) as f64 / 1000.0;
+                stats.set_sample_rate(num as f64 / dt_sec);
+                stats.publish();
+            }
+        }
+        stats.publish();
+        if let Err(e) = source.deactivate() {
+            report(DaqEvent::DeactivateFailed(e.to_string()));
+        }
+    });
+    threads.push(("reader", th_reader));
+
+    let (tx_spectrum, rx_spectrum) = queues.spectrum.channel::<Spectrum>();
+
+    let switch = Arc::new(CorrectionSwitch::new(correction.dc, correction.imbalance));
+    let mut correctors = (0..ninputs)
+        .map(|_| IqCorrector::new(correction.time_constant, sample_rate))
+        .collect::<Vec<_>>();
+
+    let (tx_zoom, rx_zoom) = queues.zoom.channel::<ZoomSpectrum>();
+    let zoom = Arc::new(Mutex::new(zoom));
+
+    let stats1 = stats.clone();
+    let spectrum_pool1 = spectrum_pool.clone();
+    let switch1 = switch.clone();
+    let tx_events1 = tx_events.clone();
+    let zoom1 = zoom.clone();
+    let th_pfb = std::thread::spawn(move || {
+        let stats = stats1;
+        let len = nch * products.nifs();
+        // samples still to be pushed through the PFB before its history is clean again
+        let mut taint_left = 0;
+        // each output row takes `hop` new samples, the PFB keeps the remainder for the next chunk
+        let hop = pfbs[0].hop();
+        let mut next_index = 0;
         let mut sigma = None;
-        loop {
-            //let mut buf = vec![Complex::<Ftype>::default(); stream.mtu().unwrap()];
-            let mut buf = Vec::with_capacity(sdr_stream.mtu().unwrap());
-            buf.resize(sdr_stream.mtu().unwrap(), Complex::default());
-            let len = sdr_stream
-                .read(&mut [&mut buf], 1_000_000)
-                .expect("read failed");
-            buf.resize(len, Complex::default());
+        let mut clipping = false;
+        let mut cnt = 0;
+        // integer samples are converted here rather than in the reader
+        let mut scratch = RawBuffers::new();
+        let mut corrected = RawBuffers::new();
+        let mut zoomer: Option<Zoom> = None;
+        let mut channelized = (0..ninputs)
+            .map(|_| Array2::zeros((0, nch)))
+            .collect::<Vec<_>>();
+        while let Ok(chunk) = rx_raw.recv() {
+            if chunk.tainted || chunk.sample_index != next_index {
+                taint_left = pfbs[0].memory();
+            }
+            next_index = chunk.sample_index + chunk.data.len() as u64;
+            let buf = chunk.data.to_float(&mut scratch);
+
             let sigma1 = buf
                 .iter()
+                .flatten()
                 .map(|x| x.norm_sqr())
                 .reduce(|a, b| a + b)
                 .unwrap()
-                / buf.len() as f32;
+                / (buf[0].len() * buf.len()) as f32;
             let k = 0.999;
             if let Some(ref mut x) = sigma {
                 *x = *x * k + (1.0 - k) * sigma1;
             } else {
                 sigma = Some(sigma1);
             }
+            stats.set_power(sigma.unwrap_or(1e-30) as f64);
 
-            if !tx_raw.is_full() {
-                if tx_raw.send(buf).is_err() {
-                    break;
+            let levels = adc.update(buf);
+            stats.set_adc(levels.peak as f64, levels.clip_fraction as f64);
+            if levels.clip_fraction > adc.warn_fraction {
+                stats.clip();
+                if !clipping {
+                    let _ = tx_events1.try_send(DaqEvent::Clipping(levels.clip_fraction));
                 }
-            } else {
-                eprintln!("WARNING: daq queue full, data losting");
             }
-
-            //pfb.analyze_par(&buf[..len]);
+            clipping = levels.clip_fraction > adc.warn_fraction;
             cnt += 1;
-            num += len as i64;
-            //println!("{}", num);
-            //println!("{}", len);
             if cnt % 100 == 0 {
-                let t1 = Utc::now().timestamp_millis();
-                let dt_sec = (t1 - t0) as f64 / 1000.0;
-                let sps = num as f64 / dt_sec;
-                println!(
-                    "{} Msps Q={} pwr={} dB",
-                    sps / 1e6,
-                    tx_raw.len(),
-                    sigma.unwrap_or(1e-30).log10() * 10.0
-                );
+                stats.set_adc_histogram(adc.histogram());
+                adc.clear_histogram();
             }
-        }
-    });
 
-    let (tx_spectrum, rx_spectrum) = bounded(n_average * 2);
-
-    std::thread::spawn(move || {
-        loop {
-            let data: Vec<Complex<f32>> = rx_raw.recv().unwrap();
-            pfb.analyze_raw_par(&data).axis_iter(Axis(0)).for_each(|x| {
-                let x1 = Array1::from_iter(
-                    x.slice(s![nch / 2..nch])
-                        .iter()
-                        .chain(x.slice(s![0..nch / 2]))
-                        .map(|x1| x1.norm_sqr()),
-                );
-                if !tx_spectrum.is_full() {
-                    if tx_spectrum.send(x1).is_err() {
-                        return;
+            correctors
+                .iter_mut()
+                .zip(buf)
+                .for_each(|(c, x)| c.update(x));
+            let c = &correctors[0];
+            stats.set_iq(
+                c.dc().re,
+                c.dc().im,
+                c.gain().log10() * 20.0,
+                c.phase().to_degrees(),
+            );
+            let (dc, imbalance) = (switch1.dc(), switch1.imbalance());
+            let buf = if dc || imbalance {
+                corrected.clone_from(buf);
+                correctors
+                    .iter()
+                    .zip(corrected.iter_mut())
+                    .for_each(|(c, x)| c.apply(x, dc, imbalance));
+                &corrected
+            } else {
+                buf
+            };
+
+            let pending = pfbs[0].buffered() as u64;
+            pfbs.iter_mut()
+                .zip(buf)
+                .zip(&mut channelized)
+                .for_each(|((pfb, x), out)| pfb.analyze_into(x, out));
+            raw_pool.put(chunk.data);
+
+            let wanted = zoom1.lock().unwrap().clone();
+            if wanted.as_ref() != zoomer.as_ref().map(|z| z.config()) {
+                zoomer = wanted.map(|c| Zoom::new(c, nch, hop, sample_rate));
+            }
+            if let Some(ref mut z) = zoomer {
+                let first = chunk.sample_index.saturating_sub(pending);
+                let time_ns = chunk.time_ns - (pending as f64 * 1e9 / sample_rate) as i64;
+                for x in z.push(&channelized[0], first, time_ns, taint_left > 0) {
+                    if let Ok(Some(_)) = tx_zoom.send(x) {
+                        stats.drop_zoom();
                     }
-                } else {
-                    println!("WARNING: spectrum queue is full, skipping");
+                }
+            }
+
+            (0..channelized[0].nrows()).for_each(|i| {
+                let mut data = spectrum_pool1.take().unwrap_or_else(|| {
+                    stats.allocate_buffer();
+                    Array1::zeros(len)
+                });
+                // the rows are gathered on the stack, all products take one or two inputs
+                match channelized.as_slice() {
+                    [a] => products.compute_into(&[a.row(i)], &mut data),
+                    [a, b] => products.compute_into(&[a.row(i), b.row(i)], &mut data),
+                    _ => unreachable!(),
+                }
+                let sample_index = (chunk.sample_index + (i * hop) as u64).saturating_sub(pending);
+                let offset = sample_index as f64 - chunk.sample_index as f64;
+                let x1 = Spectrum {
+                    data,
+                    nifs: products.nifs(),
+                    tainted: taint_left > 0,
+                    sample_index,
+                    nsamples: hop as u64,
+                    time_ns: chunk.time_ns + (offset * 1e9 / sample_rate) as i64,
+                    flags: None,
+                    unit: None,
+                };
+                taint_left = taint_left.saturating_sub(hop);
+                if x1.tainted {
+                    stats.taint_spectrum();
+                }
+                if let Ok(Some(x)) = tx_spectrum.send(x1) {
+                    stats.drop_spectrum();
+                    spectrum_pool1.put(x.data);
                 }
             });
+            stats.set_spectrum_queue(tx_spectrum.len());
         }
     });
+    threads.push(("pfb", th_pfb));
 
-    let (tx_averaged, rx_averaged) = bounded(16);
+    let (tx_averaged, rx_averaged): (Vec<_>, Vec<_>) = outputs
+        .iter()
+        .map(|_| queues.averaged.channel::<Spectrum>())
+        .unzip();
+    let mut accumulators = outputs
+        .iter()
+        .map(|&mode| Accumulator::new(mode, nch * products.nifs(), n_average))
+        .collect::<Vec<_>>();
+    let mut kurtosis = sk.map(|_| SpectralKurtosis::new(nch, products.first_if_terms(), sk_stride));
 
-    std::thread::spawn(move || {
+    // averages come back once their consumers hand them to `DaqHandle::averaged_pool`
+    let averaged_pool = Pool::<Spectrum>::new(queues.averaged.capacity * outputs.len() + 4);
+    let averaged_pool1 = averaged_pool.clone();
+    let calibration = Arc::new(Mutex::new(calibration));
+
+    let mut radiometer = radiometer.map(|r| Radiometer::new(&r, nch, meta.spectrum_interval()));
+    let (tx_power, rx_power) = match radiometer {
+        Some(_) => {
+            let (tx, rx) = queues.power.channel::<PowerSample>();
+            (Some(tx), Some(rx))
+        }
+        None => (None, None),
+    };
+
+    let mut downsampler = search.as_ref().map(|s| Downsampler::new(s.downsample, nch));
+    let (tx_search, rx_candidates) = match search {
+        Some(config) => {
+            let (tx_search, rx_search) = queues.search.channel::<Spectrum>();
+            let (tx_candidates, rx_candidates) = queues.candidates.channel::<Candidate>();
+            let setup = SearchSetup::new(&config, &meta);
+            let stats1 = stats.clone();
+            let th_search = std::thread::spawn(move || {
+                let mut search = Search::new(config, setup);
+                while let Ok(x) = rx_search.recv() {
+                    for c in search.push(x) {
+                        if let Ok(Some(_)) = tx_candidates.send(c) {
+                            stats1.drop_search();
+                        }
+                    }
+                }
+            });
+            threads.push(("search", th_search));
+            (Some(tx_search), Some(rx_candidates))
+        }
+        None => (None, None),
+    };
+
+    let stats1 = stats.clone();
+    let calibration1 = calibration.clone();
+    let th_average = std::thread::spawn(move || {
+        let stats = stats1;
+        let averaged_pool = averaged_pool1;
+        let mut flags = None;
         //let mut filtered_result=Array1::<Ftype>::zeros(NCH);
         //let mut outfile=File::create("./a.bin").unwrap();
 
         //let udp = UdpSocket::bind(format!("127.0.0.1:{}", args.tx_port)).unwrap();
-        loop {
-            let mut temp = Array1::<Ftype>::zeros(nch);
-            for _i in 0..n_average {
-                temp = temp + rx_spectrum.recv().unwrap();
+        let mut finished = false;
+        while !finished {
+            let mut n = 0;
+            while n < n_average {
+                match rx_spectrum.recv() {
+                    Ok(x) => {
+                        accumulators.iter_mut().for_each(|a| a.push(&x));
+                        if let Some(ref mut k) = kurtosis {
+                            k.push(&x);
+                        }
+                        if let Some(p) = radiometer.as_mut().and_then(|r| r.push(&x))
+                            && let Some(Ok(Some(_))) = tx_power.as_ref().map(|tx| tx.send(p))
+                        {
+                            stats.drop_power();
+                        }
+                        if let (Some(d), Some(tx)) = (downsampler.as_mut(), tx_search.as_ref())
+                            && let Some(row) = d.push(&x)
+                            && let Ok(Some(_)) = tx.send(row)
+                        {
+                            stats.drop_search();
+                        }
+                        spectrum_pool.put(x.data);
+                    }
+                    Err(_) => {
+                        finished = true;
+                        break;
+                    }
+                }
+                n += 1;
             }
-            temp /= n_average as Ftype;
 
             //filtered_result=filtered_result*K+temp*(1 as Ftype-K);
             //send_data(&udp, temp.as_slice().unwrap(), &addr);
             //write_data(&mut outfile, filtered_result.as_slice().unwrap());
 
-            if !tx_averaged.is_full() && temp.iter().all(|&x| x > 0_f32) {
-                if tx_averaged.send(temp).is_err() {
-                    break;
+            if let (Some(k), Some(config)) = (kurtosis.as_mut(), sk.as_ref()) {
+                k.take_flags_into(config, &mut flags);
+            }
+            if let Some(ref f) = flags {
+                stats.flag_channels(f.iter().filter(|&&x| x).count() as u64);
+            }
+            if let Some(ref mut r) = radiometer {
+                r.set_flags(flags.as_ref());
+            }
+            if let Some(ref mut d) = downsampler {
+                d.set_flags(flags.as_ref());
+            }
+            for (acc, tx) in accumulators.iter_mut().zip(&tx_averaged) {
+                if acc.count() == 0 {
+                    continue;
+                }
+                let mut temp = averaged_pool.take().unwrap_or_else(|| {
+                    stats.allocate_buffer();
+                    Spectrum::default()
+                });
+                acc.take_into(&mut temp);
+                match (flags.as_ref(), &mut temp.flags) {
+                    (Some(f), Some(x)) => x.assign(f),
+                    (f, x) => *x = f.cloned(),
+                }
+                if let Some(ref config) = sk {
+                    config.action.apply(&mut temp);
+                }
+                // flagged channels may legitimately be zeroed
+                let npositive = products.npositive() * nch;
+                if !temp
+                    .data
+                    .slice(s![..npositive])
+                    .iter()
+                    .enumerate()
+                    .all(|(i, &x)| x > 0_f32 || flags.as_ref().is_some_and(|f| f[i % nch]))
+                {
+                    stats.reject_average();
+                    averaged_pool.put(temp);
+                    continue;
+                }
+                {
+                    let mut calibration = calibration1.lock().unwrap();
+                    if let Some(Err(e)) = calibration
+                        .as_ref()
+                        .map(|c| check_calibration(c, nch, products, sample_rate))
+                    {
+                        let _ = tx_events.try_send(DaqEvent::CalibrationDropped(e));
+                        *calibration = None;
+                    }
+                    if let Some(ref c) = *calibration {
+                        c.apply(&mut temp.data);
+                        temp.unit = Some(c.unit());
+                    }
+                }
+                if let Ok(Some(x)) = tx.send(temp) {
+                    stats.drop_average();
+                    averaged_pool.put(x);
                 }
-            } else {
-                println!("average data queue full, skipping");
             }
+            stats.set_averaged_queue(tx_averaged.iter().map(|tx| tx.len()).max().unwrap_or(0));
+        }
+        if let Some(p) = radiometer.as_mut().and_then(|r| r.take())
+            && let Some(tx) = tx_power
+        {
+            let _ = tx.send(p);
         }
+        stats.publish();
     });
+    threads.push(("average", th_average));
 
-    rx_averaged
+    Ok(DaqHandle {
+        rx_averaged,
+        rx_events,
+        averaged_pool,
+        rx_power,
+        rx_zoom,
+        rx_candidates,
+        stats,
+        meta,
+        correction: switch,
+        calibration,
+        zoom,
+        running,
+        threads,
+    })
 }
@@ -0,0 +1,618 @@
+use binrw::BinWrite;
+use ndarray::{Array1, Array2, s};
+use rayon::prelude::*;
+use std::{collections::VecDeque, io::Write};
+
+use crate::{daq::Spectrum, meta::SpectrumMeta, sigproc_io::Header, synth::KDM, utils::write_data};
+
+type Ftype = f32;
+
+/// Single-pulse search run by `run_daq` on the un-averaged first IF.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchConfig {
+    /// centre frequency in Hz
+    pub freq: f64,
+    /// channelizer outputs summed into one search sample
+    pub downsample: usize,
+    /// lowest DM trial in pc cm^-3
+    pub dm_min: f64,
+    /// highest DM trial in pc cm^-3, `SearchSetup::max_dm` if `None`
+    pub dm_max: Option<f64>,
+    /// spacing of the DM trials in effective time resolutions
+    pub tolerance: f64,
+    /// widest boxcar in samples, the widths are the powers of two up to it
+    pub max_width: usize,
+    /// S/N above which candidates are reported
+    pub threshold: Ftype,
+    /// longest dispersion sweep across the band searched, in s, bounding the derived DM range
+    pub max_delay: f64,
+    /// time constant of the per channel baseline, in s
+    pub baseline: f64,
+    /// samples searched at once
+    pub block: usize,
+    /// samples of the snapshot before and after the sweep of a candidate
+    pub margin: usize,
+}
+
+impl SearchConfig {
+    pub fn new(freq: f64) -> SearchConfig {
+        SearchConfig {
+            freq,
+            downsample: 16,
+            dm_min: 0.0,
+            dm_max: None,
+            tolerance: 1.25,
+            max_width: 64,
+            threshold: 7.0,
+            max_delay: 1.0,
+            baseline: 1.0,
+            block: 1024,
+            margin: 64,
+        }
+    }
+
+    /// `Err` with the reason if no DM trials can be laid out for a band of `sample_rate`
+    /// around `freq`.
+    pub fn check(&self, sample_rate: f64) -> Result<(), String> {
+        let bottom = self.freq - sample_rate / 2.0;
+        if bottom <= 0.0 {
+            return Err(format!(
+                "the band must lie above 0 Hz, not start at {bottom} Hz"
+            ));
+        }
+        if self.tolerance <= 0.0 {
+            return Err(format!(
+                "tolerance must be positive, not {}",
+                self.tolerance
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Frequencies and time resolution the search sees.
+///
+/// Channels are numbered as in the spectra, channel `c` centred at
+/// `freq - sample_rate / 2 + c * sample_rate / nch`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchSetup {
+    /// centre frequency in Hz
+    pub freq: f64,
+    pub sample_rate: f64,
+    pub nch: usize,
+    /// time per search sample in s
+    pub tsamp: f64,
+}
+
+impl SearchSetup {
+    pub fn new(config: &SearchConfig, meta: &SpectrumMeta) -> SearchSetup {
+        SearchSetup {
+            freq: config.freq,
+            sample_rate: meta.sample_rate,
+            nch: meta.nch,
+            tsamp: meta.spectrum_interval() * config.downsample as f64,
+        }
+    }
+
+    /// channel width in MHz
+    pub fn channel_width(&self) -> f64 {
+        self.sample_rate / self.nch as f64 / 1e6
+    }
+
+    /// centre of channel `c` in MHz
+    pub fn channel_freq(&self, c: usize) -> f64 {
+        (self.freq - self.sample_rate / 2.0) / 1e6 + c as f64 * self.channel_width()
+    }
+
+    /// delay of channel `c` behind the top channel at `dm`, in s
+    pub fn delay(&self, c: usize, dm: f64) -> f64 {
+        KDM * dm * (self.channel_freq(c).powi(-2) - self.channel_freq(self.nch - 1).powi(-2))
+    }
+
+    /// dispersion smearing within the lowest channel at `dm`, in s
+    pub fn smearing(&self, dm: f64) -> f64 {
+        2.0 * KDM * dm * self.channel_width() / self.channel_freq(0).powi(3)
+    }
+
+    /// Largest DM whose smearing stays within `max_width` samples and whose sweep across
+    /// the band within `max_delay` s.
+    pub fn max_dm(&self, max_width: usize, max_delay: f64) -> f64 {
+        let by_smearing = max_width as f64 * self.tsamp / self.smearing(1.0);
+        let by_sweep = max_delay / self.delay(0, 1.0);
+        by_smearing.min(by_sweep)
+    }
+
+    /// Trial DMs from `lo` to `hi`, consecutive ones shifting the bottom of the band by
+    /// `tolerance` times the effective time resolution, the sample time and the smearing
+    /// added in quadrature.
+    pub fn dm_grid(&self, lo: f64, hi: f64, tolerance: f64) -> Vec<f64> {
+        // anything else would never get to `hi`
+        assert!(
+            self.channel_freq(0) > 0.0 && tolerance > 0.0,
+            "DM trials need a band above 0 Hz and a positive tolerance"
+        );
+        let mut dms = vec![lo];
+        let mut dm = lo;
+        while dm < hi {
+            let width = (self.tsamp.powi(2) + self.smearing(dm).powi(2)).sqrt();
+            dm += tolerance * width / self.delay(0, 1.0);
+            dms.push(dm.min(hi));
+        }
+        dms
+    }
+
+    /// The DM trials of `config`.
+    pub fn dms(&self, config: &SearchConfig) -> Vec<f64> {
+        let hi = config
+            .dm_max
+            .unwrap_or_else(|| self.max_dm(config.max_width, config.max_delay));
+        self.dm_grid(config.dm_min, hi, config.tolerance)
+    }
+}
+
+/// Sums the first IF of successive spectra into search samples.
+///
+/// Like the `Radiometer`, samples cover fixed stretches of input samples counted from
+/// the first spectrum; one missing some dropped spectra is scaled up to make up for
+/// them, and one missing all of them is left out for the `Search` to fill in.
+#[derive(Debug, Clone)]
+pub struct Downsampler {
+    n: usize,
+    nch: usize,
+    sum: Array1<Ftype>,
+    count: usize,
+    flags: Option<Array1<bool>>,
+    /// input sample index where the current search sample begins
+    start: Option<u64>,
+    /// input samples per spectrum
+    nsamples: u64,
+    time_ns: i64,
+    tainted: bool,
+}
+
+impl Downsampler {
+    pub fn new(n: usize, nch: usize) -> Downsampler {
+        Downsampler {
+            n,
+            nch,
+            sum: Array1::zeros(nch),
+            count: 0,
+            flags: None,
+            start: None,
+            nsamples: 0,
+            time_ns: 0,
+            tainted: false,
+        }
+    }
+
+    /// Channels flagged from now on, passed on with the samples.
+    pub fn set_flags(&mut self, flags: Option<&Array1<bool>>) {
+        match (flags, &mut self.flags) {
+            (Some(f), Some(x)) => x.assign(f),
+            (f, x) => *x = f.cloned(),
+        }
+    }
+
+    /// Add the first IF of `x`, returning the search sample this completes.
+    pub fn push(&mut self, x: &Spectrum) -> Option<Spectrum> {
+        let span = self.n as u64 * x.nsamples;
+        let start = *self.start.get_or_insert(x.sample_index);
+        let past = x.sample_index.saturating_sub(start) / span;
+        let done = if past > 0 {
+            self.start = Some(start + past * span);
+            self.take(start)
+        } else {
+            None
+        };
+        if self.count == 0 {
+            self.time_ns = x.time_ns;
+            self.tainted = false;
+        }
+        self.sum += &x.data.slice(s![..self.nch]);
+        self.nsamples = x.nsamples;
+        self.tainted |= x.tainted;
+        self.count += 1;
+        if done.is_some() || self.count < self.n {
+            return done;
+        }
+        let start = self.start.unwrap();
+        self.start = Some(start + span);
+        self.take(start)
+    }
+
+    fn take(&mut self, sample_index: u64) -> Option<Spectrum> {
+        if self.count == 0 {
+            return None;
+        }
+        let scale = self.n as Ftype / std::mem::take(&mut self.count) as Ftype;
+        let data = self.sum.mapv(|x| x * scale);
+        self.sum.fill(0.0);
+        Some(Spectrum {
+            data,
+            nifs: 1,
+            tainted: self.tainted,
+            sample_index,
+            nsamples: self.n as u64 * self.nsamples,
+            time_ns: self.time_ns,
+            flags: self.flags.clone(),
+            unit: None,
+        })
+    }
+}
+
+/// A dispersed pulse found by `Search`.
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    /// arrival at the top of the band, in ns since the unix epoch
+    pub time_ns: i64,
+    pub dm: f64,
+    /// boxcar width in search samples
+    pub width: usize,
+    pub snr: Ftype,
+    /// search samples around the candidate, from `margin` before its arrival at the top of
+    /// the band to `margin` after it left the bottom, channels in increasing frequency
+    pub snapshot: Array2<Ftype>,
+    /// time of the first snapshot row, in ns since the unix epoch
+    pub snapshot_time_ns: i64,
+}
+
+impl Candidate {
+    pub fn mjd(&self) -> f64 {
+        self.time_ns as f64 / 86400e9 + 40587.0
+    }
+}
+
+struct Row {
+    /// baseline subtracted and divided by the noise, 0 where flagged
+    norm: Array1<Ftype>,
+    raw: Array1<Ftype>,
+    time_ns: i64,
+}
+
+/// Brute-force incoherent dedispersion over a DM grid followed by boxcar filters.
+///
+/// Every channel is normalised by a running mean and standard deviation first, so that
+/// the bandpass drops out and each DM trial is a sum of unit-variance series. The S/N of
+/// each block is measured against the median and MAD of its own trial.
+/// A pulse also shows at neighbouring DMs and widths, so detections whose sweeps across
+/// the band overlap are merged into the brightest, looking one sweep into the blocks
+/// either side so that a pulse near the edge of a block is reported only once. Search
+/// samples missing from the input are filled with zeros.
+pub struct Search {
+    config: SearchConfig,
+    setup: SearchSetup,
+    dms: Vec<f64>,
+    /// per DM, per channel, in samples
+    delays: Vec<Vec<usize>>,
+    widths: Vec<usize>,
+    /// longest sweep of a detection, in samples
+    reach: usize,
+    /// rows kept before the block searched
+    history: usize,
+    /// rows needed after it
+    lookahead: usize,
+    mean: Array1<Ftype>,
+    var: Array1<Ftype>,
+    nseen: usize,
+    /// input sample index of the next search sample
+    next: Option<u64>,
+    rows: VecDeque<Row>,
+}
+
+impl Search {
+    pub fn new(config: SearchConfig, setup: SearchSetup) -> Search {
+        let dms = setup.dms(&config);
+        let delays = dms
+            .iter()
+            .map(|&dm| {
+                (0..setup.nch)
+                    .map(|c| (setup.delay(c, dm) / setup.tsamp).round() as usize)
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let max_delay = delays.iter().map(|d| d[0]).max().unwrap_or(0);
+        let widths = std::iter::successors(Some(1), |w| Some(w * 2))
+            .take_while(|&w| w <= config.max_width.max(1))
+            .collect::<Vec<_>>();
+        let reach = max_delay + widths.last().unwrap();
+        Search {
+            reach,
+            history: reach.max(config.margin),
+            lookahead: reach + reach.max(config.margin),
+            mean: Array1::zeros(setup.nch),
+            var: Array1::zeros(setup.nch),
+            nseen: 0,
+            next: None,
+            rows: VecDeque::new(),
+            dms,
+            delays,
+            widths,
+            config,
+            setup,
+        }
+    }
+
+    pub fn dms(&self) -> &[f64] {
+        &self.dms
+    }
+
+    /// samples between a pulse reaching the top of the band and it being reported, at most
+    pub fn latency(&self) -> usize {
+        self.config.block + self.lookahead
+    }
+
+    /// Add a downsampled spectrum, returning the candidates of the blocks it completes.
+    pub fn push(&mut self, x: Spectrum) -> Vec<Candidate> {
+        let mut candidates = vec![];
+        let missing = self
+            .next
+            .map_or(0, |next| x.sample_index.saturating_sub(next) / x.nsamples);
+        self.next = Some(x.sample_index + x.nsamples);
+        if missing as usize > self.history + self.config.block + self.lookahead {
+            // too long a gap to bridge, start over
+            self.rows.clear();
+        } else if !self.rows.is_empty() {
+            let tsamp_ns = (self.setup.tsamp * 1e9) as i64;
+            for k in (1..=missing as i64).rev() {
+                let zeros = Array1::zeros(self.setup.nch);
+                candidates.extend(self.add(Row {
+                    norm: zeros.clone(),
+                    raw: zeros,
+                    time_ns: x.time_ns - k * tsamp_ns,
+                }));
+            }
+        }
+
+        let alpha = (1.0 / (self.nseen + 1) as Ftype)
+            .max(1.0 - (-self.setup.tsamp / self.config.baseline).exp() as Ftype);
+        // nothing is searched until the baseline has settled
+        let settled = self.nseen as f64 * self.setup.tsamp >= self.config.baseline;
+        let mut norm = Array1::zeros(self.setup.nch);
+        for c in 0..self.setup.nch {
+            if x.flags.as_ref().is_some_and(|f| f[c]) {
+                continue;
+            }
+            let v = x.data[c];
+            let (m, var) = (self.mean[c], self.var[c]);
+            if var > 0.0 && !x.tainted {
+                norm[c] = (v - m) / var.sqrt();
+            }
+            let d = v - m;
+            self.mean[c] = m + alpha * d;
+            self.var[c] = (1.0 - alpha) * (var + alpha * d * d);
+        }
+        self.nseen += 1;
+        if !settled {
+            return candidates;
+        }
+        candidates.extend(self.add(Row {
+            norm,
+            raw: x.data,
+            time_ns: x.time_ns,
+        }));
+        candidates
+    }
+
+    fn add(&mut self, row: Row) -> Vec<Candidate> {
+        self.rows.push_back(row);
+        if self.rows.len() < self.history + self.config.block + self.lookahead {
+            return vec![];
+        }
+        let candidates = self.search_block();
+        self.rows.drain(..self.config.block);
+        candidates
+    }
+
+    /// Search the block after the first `history` rows.
+    fn search_block(&self) -> Vec<Candidate> {
+        let (reach, block) = (self.reach, self.config.block);
+        let max_width = *self.widths.last().unwrap();
+        // the series start `reach` before the block and end `reach` after it
+        let start = self.history - reach;
+        let span = reach + block + reach;
+        let len = span + max_width;
+        let rows = &self.rows;
+        let threshold = self.config.threshold;
+        // channel-major, so that each channel of a trial is a contiguous slice
+        let mut norm = Array2::<Ftype>::zeros((self.setup.nch, rows.len()));
+        for (mut col, r) in norm.columns_mut().into_iter().zip(rows) {
+            col.assign(&r.norm);
+        }
+        // (sample, DM index, width, S/N), the peak of each run above the threshold
+        let mut hits = self
+            .delays
+            .par_iter()
+            .enumerate()
+            .flat_map_iter(|(i, delays)| {
+                let mut series = vec![0.0 as Ftype; len];
+                for (c, &d) in delays.iter().enumerate() {
+                    let x = &norm.row(c).to_slice().unwrap()[start + d..start + d + len];
+                    series.iter_mut().zip(x).for_each(|(a, &b)| *a += b);
+                }
+                // median and MAD, so that the pulse doesn't raise its own noise level
+                let mut sorted = series[reach..reach + block].to_vec();
+                let mid = block / 2;
+                let median = *sorted.select_nth_unstable_by(mid, Ftype::total_cmp).1;
+                sorted.iter_mut().for_each(|x| *x = (*x - median).abs());
+                let mad = *sorted.select_nth_unstable_by(mid, Ftype::total_cmp).1;
+                let (median, var) = (median as f64, (1.4826 * mad as f64).powi(2));
+                let mut prefix = vec![0.0; len + 1];
+                for t in 0..len {
+                    prefix[t + 1] = prefix[t] + series[t] as f64;
+                }
+                let mut hits = vec![];
+                if var <= 0.0 {
+                    return hits;
+                }
+                for &w in &self.widths {
+                    let mut best: Option<(usize, usize, usize, Ftype)> = None;
+                    for t in 0..span {
+                        let snr = ((prefix[t + w] - prefix[t] - w as f64 * median)
+                            / (var * w as f64).sqrt()) as Ftype;
+                        if snr >= threshold {
+                            if best.is_none_or(|b| snr > b.3) {
+                                best = Some((t, i, w, snr));
+                            }
+                        } else if let Some(b) = best.take() {
+                            hits.push(b);
+                        }
+                    }
+                    hits.extend(best);
+                }
+                hits
+            })
+            .collect::<Vec<_>>();
+
+        hits.sort_by(|a, b| b.3.total_cmp(&a.3));
+        // samples from the arrival at the top of the band to the end at the bottom
+        let sweep = |&(t, i, w, _): &(usize, usize, usize, Ftype)| t..t + self.delays[i][0] + w;
+        let mut kept: Vec<(usize, usize, usize, Ftype)> = vec![];
+        for h in hits {
+            let a = sweep(&h);
+            if kept
+                .iter()
+                .map(sweep)
+                .all(|b| a.end <= b.start || b.end <= a.start)
+            {
+                kept.push(h);
+            }
+        }
+        // those of the neighbouring blocks are reported with them
+        kept.into_iter()
+            .filter(|&(t, ..)| (reach..reach + block).contains(&t))
+            .map(|(t, i, w, snr)| {
+                let first = start + t - self.config.margin;
+                let last = (start + t + self.delays[i][0] + w + self.config.margin).min(rows.len());
+                let mut snapshot = Array2::zeros((last - first, self.setup.nch));
+                for (mut row, r) in snapshot.outer_iter_mut().zip(rows.range(first..last)) {
+                    row.assign(&r.raw);
+                }
+                Candidate {
+                    time_ns: rows[start + t].time_ns,
+                    dm: self.dms[i],
+                    width: w,
+                    snr,
+                    snapshot,
+                    snapshot_time_ns: rows[first].time_ns,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Candidates as text, one line of `<mjd> <dm> <width in ms> <snr> <snapshot>` each, and
+/// each snapshot as a sigproc filterbank `<prefix>_<n>.fil`.
+pub struct CandidateWriter {
+    out: std::fs::File,
+    prefix: String,
+    setup: SearchSetup,
+    n: usize,
+}
+
+impl CandidateWriter {
+    /// Snapshots go next to `path`, named after it without the extension.
+    pub fn new(path: &str, setup: SearchSetup) -> std::io::Result<CandidateWriter> {
+        let mut out = std::fs::File::create(path)?;
+        writeln!(out, "# mjd dm width_ms snr snapshot")?;
+        let prefix = std::path::Path::new(path)
+            .with_extension("")
+            .to_string_lossy()
+            .into_owned();
+        Ok(CandidateWriter {
+            out,
+            prefix,
+            setup,
+            n: 0,
+        })
+    }
+
+    pub fn write(&mut self, c: &Candidate) -> std::io::Result<()> {
+        let name = format!("{}_{:05}.fil", self.prefix, self.n);
+        self.n += 1;
+        let setup = &self.setup;
+        let foff = -setup.channel_width();
+        let mut header = Header::new(
+            setup.channel_freq(setup.nch - 1),
+            setup.nch,
+            foff,
+            c.snapshot_time_ns as f64 / 86400e9 + 40587.0,
+            setup.tsamp,
+        );
+        header.set_nifs(1);
+        let mut f = std::io::BufWriter::new(std::fs::File::create(&name)?);
+        header
+            .write_le(&mut f)
+            .map_err(|e| std::io::Error::other(e.to_string()))?;
+        // sigproc starts from the top of the band
+        let mut row = vec![0.0 as Ftype; setup.nch];
+        for x in c.snapshot.outer_iter() {
+            row.iter_mut()
+                .zip(x.iter().rev())
+                .for_each(|(a, &b)| *a = b);
+            write_data(&mut f, &row);
+        }
+        f.flush()?;
+        writeln!(
+            self.out,
+            "{:.10} {:.2} {:.3} {:.1} {name}",
+            c.mjd(),
+            c.dm,
+            c.width as f64 * setup.tsamp * 1e3,
+            c.snr
+        )?;
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup() -> SearchSetup {
+        SearchSetup {
+            freq: 400e6,
+            sample_rate: 10e6,
+            nch: 256,
+            tsamp: 1e-3,
+        }
+    }
+
+    #[test]
+    fn delays() {
+        let s = setup();
+        assert_eq!(s.channel_freq(0), 395.0);
+        assert_eq!(s.channel_freq(128), 400.0);
+        assert_eq!(s.delay(s.nch - 1, 100.0), 0.0);
+        // 4.149 ms GHz^2 per unit DM between the bottom and the top channel
+        let (bottom, top) = (s.channel_freq(0) / 1e3, s.channel_freq(s.nch - 1) / 1e3);
+        let expected = 4.148808e-3 * 100.0 * (bottom.powi(-2) - top.powi(-2));
+        assert!((s.delay(0, 100.0) - expected).abs() < 1e-9);
+        assert!((0..s.nch - 1).all(|c| s.delay(c, 100.0) > s.delay(c + 1, 100.0)));
+        assert!((s.delay(0, 200.0) - 2.0 * s.delay(0, 100.0)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn dm_trials() {
+        let s = setup();
+        let max_dm = s.max_dm(64, 1.0);
+        assert!(s.delay(0, max_dm) <= 1.0 + 1e-9);
+        assert!(s.smearing(max_dm) <= 64.0 * s.tsamp + 1e-9);
+        let dms = s.dm_grid(0.0, 100.0, 1.25);
+        assert_eq!((dms[0], dms[dms.len() - 1]), (0.0, 100.0));
+        assert!(dms.windows(2).all(|w| w[1] > w[0]));
+        // neighbouring trials shift the bottom of the band by at most 1.25 samples
+        assert!(dms.windows(2).all(|w| s.delay(0, w[1] - w[0])
+            <= 1.25 * (s.tsamp.powi(2) + s.smearing(w[1]).powi(2)).sqrt()));
+    }
+
+    #[test]
+    fn check() {
+        assert!(SearchConfig::new(400e6).check(10e6).is_ok());
+        assert!(SearchConfig::new(5e6).check(10e6).is_err());
+        let config = SearchConfig {
+            tolerance: 0.0,
+            ..SearchConfig::new(400e6)
+        };
+        assert!(config.check(10e6).is_err());
+    }
+}
@@ -0,0 +1,193 @@
+use num::Complex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+type Ftype = f32;
+
+/// DC and IQ imbalance correction of the raw samples in `run_daq`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IqCorrection {
+    pub dc: bool,
+    pub imbalance: bool,
+    /// smoothing of the estimates, in s
+    pub time_constant: f64,
+}
+
+impl Default for IqCorrection {
+    fn default() -> Self {
+        IqCorrection {
+            dc: false,
+            imbalance: false,
+            time_constant: 1.0,
+        }
+    }
+}
+
+/// Which of the corrections are applied, changeable while `run_daq` is running.
+/// The estimates are kept up to date either way.
+#[derive(Debug, Default)]
+pub struct CorrectionSwitch {
+    dc: AtomicBool,
+    imbalance: AtomicBool,
+}
+
+impl CorrectionSwitch {
+    pub fn new(dc: bool, imbalance: bool) -> CorrectionSwitch {
+        CorrectionSwitch {
+            dc: AtomicBool::new(dc),
+            imbalance: AtomicBool::new(imbalance),
+        }
+    }
+
+    pub fn dc(&self) -> bool {
+        self.dc.load(Ordering::Relaxed)
+    }
+
+    pub fn imbalance(&self) -> bool {
+        self.imbalance.load(Ordering::Relaxed)
+    }
+
+    pub fn set_dc(&self, on: bool) {
+        self.dc.store(on, Ordering::Relaxed);
+    }
+
+    pub fn set_imbalance(&self, on: bool) {
+        self.imbalance.store(on, Ordering::Relaxed);
+    }
+}
+
+/// Running estimates of the DC offset and of the gain and phase mismatch of `Q`
+/// relative to `I` for one input channel.
+///
+/// With `Q` carrying `g` times the amplitude of `I` and leading by `phi` in excess of
+/// 90 degrees, `E[IQ] / sqrt(E[I^2] E[Q^2]) = sin(phi)`, which is undone by
+/// `Q' = (Q / g - I sin(phi)) / cos(phi)`.
+#[derive(Debug, Clone)]
+pub struct IqCorrector {
+    time_constant: f64,
+    sample_rate: f64,
+    dc: Complex<f64>,
+    ii: f64,
+    qq: f64,
+    iq: f64,
+    primed: bool,
+}
+
+impl IqCorrector {
+    pub fn new(time_constant: f64, sample_rate: f64) -> IqCorrector {
+        IqCorrector {
+            time_constant,
+            sample_rate,
+            dc: Complex::default(),
+            ii: 1.0,
+            qq: 1.0,
+            iq: 0.0,
+            primed: false,
+        }
+    }
+
+    pub fn update(&mut self, x: &[Complex<Ftype>]) {
+        if x.is_empty() {
+            return;
+        }
+        let n = x.len() as f64;
+        let mean = x.iter().fold(Complex::<f64>::default(), |a, x| {
+            a + Complex::new(x.re as f64, x.im as f64)
+        }) / n;
+        let k = if self.primed {
+            (-n / (self.time_constant * self.sample_rate)).exp()
+        } else {
+            0.0
+        };
+        self.dc = self.dc * k + mean * (1.0 - k);
+        let (mut ii, mut qq, mut iq) = (0.0, 0.0, 0.0);
+        for x in x {
+            let i = x.re as f64 - self.dc.re;
+            let q = x.im as f64 - self.dc.im;
+            ii += i * i;
+            qq += q * q;
+            iq += i * q;
+        }
+        self.ii = self.ii * k + ii / n * (1.0 - k);
+        self.qq = self.qq * k + qq / n * (1.0 - k);
+        self.iq = self.iq * k + iq / n * (1.0 - k);
+        self.primed = true;
+    }
+
+    pub fn dc(&self) -> Complex<f64> {
+        self.dc
+    }
+
+    /// amplitude of `Q` over that of `I`
+    pub fn gain(&self) -> f64 {
+        if self.ii > 0.0 {
+            (self.qq / self.ii).sqrt()
+        } else {
+            1.0
+        }
+    }
+
+    /// in radians
+    pub fn phase(&self) -> f64 {
+        let norm = (self.ii * self.qq).sqrt();
+        if norm > 0.0 {
+            (self.iq / norm).clamp(-1.0, 1.0).asin()
+        } else {
+            0.0
+        }
+    }
+
+    pub fn apply(&self, x: &mut [Complex<Ftype>], dc: bool, imbalance: bool) {
+        let offset = if dc {
+            Complex::new(self.dc.re as Ftype, self.dc.im as Ftype)
+        } else {
+            Complex::default()
+        };
+        if !imbalance {
+            x.iter_mut().for_each(|x| *x -= offset);
+            return;
+        }
+        let g = self.gain() as Ftype;
+        let (s, c) = self.phase().sin_cos();
+        let (s, c) = (s as Ftype, c as Ftype);
+        for x in x.iter_mut() {
+            let i = x.re - offset.re;
+            let q = x.im - offset.im;
+            *x = Complex::new(i, (q / g - i * s) / c);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dc_and_imbalance() {
+        let (n, g, phi) = (4096, 1.2_f64, 5_f64.to_radians());
+        let dc = Complex::new(0.1, -0.05);
+        let theta = |t: usize| 2.0 * std::f64::consts::PI * 37.0 * t as f64 / n as f64;
+        let x = (0..n)
+            .map(|t| {
+                let th = theta(t);
+                Complex::new(th.cos() as Ftype, (g * (th + phi).sin()) as Ftype) + dc
+            })
+            .collect::<Vec<_>>();
+
+        let mut c = IqCorrector::new(1.0, 1e6);
+        c.update(&x);
+        assert!((c.dc() - Complex::new(0.1, -0.05)).norm() < 1e-6);
+        assert!((c.gain() - g).abs() < 1e-4);
+        assert!((c.phase() - phi).abs() < 1e-4);
+
+        let mut y = x.clone();
+        c.apply(&mut y, true, false);
+        assert!(y.iter().zip(&x).all(|(y, x)| (x - y - dc).norm() < 1e-6));
+
+        let mut y = x.clone();
+        c.apply(&mut y, true, true);
+        for (t, y) in y.iter().enumerate() {
+            let (s, c) = theta(t).sin_cos();
+            assert!((y - Complex::new(c as Ftype, s as Ftype)).norm() < 1e-4);
+        }
+    }
+}
@@ -0,0 +1,305 @@
+use num::Complex;
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use crate::source::{RawSamples, SampleSource, SourceError, Throttle};
+
+type Ftype = f32;
+
+/// Interleaved IQ sample formats, named as in SoapySDR.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleFormat {
+    CF32,
+    CS16,
+    CS8,
+    CU8,
+}
+
+impl std::str::FromStr for SampleFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "cf32" => Ok(SampleFormat::CF32),
+            "cs16" => Ok(SampleFormat::CS16),
+            "cs8" => Ok(SampleFormat::CS8),
+            "cu8" => Ok(SampleFormat::CU8),
+            _ => Err(format!(
+                "unknown sample format '{s}', can be cf32, cs16, cs8 or cu8"
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for SampleFormat {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            SampleFormat::CF32 => "cf32",
+            SampleFormat::CS16 => "cs16",
+            SampleFormat::CS8 => "cs8",
+            SampleFormat::CU8 => "cu8",
+        };
+        name.fmt(fmt)
+    }
+}
+
+impl SampleFormat {
+    /// bytes per complex sample
+    pub fn sample_size(&self) -> usize {
+        match self {
+            SampleFormat::CF32 => 8,
+            SampleFormat::CS16 => 4,
+            SampleFormat::CS8 | SampleFormat::CU8 => 2,
+        }
+    }
+
+    /// Convert whole samples in `bytes` into `out`, integer formats are scaled to [-1, 1).
+    pub fn decode(&self, bytes: &[u8], out: &mut [Complex<Ftype>]) {
+        let ss = self.sample_size();
+        for (x, b) in out.iter_mut().zip(bytes.chunks_exact(ss)) {
+            *x = match self {
+                SampleFormat::CF32 => Complex::new(
+                    f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+                    f32::from_le_bytes([b[4], b[5], b[6], b[7]]),
+                ),
+                SampleFormat::CS16 => Complex::new(
+                    i16::from_le_bytes([b[0], b[1]]) as Ftype / 32768.0,
+                    i16::from_le_bytes([b[2], b[3]]) as Ftype / 32768.0,
+                ),
+                SampleFormat::CS8 => {
+                    Complex::new(b[0] as i8 as Ftype / 128.0, b[1] as i8 as Ftype / 128.0)
+                }
+                SampleFormat::CU8 => Complex::new(
+                    (b[0] as Ftype - 127.5) / 128.0,
+                    (b[1] as Ftype - 127.5) / 128.0,
+                ),
+            };
+        }
+    }
+
+    /// Inverse of `decode`, integer formats saturate outside [-1, 1).
+    pub fn encode(&self, data: &[Complex<Ftype>], bytes: &mut Vec<u8>) {
+        bytes.clear();
+        bytes.reserve(data.len() * self.sample_size());
+        for x in data {
+            match self {
+                SampleFormat::CF32 => {
+                    bytes.extend_from_slice(&x.re.to_le_bytes());
+                    bytes.extend_from_slice(&x.im.to_le_bytes());
+                }
+                SampleFormat::CS16 => {
+                    bytes.extend_from_slice(&((x.re * 32768.0) as i16).to_le_bytes());
+                    bytes.extend_from_slice(&((x.im * 32768.0) as i16).to_le_bytes());
+                }
+                SampleFormat::CS8 => {
+                    bytes.push((x.re * 128.0) as i8 as u8);
+                    bytes.push((x.im * 128.0) as i8 as u8);
+                }
+                SampleFormat::CU8 => {
+                    bytes.push((x.re * 128.0 + 127.5) as u8);
+                    bytes.push((x.im * 128.0 + 127.5) as u8);
+                }
+            }
+        }
+    }
+}
+
+/// Replays an interleaved IQ recording as if it came from a receiver.
+pub struct IqFileSource {
+    reader: BufReader<File>,
+    format: SampleFormat,
+    sample_rate: f64,
+    mtu: usize,
+    /// first and one-past-last sample to be replayed
+    start: u64,
+    stop: u64,
+    pos: u64,
+    looping: bool,
+    throttle: Option<Throttle>,
+    bytes: Vec<u8>,
+}
+
+impl IqFileSource {
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        format: SampleFormat,
+        sample_rate: f64,
+    ) -> Result<IqFileSource, std::io::Error> {
+        let file = File::open(path)?;
+        let nsamples = file.metadata()?.len() / format.sample_size() as u64;
+        Ok(IqFileSource {
+            reader: BufReader::new(file),
+            format,
+            sample_rate,
+            mtu: 65536,
+            start: 0,
+            stop: nsamples,
+            pos: 0,
+            looping: false,
+            throttle: None,
+            bytes: vec![],
+        })
+    }
+
+    /// Restrict replay to `duration` seconds starting `offset` seconds into the file.
+    pub fn with_segment(
+        mut self,
+        offset: f64,
+        duration: Option<f64>,
+    ) -> Result<IqFileSource, std::io::Error> {
+        let nsamples = self.stop;
+        self.start = ((offset * self.sample_rate) as u64).min(nsamples);
+        self.stop = match duration {
+            Some(d) => (self.start + (d * self.sample_rate) as u64).min(nsamples),
+            None => nsamples,
+        };
+        self.rewind()?;
+        Ok(self)
+    }
+
+    pub fn looping(mut self, looping: bool) -> Self {
+        self.looping = looping;
+        self
+    }
+
+    /// Deliver samples no faster than `sample_rate`, like a real receiver.
+    pub fn throttled(mut self, realtime: bool) -> Self {
+        self.throttle = realtime.then(|| Throttle::new(self.sample_rate));
+        self
+    }
+
+    /// Read up to `max_len` samples into `bytes`, undecoded.
+    fn fetch(&mut self, max_len: usize) -> Result<usize, SourceError> {
+        if self.pos >= self.stop {
+            if !self.looping || self.stop == self.start {
+                return Err(SourceError::Eof);
+            }
+            self.rewind()?;
+        }
+
+        let len = max_len.min(self.mtu).min((self.stop - self.pos) as usize);
+        self.bytes.resize(len * self.format.sample_size(), 0);
+        self.reader.read_exact(&mut self.bytes)?;
+        self.pos += len as u64;
+
+        if let Some(ref mut t) = self.throttle {
+            t.wait(len);
+        }
+        Ok(len)
+    }
+
+    fn rewind(&mut self) -> Result<(), std::io::Error> {
+        self.reader.seek(SeekFrom::Start(
+            self.start * self.format.sample_size() as u64,
+        ))?;
+        self.pos = self.start;
+        Ok(())
+    }
+}
+
+impl SampleSource for IqFileSource {
+    fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    fn activate(&mut self) -> Result<(), SourceError> {
+        if let Some(ref mut t) = self.throttle {
+            t.reset();
+        }
+        Ok(())
+    }
+
+    fn deactivate(&mut self) -> Result<(), SourceError> {
+        Ok(())
+    }
+
+    fn read(&mut self, buffers: &mut [&mut [Complex<Ftype>]]) -> Result<usize, SourceError> {
+        let len = self.fetch(buffers[0].len())?;
+        self.format.decode(&self.bytes, &mut buffers[0][..len]);
+        Ok(len)
+    }
+
+    fn new_samples(&self) -> RawSamples {
+        match self.format {
+            SampleFormat::CS16 => RawSamples::CS16 {
+                data: vec![],
+                scale: 1.0 / 32768.0,
+            },
+            SampleFormat::CS8 => RawSamples::CS8 {
+                data: vec![],
+                scale: 1.0 / 128.0,
+            },
+            SampleFormat::CF32 | SampleFormat::CU8 => RawSamples::CF32(vec![]),
+        }
+    }
+
+    fn read_samples(&mut self, buffers: &mut RawSamples) -> Result<usize, SourceError> {
+        match buffers {
+            RawSamples::CF32(data) => self.read(&mut [data[0].as_mut_slice()]),
+            RawSamples::CS16 { data, .. } => {
+                let len = self.fetch(data[0].len())?;
+                for (x, b) in data[0].iter_mut().zip(self.bytes.chunks_exact(4)) {
+                    *x = Complex::new(
+                        i16::from_le_bytes([b[0], b[1]]),
+                        i16::from_le_bytes([b[2], b[3]]),
+                    );
+                }
+                Ok(len)
+            }
+            RawSamples::CS8 { data, .. } => {
+                let len = self.fetch(data[0].len())?;
+                for (x, b) in data[0].iter_mut().zip(self.bytes.chunks_exact(2)) {
+                    *x = Complex::new(b[0] as i8, b[1] as i8);
+                }
+                Ok(len)
+            }
+        }
+    }
+
+    fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let data = [-1.0, -0.5, -0.01, 0.0, 0.25, 0.99]
+            .map(|x| Complex::new(x, -x * 0.5))
+            .to_vec();
+        for (format, lsb) in [
+            (SampleFormat::CF32, 0.0),
+            (SampleFormat::CS16, 1.0 / 32768.0),
+            (SampleFormat::CS8, 1.0 / 128.0),
+            (SampleFormat::CU8, 1.0 / 128.0),
+        ] {
+            let mut bytes = vec![];
+            format.encode(&data, &mut bytes);
+            assert_eq!(bytes.len(), data.len() * format.sample_size());
+            let mut out = vec![Complex::default(); data.len()];
+            format.decode(&bytes, &mut out);
+            for (x, y) in data.iter().zip(&out) {
+                assert!(
+                    (x - y).norm() <= lsb * 1.5,
+                    "{format}: {x} came back as {y}"
+                );
+            }
+            assert_eq!(format.to_string().parse::<SampleFormat>().unwrap(), format);
+        }
+    }
+
+    #[test]
+    fn saturation() {
+        let mut bytes = vec![];
+        let mut out = [Complex::default()];
+        SampleFormat::CS16.encode(&[Complex::new(1.5, -1.5)], &mut bytes);
+        SampleFormat::CS16.decode(&bytes, &mut out);
+        assert!((out[0] - Complex::new(1.0, -1.0)).norm() < 1e-3);
+    }
+}
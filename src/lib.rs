@@ -1,3 +1,23 @@
 pub mod utils;
 pub mod daq;
-pub mod sigproc_io;
\ No newline at end of file
+pub mod sigproc_io;
+pub mod source;
+pub mod synth;
+pub mod iq_file;
+pub mod recorder;
+pub mod stats;
+pub mod accumulate;
+pub mod correlator;
+pub mod sweep;
+pub mod schedule;
+pub mod adc;
+pub mod channelizer;
+pub mod meta;
+pub mod queue;
+pub mod pool;
+pub mod iq_correct;
+pub mod calibration;
+pub mod radiometer;
+pub mod zoom;
+pub mod dedisperse;
+pub mod velocity;
\ No newline at end of file
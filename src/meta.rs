@@ -0,0 +1,127 @@
+use std::io::Write;
+
+use crate::velocity::VelocityFrame;
+
+/// What the averaged spectra of a `run_daq` pipeline mean, as needed to turn them
+/// into a filterbank. Saved as `key = value` lines next to raw output files.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpectrumMeta {
+    pub nch: usize,
+    pub nifs: usize,
+    /// input samples per second
+    pub sample_rate: f64,
+    /// channel bandwidth over channel spacing in time, i.e. `nch` over the samples per row
+    pub osr: f64,
+    pub n_average: usize,
+    /// MJD of the first spectrum written
+    pub tstart: Option<f64>,
+    /// unit of calibrated spectra, e.g. `K`, uncalibrated power if `None`
+    pub unit: Option<String>,
+    /// velocities the spectra are to be read as, if any
+    pub velocity: Option<VelocityFrame>,
+}
+
+impl SpectrumMeta {
+    /// in Hz
+    pub fn channel_bandwidth(&self) -> f64 {
+        self.sample_rate / self.nch as f64
+    }
+
+    /// time between spectra leaving the channelizer, in s
+    pub fn spectrum_interval(&self) -> f64 {
+        self.nch as f64 / self.osr / self.sample_rate
+    }
+
+    /// time between averaged spectra, in s
+    pub fn tsamp(&self) -> f64 {
+        self.spectrum_interval() * self.n_average as f64
+    }
+
+    /// Conventionally `<output file>.meta`.
+    pub fn path_for(output: &str) -> String {
+        format!("{output}.meta")
+    }
+
+    pub fn save(&self, path: &str) -> std::io::Result<()> {
+        let mut f = std::fs::File::create(path)?;
+        writeln!(f, "nch = {}", self.nch)?;
+        writeln!(f, "nifs = {}", self.nifs)?;
+        writeln!(f, "sample_rate = {}", self.sample_rate)?;
+        writeln!(f, "osr = {}", self.osr)?;
+        writeln!(f, "n_average = {}", self.n_average)?;
+        if let Some(t) = self.tstart {
+            writeln!(f, "tstart = {t:.12}")?;
+        }
+        if let Some(ref u) = self.unit {
+            writeln!(f, "unit = {u}")?;
+        }
+        if let Some(ref v) = self.velocity {
+            writeln!(f, "rest_freq = {}", v.rest_freq)?;
+            writeln!(f, "convention = {}", v.convention)?;
+            writeln!(f, "frame = {}", v.frame)?;
+            if let Some(site) = v.site {
+                writeln!(f, "site = {site}")?;
+            }
+            if let Some(pointing) = v.pointing {
+                writeln!(f, "pointing = {pointing}")?;
+            }
+        }
+        // derived, for the reader's convenience only
+        writeln!(f, "# channel_bandwidth = {}", self.channel_bandwidth())?;
+        writeln!(f, "# tsamp = {}", self.tsamp())
+    }
+
+    pub fn load(path: &str) -> Result<SpectrumMeta, String> {
+        std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {path}: {e}"))?
+            .parse()
+            .map_err(|e| format!("{path}: {e}"))
+    }
+}
+
+impl std::str::FromStr for SpectrumMeta {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut values = std::collections::HashMap::new();
+        for line in s.lines() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (k, v) = line
+                .split_once('=')
+                .ok_or_else(|| format!("expected key = value, got '{line}'"))?;
+            values.insert(k.trim(), v.trim());
+        }
+        fn get<T: std::str::FromStr>(
+            values: &std::collections::HashMap<&str, &str>,
+            k: &str,
+        ) -> Result<Option<T>, String> {
+            values
+                .get(k)
+                .map(|v| v.parse().map_err(|_| format!("bad value '{v}' for {k}")))
+                .transpose()
+        }
+        let required = |k: &str| format!("{k} is missing");
+        Ok(SpectrumMeta {
+            nch: get(&values, "nch")?.ok_or_else(|| required("nch"))?,
+            nifs: get(&values, "nifs")?.unwrap_or(1),
+            sample_rate: get(&values, "sample_rate")?.ok_or_else(|| required("sample_rate"))?,
+            osr: get(&values, "osr")?.ok_or_else(|| required("osr"))?,
+            n_average: get(&values, "n_average")?.ok_or_else(|| required("n_average"))?,
+            tstart: get(&values, "tstart")?,
+            unit: get(&values, "unit")?,
+            velocity: match get(&values, "rest_freq")? {
+                Some(rest_freq) => Some(VelocityFrame {
+                    rest_freq,
+                    convention: get(&values, "convention")?.unwrap_or_default(),
+                    frame: get(&values, "frame")?.unwrap_or_default(),
+                    site: get(&values, "site")?,
+                    pointing: get(&values, "pointing")?,
+                }),
+                None => None,
+            },
+        })
+    }
+}
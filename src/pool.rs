@@ -0,0 +1,44 @@
+use crossbeam::channel::{Receiver, Sender, bounded};
+
+/// Free list of buffers handed back by the thread that consumed them, so that the
+/// producing thread of `run_daq` can refill them instead of allocating.
+///
+/// Clones share the same buffers. Buffers returned while the pool is full are freed.
+pub struct Pool<T> {
+    tx: Sender<T>,
+    rx: Receiver<T>,
+}
+
+impl<T> Clone for Pool<T> {
+    fn clone(&self) -> Self {
+        Pool {
+            tx: self.tx.clone(),
+            rx: self.rx.clone(),
+        }
+    }
+}
+
+impl<T> Pool<T> {
+    pub fn new(capacity: usize) -> Pool<T> {
+        let (tx, rx) = bounded(capacity);
+        Pool { tx, rx }
+    }
+
+    /// A recycled buffer, in whatever state it was returned, if there is one.
+    pub fn take(&self) -> Option<T> {
+        self.rx.try_recv().ok()
+    }
+
+    pub fn put(&self, x: T) {
+        let _ = self.tx.try_send(x);
+    }
+
+    /// number of buffers waiting to be reused
+    pub fn len(&self) -> usize {
+        self.rx.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rx.is_empty()
+    }
+}
@@ -0,0 +1,300 @@
+use crossbeam::channel::{Receiver, SendError, Sender, TrySendError, bounded};
+
+/// What a stage of `run_daq` does when the queue to the next one is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    /// discard the item being sent
+    DropNewest,
+    /// discard the item at the head of the queue to make room; the sender holds a
+    /// receiver for that, so the queue never disconnects and sending keeps succeeding
+    /// after the consumer is gone
+    DropOldest,
+    /// wait for the consumer, stalling this stage and everything before it
+    Block,
+}
+
+impl std::str::FromStr for Policy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop-newest" => Ok(Policy::DropNewest),
+            "drop-oldest" => Ok(Policy::DropOldest),
+            "block" => Ok(Policy::Block),
+            _ => Err(format!(
+                "unknown queue policy '{s}', can be drop-newest, drop-oldest or block"
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Policy {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Policy::DropNewest => write!(fmt, "drop-newest"),
+            Policy::DropOldest => write!(fmt, "drop-oldest"),
+            Policy::Block => write!(fmt, "block"),
+        }
+    }
+}
+
+/// Capacity and full-queue policy of one bounded channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Queue {
+    pub capacity: usize,
+    pub policy: Policy,
+}
+
+impl Queue {
+    pub fn new(capacity: usize, policy: Policy) -> Queue {
+        Queue { capacity, policy }
+    }
+
+    /// Panics for a capacity of 0, which a full-queue policy cannot work with.
+    pub fn channel<T>(&self) -> (QueueSender<T>, Receiver<T>) {
+        assert!(self.capacity > 0, "queue capacity must be at least 1");
+        let (tx, rx) = bounded(self.capacity);
+        let head = (self.policy == Policy::DropOldest).then(|| rx.clone());
+        (
+            QueueSender {
+                tx,
+                head,
+                policy: self.policy,
+            },
+            rx,
+        )
+    }
+}
+
+impl std::fmt::Display for Queue {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(fmt, "{}:{}", self.policy, self.capacity)
+    }
+}
+
+/// Queues between the stages of `run_daq`.
+///
+/// Nothing is discarded silently: every drop is counted in `DaqStats`. With `Policy::Block`
+/// throughout, the only possible loss is an overflow of the source itself, which taints
+/// the affected spectra. Blocking queues must be drained or their receivers dropped for
+/// `DaqHandle::join` to return.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Queues {
+    /// reader to channelizer
+    pub raw: Queue,
+    /// reader to raw recorder
+    pub record: Queue,
+    /// channelizer to averaging
+    pub spectrum: Queue,
+    /// averaging to each output receiver
+    pub averaged: Queue,
+    /// radiometer to its receiver
+    pub power: Queue,
+    /// zoom channelizer to its receiver
+    pub zoom: Queue,
+    /// averaging to the single-pulse search, one item per search sample
+    pub search: Queue,
+    /// single-pulse search to its receiver
+    pub candidates: Queue,
+}
+
+impl Queues {
+    /// Dropping the newest item everywhere, the spectrum queue holding two averages.
+    pub fn new(n_average: usize) -> Queues {
+        Queues {
+            raw: Queue::new(64, Policy::DropNewest),
+            record: Queue::new(64, Policy::DropNewest),
+            spectrum: Queue::new((n_average * 2).max(1), Policy::DropNewest),
+            averaged: Queue::new(16, Policy::DropNewest),
+            power: Queue::new(1024, Policy::DropNewest),
+            zoom: Queue::new(16, Policy::DropNewest),
+            search: Queue::new(4096, Policy::DropNewest),
+            candidates: Queue::new(64, Policy::DropNewest),
+        }
+    }
+
+    /// `Err` naming the first stage whose queue has no room at all.
+    pub fn check(&self) -> Result<(), String> {
+        for (name, queue) in [
+            ("raw", self.raw),
+            ("record", self.record),
+            ("spectrum", self.spectrum),
+            ("averaged", self.averaged),
+        ] {
+            if queue.capacity == 0 {
+                return Err(format!("the {name} queue needs a capacity of at least 1"));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn apply(&mut self, setting: &QueueSetting) {
+        let queue = match setting.stage {
+            Stage::Raw => &mut self.raw,
+            Stage::Record => &mut self.record,
+            Stage::Spectrum => &mut self.spectrum,
+            Stage::Averaged => &mut self.averaged,
+            Stage::Power => &mut self.power,
+            Stage::Zoom => &mut self.zoom,
+            Stage::Search => &mut self.search,
+            Stage::Candidates => &mut self.candidates,
+        };
+        queue.policy = setting.policy;
+        if let Some(c) = setting.capacity {
+            queue.capacity = c;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Raw,
+    Record,
+    Spectrum,
+    Averaged,
+    Power,
+    Zoom,
+    Search,
+    Candidates,
+}
+
+/// Command line override of one of `Queues`, `<stage>=<policy>[:<capacity>]`,
+/// e.g. `averaged=block` or `raw=drop-oldest:256`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueSetting {
+    pub stage: Stage,
+    pub policy: Policy,
+    /// keep the default if `None`
+    pub capacity: Option<usize>,
+}
+
+impl std::str::FromStr for QueueSetting {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (stage, value) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected <stage>=<policy>[:<capacity>], got '{s}'"))?;
+        let stage = match stage {
+            "raw" => Stage::Raw,
+            "record" => Stage::Record,
+            "spectrum" => Stage::Spectrum,
+            "averaged" => Stage::Averaged,
+            "power" => Stage::Power,
+            "zoom" => Stage::Zoom,
+            "search" => Stage::Search,
+            "candidates" => Stage::Candidates,
+            _ => {
+                return Err(format!(
+                    "unknown stage '{stage}', can be raw, record, spectrum, averaged, power, zoom, search or candidates"
+                ));
+            }
+        };
+        let (policy, capacity) = match value.split_once(':') {
+            Some((p, c)) => match c.parse() {
+                Ok(n) if n > 0 => (p, Some(n)),
+                _ => return Err(format!("bad capacity '{c}' in '{s}'")),
+            },
+            None => (value, None),
+        };
+        Ok(QueueSetting {
+            stage,
+            policy: policy.parse()?,
+            capacity,
+        })
+    }
+}
+
+/// Sending half of a `Queue`, applying its policy.
+///
+/// With `Policy::DropOldest` the sender keeps a receiver of its own to discard from,
+/// so sending never fails even after the consumer is gone: a stage feeding such a queue
+/// cannot tell that nobody listens any more and keeps going until it is stopped.
+pub struct QueueSender<T> {
+    tx: Sender<T>,
+    head: Option<Receiver<T>>,
+    policy: Policy,
+}
+
+impl<T> QueueSender<T> {
+    /// The item discarded to make room, `x` itself for `Policy::DropNewest`, so that
+    /// its buffers can be recycled.
+    pub fn send(&self, x: T) -> Result<Option<T>, SendError<T>> {
+        match self.policy {
+            Policy::Block => self.tx.send(x).map(|_| None),
+            Policy::DropNewest => match self.tx.try_send(x) {
+                Ok(()) => Ok(None),
+                Err(TrySendError::Full(x)) => Ok(Some(x)),
+                Err(TrySendError::Disconnected(x)) => Err(SendError(x)),
+            },
+            Policy::DropOldest => {
+                let head = self.head.as_ref().unwrap();
+                let mut x = x;
+                let mut dropped = None;
+                loop {
+                    match self.tx.try_send(x) {
+                        Ok(()) => return Ok(dropped),
+                        Err(TrySendError::Full(y)) => {
+                            x = y;
+                            // the consumer may have emptied the queue meanwhile; with a
+                            // single producer only one item is ever discarded
+                            if let Ok(old) = head.try_recv() {
+                                dropped = Some(old);
+                            }
+                        }
+                        Err(TrySendError::Disconnected(x)) => return Err(SendError(x)),
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.tx.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tx.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policies() {
+        let (tx, rx) = Queue::new(2, Policy::DropNewest).channel();
+        assert_eq!(
+            [1, 2, 3].map(|x| tx.send(x).unwrap()),
+            [None, None, Some(3)]
+        );
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), [1, 2]);
+
+        let (tx, rx) = Queue::new(2, Policy::DropOldest).channel();
+        assert_eq!(
+            [1, 2, 3, 4].map(|x| tx.send(x).unwrap()),
+            [None, None, Some(1), Some(2)]
+        );
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), [3, 4]);
+        // the sender's own receiver keeps the queue connected
+        drop(rx);
+        assert_eq!(tx.send(5).unwrap(), None);
+
+        let (tx, rx) = Queue::new(1, Policy::DropNewest).channel();
+        drop(rx);
+        assert!(tx.send(1).is_err());
+    }
+
+    #[test]
+    fn capacity() {
+        assert!(Queues::new(0).check().is_ok());
+        let mut queues = Queues::new(8);
+        assert_eq!(queues.spectrum.capacity, 16);
+        queues.apply(&"raw=block:4".parse().unwrap());
+        assert_eq!(queues.raw, Queue::new(4, Policy::Block));
+        queues.averaged.capacity = 0;
+        assert!(queues.check().unwrap_err().contains("averaged"));
+        assert!("raw=block:0".parse::<QueueSetting>().is_err());
+    }
+}
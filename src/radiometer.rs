@@ -0,0 +1,284 @@
+use ndarray::Array1;
+
+use crate::daq::Spectrum;
+
+/// Inclusive range of channels of the first IF, `<first>:<last>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelRange {
+    pub first: usize,
+    pub last: usize,
+}
+
+impl ChannelRange {
+    /// number of channels
+    pub fn count(&self) -> usize {
+        self.last + 1 - self.first
+    }
+}
+
+impl std::str::FromStr for ChannelRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (first, last) = s
+            .split_once(':')
+            .ok_or_else(|| format!("expected <first>:<last>, got '{s}'"))?;
+        let num = |x: &str| {
+            x.parse::<usize>()
+                .map_err(|e| format!("bad channel '{x}' in '{s}': {e}"))
+        };
+        let (first, last) = (num(first)?, num(last)?);
+        if first > last {
+            return Err(format!("empty channel range '{s}'"));
+        }
+        Ok(ChannelRange { first, last })
+    }
+}
+
+impl std::fmt::Display for ChannelRange {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(fmt, "{}:{}", self.first, self.last)
+    }
+}
+
+/// Band-integrated power computed by `run_daq` from every channelizer output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RadiometerConfig {
+    /// one power per range, the whole band if empty
+    pub ranges: Vec<ChannelRange>,
+    /// time per power sample in s, rounded to a whole number of channelizer outputs
+    pub interval: f64,
+}
+
+impl RadiometerConfig {
+    pub fn new(ranges: Vec<ChannelRange>, interval: f64) -> RadiometerConfig {
+        RadiometerConfig { ranges, interval }
+    }
+
+    /// `ranges`, or the whole band of `nch` channels if there are none
+    pub fn ranges_for(&self, nch: usize) -> Vec<ChannelRange> {
+        if self.ranges.is_empty() {
+            vec![ChannelRange {
+                first: 0,
+                last: nch - 1,
+            }]
+        } else {
+            self.ranges.clone()
+        }
+    }
+}
+
+/// Power over each range of a `RadiometerConfig`, averaged over its interval.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PowerSample {
+    /// time of the first input sample, in ns since the unix epoch
+    pub time_ns: i64,
+    /// channelizer outputs averaged, fewer than `Radiometer::per_sample` if some were dropped
+    pub nspectra: usize,
+    pub power: Vec<f64>,
+    /// any of the spectra was tainted
+    pub tainted: bool,
+}
+
+impl PowerSample {
+    pub fn mjd(&self) -> f64 {
+        self.time_ns as f64 / 86400e9 + 40587.0
+    }
+}
+
+/// Sums channel ranges of successive spectra into `PowerSample`s.
+///
+/// Samples cover fixed stretches of input samples counted from the first spectrum, so
+/// dropped spectra leave a sample short rather than stretch it. Flagged channels are
+/// left out and the rest of their range scaled up to make up for them, so that flagging
+/// doesn't show as steps in the power. A range without any unflagged channel gives NaN.
+#[derive(Debug, Clone)]
+pub struct Radiometer {
+    ranges: Vec<ChannelRange>,
+    per_sample: usize,
+    flags: Option<Array1<bool>>,
+    sum: Vec<f64>,
+    n: usize,
+    /// input sample index where the current power sample begins
+    start: Option<u64>,
+    time_ns: i64,
+    tainted: bool,
+}
+
+impl Radiometer {
+    /// `spectrum_interval` is the time between channelizer outputs in s.
+    pub fn new(config: &RadiometerConfig, nch: usize, spectrum_interval: f64) -> Radiometer {
+        let ranges = config.ranges_for(nch);
+        for r in &ranges {
+            assert!(r.last < nch, "channel range {r} beyond {nch} channels");
+        }
+        Radiometer {
+            per_sample: ((config.interval / spectrum_interval).round() as usize).max(1),
+            sum: vec![0.0; ranges.len()],
+            ranges,
+            flags: None,
+            n: 0,
+            start: None,
+            time_ns: 0,
+            tainted: false,
+        }
+    }
+
+    pub fn ranges(&self) -> &[ChannelRange] {
+        &self.ranges
+    }
+
+    /// channelizer outputs per power sample
+    pub fn per_sample(&self) -> usize {
+        self.per_sample
+    }
+
+    /// Channels to leave out from now on, e.g. those flagged in the latest average.
+    pub fn set_flags(&mut self, flags: Option<&Array1<bool>>) {
+        match (flags, &mut self.flags) {
+            (Some(f), Some(x)) => x.assign(f),
+            (f, x) => *x = f.cloned(),
+        }
+    }
+
+    /// Add the first IF of `x`, returning the power sample this completes.
+    ///
+    /// A sample is complete once it has all its spectra, or as soon as a spectrum past
+    /// its end arrives, in which case that spectrum goes into the next one.
+    pub fn push(&mut self, x: &Spectrum) -> Option<PowerSample> {
+        let span = self.per_sample as u64 * x.nsamples;
+        let start = *self.start.get_or_insert(x.sample_index);
+        let past = x.sample_index.saturating_sub(start) / span;
+        let done = if past > 0 {
+            self.start = Some(start + past * span);
+            self.take()
+        } else {
+            None
+        };
+        if self.n == 0 {
+            self.time_ns = x.time_ns;
+            self.tainted = false;
+        }
+        for (r, sum) in self.ranges.iter().zip(self.sum.iter_mut()) {
+            let (mut p, mut used) = (0.0, 0);
+            for ch in r.first..=r.last {
+                if self.flags.as_ref().is_some_and(|f| f[ch]) {
+                    continue;
+                }
+                p += x.data[ch] as f64;
+                used += 1;
+            }
+            *sum += if used > 0 {
+                p * r.count() as f64 / used as f64
+            } else {
+                f64::NAN
+            };
+        }
+        self.tainted |= x.tainted;
+        self.n += 1;
+        if done.is_some() || self.n < self.per_sample {
+            return done;
+        }
+        self.start = Some(start + span);
+        self.take()
+    }
+
+    /// The power sample accumulated so far, if any, e.g. at the end of the acquisition.
+    pub fn take(&mut self) -> Option<PowerSample> {
+        if self.n == 0 {
+            return None;
+        }
+        let n = self.n;
+        self.n = 0;
+        Some(PowerSample {
+            time_ns: self.time_ns,
+            nspectra: n,
+            power: self
+                .sum
+                .iter_mut()
+                .map(|s| std::mem::take(s) / n as f64)
+                .collect(),
+            tainted: self.tainted,
+        })
+    }
+}
+
+/// Power samples as text, one line of `<mjd> <power per range...> <tainted>` each.
+pub struct PowerWriter<W: std::io::Write> {
+    out: W,
+}
+
+impl<W: std::io::Write> PowerWriter<W> {
+    /// Starts with a comment naming the ranges.
+    pub fn new(mut out: W, ranges: &[ChannelRange]) -> std::io::Result<PowerWriter<W>> {
+        let names = ranges.iter().map(|r| r.to_string()).collect::<Vec<_>>();
+        writeln!(out, "# mjd {} tainted", names.join(" "))?;
+        Ok(PowerWriter { out })
+    }
+
+    pub fn write(&mut self, x: &PowerSample) -> std::io::Result<()> {
+        write!(self.out, "{:.10}", x.mjd())?;
+        for p in &x.power {
+            write!(self.out, " {p:e}")?;
+        }
+        writeln!(self.out, " {}", x.tainted as u8)?;
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spectrum(sample_index: u64, tainted: bool) -> Spectrum {
+        Spectrum {
+            data: Array1::from_iter((1..=8).map(|x| x as f32)),
+            nifs: 1,
+            tainted,
+            sample_index,
+            nsamples: 8,
+            time_ns: sample_index as i64 * 1000,
+            flags: None,
+            unit: None,
+        }
+    }
+
+    #[test]
+    fn ranges() {
+        let r = "2:5".parse::<ChannelRange>().unwrap();
+        assert_eq!((r.count(), r.to_string()), (4, "2:5".to_string()));
+        assert!("5:2".parse::<ChannelRange>().is_err());
+        assert!("a:2".parse::<ChannelRange>().is_err());
+        let whole = RadiometerConfig::new(vec![], 1.0).ranges_for(8);
+        assert_eq!(whole, [ChannelRange { first: 0, last: 7 }]);
+    }
+
+    #[test]
+    fn channel_range_power() {
+        let ranges = vec!["0:1".parse().unwrap(), "4:7".parse().unwrap()];
+        let mut r = Radiometer::new(&RadiometerConfig::new(ranges, 3e-3), 8, 1e-3);
+        assert_eq!(r.per_sample(), 3);
+        assert_eq!(r.push(&spectrum(0, false)), None);
+        assert_eq!(r.push(&spectrum(8, true)), None);
+        let p = r.push(&spectrum(16, false)).unwrap();
+        assert_eq!((p.time_ns, p.nspectra, p.tainted), (0, 3, true));
+        assert_eq!(p.power, [3.0, 26.0]);
+
+        // flagged channels are made up for by the rest of their range
+        let mut flags = Array1::from_elem(8, false);
+        flags[0] = true;
+        flags[1] = true;
+        flags[5] = true;
+        r.set_flags(Some(&flags));
+        assert_eq!(r.push(&spectrum(24, false)), None);
+        // the spectrum at 32 is lost, the one at 48 goes into the next sample
+        assert_eq!(r.push(&spectrum(40, false)), None);
+        let p = r.push(&spectrum(48, false)).unwrap();
+        assert_eq!((p.time_ns, p.nspectra, p.tainted), (24000, 2, false));
+        assert!(p.power[0].is_nan());
+        assert_eq!(p.power[1], 20.0 * 4.0 / 3.0);
+        let p = r.take().unwrap();
+        assert_eq!((p.time_ns, p.nspectra), (48000, 1));
+        assert_eq!(r.take(), None);
+    }
+}
@@ -0,0 +1,132 @@
+use chrono::Utc;
+use crossbeam::channel::Receiver;
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    sync::Arc,
+    thread::JoinHandle,
+};
+
+use crate::{
+    iq_file::SampleFormat,
+    pool::Pool,
+    queue::{Queue, QueueSender},
+    source::{RawBuffers, RawSamples},
+    stats::DaqStats,
+};
+
+/// Settings of the raw baseband recorder fed by the reader thread of `run_daq`.
+///
+/// Files are named `<prefix>_<UTC time>_<seq>.<format>`, with `_ch<n>` appended to
+/// the sequence number for multi-channel sources, and new ones are started
+/// whenever either rotation limit is reached.
+#[derive(Debug, Clone)]
+pub struct RawRecorder {
+    pub prefix: String,
+    pub format: SampleFormat,
+    pub max_bytes: Option<u64>,
+    pub max_secs: Option<f64>,
+}
+
+impl RawRecorder {
+    pub fn new(prefix: &str, format: SampleFormat) -> RawRecorder {
+        RawRecorder {
+            prefix: prefix.to_string(),
+            format,
+            max_bytes: None,
+            max_secs: None,
+        }
+    }
+
+    fn open(
+        &self,
+        seq: usize,
+        nchannels: usize,
+        stats: &DaqStats,
+    ) -> io::Result<Vec<BufWriter<File>>> {
+        let time = Utc::now().format("%Y%m%dT%H%M%S");
+        (0..nchannels)
+            .map(|ch| {
+                let name = if nchannels == 1 {
+                    format!("{}_{}_{:04}.{}", self.prefix, time, seq, self.format)
+                } else {
+                    format!(
+                        "{}_{}_{:04}_ch{}.{}",
+                        self.prefix, time, seq, ch, self.format
+                    )
+                };
+                let file = File::create(&name).map(BufWriter::new)?;
+                stats.record_file();
+                Ok(file)
+            })
+            .collect()
+    }
+
+    fn record(
+        &self,
+        rx: &Receiver<RawSamples>,
+        pool: &Pool<RawSamples>,
+        max_samples: u64,
+        nchannels: usize,
+        stats: &DaqStats,
+    ) -> io::Result<()> {
+        let max_bytes = self.max_bytes.unwrap_or(u64::MAX);
+        let mut seq = 0;
+        let mut outfiles = self.open(seq, nchannels, stats)?;
+        let mut nbytes = 0_u64;
+        let mut nsamples = 0_u64;
+        let mut bytes = vec![];
+        let mut scratch = RawBuffers::new();
+        while let Ok(raw) = rx.recv() {
+            let buf = raw.to_float(&mut scratch);
+            if nbytes >= max_bytes || nsamples >= max_samples {
+                for f in &mut outfiles {
+                    f.flush()?;
+                }
+                seq += 1;
+                outfiles = self.open(seq, nchannels, stats)?;
+                nbytes = 0;
+                nsamples = 0;
+            }
+            for (outfile, buf) in outfiles.iter_mut().zip(buf) {
+                self.format.encode(buf, &mut bytes);
+                outfile.write_all(&bytes)?;
+            }
+            nbytes += bytes.len() as u64;
+            nsamples += buf[0].len() as u64;
+            pool.put(raw);
+        }
+        for f in &mut outfiles {
+            f.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Start the writer thread, it exits once the returned sender is dropped.
+    ///
+    /// Each message holds one buffer per channel, handed back to `pool` once written, and
+    /// every file started is counted in `stats`. On an I/O error the thread passes it to
+    /// `on_error` and exits, so that sending to it fails from then on.
+    pub fn spawn(
+        self,
+        queue: Queue,
+        pool: Pool<RawSamples>,
+        sample_rate: f64,
+        nchannels: usize,
+        stats: Arc<DaqStats>,
+        on_error: impl FnOnce(io::Error) + Send + 'static,
+    ) -> (QueueSender<RawSamples>, JoinHandle<()>) {
+        let (tx, rx) = queue.channel::<RawSamples>();
+        let max_samples = self
+            .max_secs
+            .map(|t| (t * sample_rate) as u64)
+            .unwrap_or(u64::MAX);
+
+        let th = std::thread::spawn(move || {
+            if let Err(e) = self.record(&rx, &pool, max_samples, nchannels, &stats) {
+                on_error(e);
+            }
+        });
+        (tx, th)
+    }
+}
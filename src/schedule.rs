@@ -0,0 +1,303 @@
+use chrono::{DateTime, TimeDelta, Utc};
+
+/// begin and end of a schedule entry
+pub type Slot = (DateTime<Utc>, DateTime<Utc>);
+
+/// When a schedule entry begins.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Start {
+    /// seconds after the schedule is started, written `+<s>`
+    Offset(f64),
+    /// absolute UTC time, in RFC 3339
+    At(DateTime<Utc>),
+}
+
+impl std::str::FromStr for Start {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(x) = s.strip_prefix('+') {
+            x.parse::<f64>()
+                .map(Start::Offset)
+                .map_err(|e| format!("bad offset '{s}': {e}"))
+        } else {
+            DateTime::parse_from_rfc3339(s)
+                .map(|t| Start::At(t.with_timezone(&Utc)))
+                .map_err(|e| format!("bad start time '{s}': {e}"))
+        }
+    }
+}
+
+impl std::fmt::Display for Start {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Start::Offset(s) => write!(fmt, "+{s}"),
+            Start::At(t) => write!(fmt, "{}", t.to_rfc3339()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ScheduleEntry {
+    /// line in the schedule file, for messages
+    pub line: usize,
+    pub start: Start,
+    /// in s
+    pub duration: f64,
+    /// centre frequency in Hz
+    pub freq: f64,
+    pub lna: f64,
+    pub mix: f64,
+    pub vga: f64,
+    pub nch: usize,
+    pub n_average: usize,
+    pub out: Option<String>,
+}
+
+impl Default for ScheduleEntry {
+    fn default() -> Self {
+        ScheduleEntry {
+            line: 0,
+            start: Start::Offset(0.0),
+            duration: 0.0,
+            freq: 0.0,
+            lna: 5.0,
+            mix: 5.0,
+            vga: 5.0,
+            nch: 512,
+            n_average: 128,
+            out: None,
+        }
+    }
+}
+
+/// A list of observations, one per line of `key=value` pairs, e.g.
+///
+/// ```text
+/// # comments and blank lines are ignored
+/// start=+0 duration=600 freq=1420.4e6 lna=10 mix=10 vga=8 nch=1024 avg=500 out=hi.bin
+/// start=2025-06-01T12:00:00Z duration=60 freq=1421e6 out=hi2.bin
+/// ```
+///
+/// `start`, `duration` and `freq` are required on the first line; every other key, and
+/// `freq` on later lines, keeps the value of the previous line when omitted. `out` is
+/// never carried over.
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    pub entries: Vec<ScheduleEntry>,
+}
+
+impl std::str::FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut entries: Vec<ScheduleEntry> = vec![];
+        for (i, line) in s.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let lineno = i + 1;
+            let err = |e: String| format!("line {lineno}: {e}");
+            let mut entry = ScheduleEntry {
+                line: lineno,
+                out: None,
+                ..entries.last().cloned().unwrap_or_default()
+            };
+            let (mut has_start, mut has_duration, mut has_freq) =
+                (false, false, !entries.is_empty());
+            for kv in line.split_whitespace() {
+                let (k, v) = kv
+                    .split_once('=')
+                    .ok_or_else(|| err(format!("expected key=value, got '{kv}'")))?;
+                let num = |v: &str| {
+                    v.parse::<f64>()
+                        .map_err(|e| err(format!("bad value for {k}: {e}")))
+                };
+                match k {
+                    "start" => {
+                        entry.start = v.parse().map_err(err)?;
+                        has_start = true;
+                    }
+                    "duration" => {
+                        entry.duration = num(v)?;
+                        has_duration = true;
+                    }
+                    "freq" => {
+                        entry.freq = num(v)?;
+                        has_freq = true;
+                    }
+                    "lna" => entry.lna = num(v)?,
+                    "mix" => entry.mix = num(v)?,
+                    "vga" => entry.vga = num(v)?,
+                    "nch" => {
+                        entry.nch = v
+                            .parse()
+                            .map_err(|e| err(format!("bad value for nch: {e}")))?
+                    }
+                    "avg" => {
+                        entry.n_average = v
+                            .parse()
+                            .map_err(|e| err(format!("bad value for avg: {e}")))?
+                    }
+                    "out" => entry.out = Some(v.to_string()),
+                    _ => return Err(err(format!("unknown key '{k}'"))),
+                }
+            }
+            if !has_start || !has_duration || !has_freq {
+                return Err(err("start, duration and freq are required".to_string()));
+            }
+            entries.push(entry);
+        }
+        Ok(Schedule { entries })
+    }
+}
+
+impl Schedule {
+    pub fn load(path: &str) -> Result<Schedule, String> {
+        std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {path}: {e}"))?
+            .parse()
+    }
+
+    /// Check the whole schedule against a start at `t0`, returning the begin and end
+    /// time of every entry, or every problem found.
+    pub fn validate(&self, t0: DateTime<Utc>) -> Result<Vec<Slot>, Vec<String>> {
+        let mut errors = vec![];
+        let mut times: Vec<Slot> = vec![];
+        let mut outputs = std::collections::HashSet::new();
+        if self.entries.is_empty() {
+            errors.push("schedule is empty".to_string());
+        }
+        for e in &self.entries {
+            let mut err = |msg: String| errors.push(format!("line {}: {msg}", e.line));
+            if !e.duration.is_finite() || e.duration <= 0.0 {
+                err(format!("duration must be positive, got {}", e.duration));
+            }
+            if e.freq <= 0.0 {
+                err(format!("freq must be positive, got {}", e.freq));
+            }
+            if !e.nch.is_power_of_two() || e.nch > 8192 {
+                err(format!("nch must be a power of two <= 8192, got {}", e.nch));
+            }
+            if e.n_average == 0 {
+                err("avg must be at least 1".to_string());
+            }
+            if let Some(ref out) = e.out {
+                if !outputs.insert(out.clone()) {
+                    err(format!("output {out} is used by an earlier entry"));
+                }
+                let dir = std::path::Path::new(out)
+                    .parent()
+                    .filter(|p| !p.as_os_str().is_empty())
+                    .unwrap_or(std::path::Path::new("."));
+                if !dir.is_dir() {
+                    err(format!("directory of {out} does not exist"));
+                }
+                if std::path::Path::new(out).exists() {
+                    err(format!("output {out} already exists"));
+                }
+            }
+
+            let begin = match e.start {
+                Start::Offset(s) => t0 + TimeDelta::milliseconds((s * 1000.0) as i64),
+                Start::At(t) => t,
+            };
+            let end = begin + TimeDelta::milliseconds((e.duration.max(0.0) * 1000.0) as i64);
+            if end <= t0 {
+                err(format!("ends at {end}, before the schedule starts"));
+            }
+            if let Some(&(_, prev_end)) = times.last()
+                && begin < prev_end
+            {
+                err(format!(
+                    "starts at {begin}, before the previous entry ends at {prev_end}"
+                ));
+            }
+            times.push((begin, end));
+        }
+        if errors.is_empty() {
+            Ok(times)
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn t0() -> DateTime<Utc> {
+        "2025-06-01T12:00:00Z".parse().unwrap()
+    }
+
+    #[test]
+    fn parse() {
+        let s: Schedule = "# comment\n\
+                           start=+0 duration=60 freq=1420.4e6 nch=1024 avg=500 out=a.bin\n\
+                           \n\
+                           start=2025-06-01T12:05:00Z duration=30 lna=8\n"
+            .parse()
+            .unwrap();
+        assert_eq!(s.entries.len(), 2);
+        let (a, b) = (&s.entries[0], &s.entries[1]);
+        assert_eq!((a.line, a.start, a.duration), (2, Start::Offset(0.0), 60.0));
+        assert_eq!(a.out.as_deref(), Some("a.bin"));
+        // everything but out carries over
+        assert_eq!(
+            (b.line, b.freq, b.nch, b.n_average),
+            (4, 1420.4e6, 1024, 500)
+        );
+        assert_eq!((b.lna, b.mix), (8.0, 5.0));
+        assert_eq!(b.out, None);
+        assert_eq!(b.start, Start::At(t0() + TimeDelta::minutes(5)));
+
+        assert!("duration=60 freq=1e9".parse::<Schedule>().is_err());
+        assert!("start=+0 duration=60".parse::<Schedule>().is_err());
+        assert!(
+            "start=+0 duration=60 freq=1e9 gain=3"
+                .parse::<Schedule>()
+                .is_err()
+        );
+        assert!("start=+0 duration=x freq=1e9".parse::<Schedule>().is_err());
+    }
+
+    #[test]
+    fn validate() {
+        let s: Schedule = "start=+10 duration=60 freq=1e9\nstart=+70 duration=5"
+            .parse()
+            .unwrap();
+        let times = s.validate(t0()).unwrap();
+        assert_eq!(
+            times[0],
+            (t0() + TimeDelta::seconds(10), t0() + TimeDelta::seconds(70))
+        );
+        assert_eq!(times[1].0, t0() + TimeDelta::seconds(70));
+
+        let s: Schedule = "start=+0 duration=60 freq=1e9 nch=1000 avg=0\n\
+                           start=+30 duration=60\n\
+                           start=2025-06-01T11:00:00Z duration=60\n\
+                           start=+200 duration=10 out=Cargo.toml\n\
+                           start=+300 duration=10 out=no/such/dir/x.bin"
+            .parse()
+            .unwrap();
+        let errors = s.validate(t0()).unwrap_err();
+        for (line, what) in [
+            (1, "nch"),
+            (1, "avg"),
+            (2, "before the previous entry ends"),
+            (3, "before the schedule starts"),
+            (4, "already exists"),
+            (5, "does not exist"),
+        ] {
+            assert!(
+                errors
+                    .iter()
+                    .any(|e| e.starts_with(&format!("line {line}:")) && e.contains(what)),
+                "no '{what}' error for line {line} in {errors:?}"
+            );
+        }
+        assert!("".parse::<Schedule>().unwrap().validate(t0()).is_err());
+    }
+}
@@ -314,6 +314,18 @@ impl Header {
         unreachable!()
     }
 
+    pub fn set_nifs(&mut self, nifs: usize) {
+        for i in &mut self.items {
+            if let HeaderItem::IntItem(x) = i
+                && String::from_utf8(x.key.content.clone()).unwrap() == "nifs"
+            {
+                x.value = nifs as u32;
+                return;
+            }
+        }
+        unreachable!()
+    }
+
     pub fn nbits(&self) -> usize {
         for i in &self.items {
             if let HeaderItem::IntItem(x) = i
@@ -0,0 +1,562 @@
+use num::Complex;
+use soapysdr::{Device, Direction, ErrorCode, Format, RxStream};
+
+use crate::iq_file::SampleFormat;
+
+type Ftype = f32;
+
+/// One buffer per channel, as filled by `SampleSource::read`.
+pub type RawBuffers = Vec<Vec<Complex<Ftype>>>;
+
+/// One buffer per channel in the format a source delivers natively, so that the
+/// conversion to float happens in the channelizer thread rather than the reader.
+#[derive(Debug)]
+pub enum RawSamples {
+    CF32(RawBuffers),
+    /// `scale` maps the integers to a full scale of 1
+    CS16 {
+        data: Vec<Vec<Complex<i16>>>,
+        scale: Ftype,
+    },
+    CS8 {
+        data: Vec<Vec<Complex<i8>>>,
+        scale: Ftype,
+    },
+}
+
+impl Clone for RawSamples {
+    fn clone(&self) -> Self {
+        let mut x = self.empty();
+        x.clone_from(self);
+        x
+    }
+
+    /// Reuses the buffers of `self` if the formats agree.
+    fn clone_from(&mut self, source: &Self) {
+        match (self, source) {
+            (RawSamples::CF32(a), RawSamples::CF32(b)) => a.clone_from(b),
+            (RawSamples::CS16 { data: a, scale: sa }, RawSamples::CS16 { data: b, scale: sb }) => {
+                a.clone_from(b);
+                *sa = *sb;
+            }
+            (RawSamples::CS8 { data: a, scale: sa }, RawSamples::CS8 { data: b, scale: sb }) => {
+                a.clone_from(b);
+                *sa = *sb;
+            }
+            (a, b) => {
+                *a = b.empty();
+                a.clone_from(b);
+            }
+        }
+    }
+}
+
+impl RawSamples {
+    /// No channels, of the same format and scale.
+    pub fn empty(&self) -> RawSamples {
+        match *self {
+            RawSamples::CF32(_) => RawSamples::CF32(vec![]),
+            RawSamples::CS16 { scale, .. } => RawSamples::CS16 {
+                data: vec![],
+                scale,
+            },
+            RawSamples::CS8 { scale, .. } => RawSamples::CS8 {
+                data: vec![],
+                scale,
+            },
+        }
+    }
+
+    pub fn format(&self) -> SampleFormat {
+        match self {
+            RawSamples::CF32(_) => SampleFormat::CF32,
+            RawSamples::CS16 { .. } => SampleFormat::CS16,
+            RawSamples::CS8 { .. } => SampleFormat::CS8,
+        }
+    }
+
+    /// `nchannels` buffers of `len` samples each.
+    pub fn resize(&mut self, nchannels: usize, len: usize) {
+        fn resize<T: Default + Clone>(data: &mut Vec<Vec<T>>, nchannels: usize, len: usize) {
+            data.resize_with(nchannels, Vec::new);
+            data.iter_mut().for_each(|b| b.resize(len, T::default()));
+        }
+        match self {
+            RawSamples::CF32(data) => resize(data, nchannels, len),
+            RawSamples::CS16 { data, .. } => resize(data, nchannels, len),
+            RawSamples::CS8 { data, .. } => resize(data, nchannels, len),
+        }
+    }
+
+    /// samples per channel
+    pub fn len(&self) -> usize {
+        match self {
+            RawSamples::CF32(data) => data.first().map_or(0, |b| b.len()),
+            RawSamples::CS16 { data, .. } => data.first().map_or(0, |b| b.len()),
+            RawSamples::CS8 { data, .. } => data.first().map_or(0, |b| b.len()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The samples as float, converted into `scratch` unless they already are.
+    pub fn to_float<'a>(&'a self, scratch: &'a mut RawBuffers) -> &'a RawBuffers {
+        fn convert<T: Copy + Into<Ftype>>(
+            data: &[Vec<Complex<T>>],
+            scale: Ftype,
+            scratch: &mut RawBuffers,
+        ) {
+            scratch.resize_with(data.len(), Vec::new);
+            for (out, x) in scratch.iter_mut().zip(data) {
+                out.clear();
+                out.extend(
+                    x.iter()
+                        .map(|x| Complex::new(x.re.into() * scale, x.im.into() * scale)),
+                );
+            }
+        }
+        match *self {
+            RawSamples::CF32(ref data) => return data,
+            RawSamples::CS16 { ref data, scale } => convert(data, scale, scratch),
+            RawSamples::CS8 { ref data, scale } => convert(data, scale, scratch),
+        }
+        scratch
+    }
+}
+
+#[derive(Debug)]
+pub enum SourceError {
+    Timeout,
+    Overflow,
+    Eof,
+    Other(String),
+}
+
+impl std::fmt::Display for SourceError {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SourceError::Timeout => write!(fmt, "read timeout"),
+            SourceError::Overflow => write!(fmt, "overflow"),
+            SourceError::Eof => write!(fmt, "end of stream"),
+            SourceError::Other(msg) => write!(fmt, "{msg}"),
+        }
+    }
+}
+
+impl std::error::Error for SourceError {}
+
+impl From<soapysdr::Error> for SourceError {
+    fn from(e: soapysdr::Error) -> Self {
+        match e.code {
+            ErrorCode::Timeout => SourceError::Timeout,
+            ErrorCode::Overflow => SourceError::Overflow,
+            _ => SourceError::Other(e.to_string()),
+        }
+    }
+}
+
+impl From<std::io::Error> for SourceError {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            std::io::ErrorKind::UnexpectedEof => SourceError::Eof,
+            _ => SourceError::Other(e.to_string()),
+        }
+    }
+}
+
+/// Anything that can feed complex baseband into `run_daq`.
+///
+/// `read` follows the SoapySDR convention: one buffer per channel, returns
+/// the number of samples written into each of them.
+pub trait SampleSource: Send {
+    fn mtu(&self) -> usize;
+
+    fn channels(&self) -> usize {
+        1
+    }
+
+    fn activate(&mut self) -> Result<(), SourceError>;
+
+    fn deactivate(&mut self) -> Result<(), SourceError>;
+
+    /// Try to recover after repeated read failures.
+    fn reopen(&mut self) -> Result<(), SourceError> {
+        let _ = self.deactivate();
+        self.activate()
+    }
+
+    fn read(&mut self, buffers: &mut [&mut [Complex<Ftype>]]) -> Result<usize, SourceError>;
+
+    /// An empty buffer for `read_samples`, in the format the source delivers natively.
+    fn new_samples(&self) -> RawSamples {
+        RawSamples::CF32(vec![])
+    }
+
+    /// As `read`, into buffers made by `new_samples` and resized to `mtu()` samples.
+    fn read_samples(&mut self, buffers: &mut RawSamples) -> Result<usize, SourceError> {
+        match buffers {
+            RawSamples::CF32(data) => with_slices(data, |b| self.read(b)),
+            x => Err(SourceError::Other(format!(
+                "{} samples are not supported by this source",
+                x.format()
+            ))),
+        }
+    }
+
+    fn sample_rate(&self) -> f64;
+
+    /// Time of the first sample returned by the last `read`, in ns, if the source knows it.
+    fn timestamp_ns(&self) -> Option<i64> {
+        None
+    }
+}
+
+impl<S: SampleSource + ?Sized> SampleSource for Box<S> {
+    fn mtu(&self) -> usize {
+        (**self).mtu()
+    }
+
+    fn channels(&self) -> usize {
+        (**self).channels()
+    }
+
+    fn activate(&mut self) -> Result<(), SourceError> {
+        (**self).activate()
+    }
+
+    fn deactivate(&mut self) -> Result<(), SourceError> {
+        (**self).deactivate()
+    }
+
+    fn reopen(&mut self) -> Result<(), SourceError> {
+        (**self).reopen()
+    }
+
+    fn read(&mut self, buffers: &mut [&mut [Complex<Ftype>]]) -> Result<usize, SourceError> {
+        (**self).read(buffers)
+    }
+
+    fn new_samples(&self) -> RawSamples {
+        (**self).new_samples()
+    }
+
+    fn read_samples(&mut self, buffers: &mut RawSamples) -> Result<usize, SourceError> {
+        (**self).read_samples(buffers)
+    }
+
+    fn sample_rate(&self) -> f64 {
+        (**self).sample_rate()
+    }
+
+    fn timestamp_ns(&self) -> Option<i64> {
+        (**self).timestamp_ns()
+    }
+}
+
+/// Device stream of whichever sample type `SoapySource` asked for.
+enum Stream {
+    CF32(RxStream<Complex<Ftype>>),
+    CS16(RxStream<Complex<i16>>),
+    CS8(RxStream<Complex<i8>>),
+}
+
+impl Stream {
+    /// `CU8` is asked for as `CS8`, drivers offering one convert to the other.
+    fn open(
+        device: &Device,
+        channels: &[usize],
+        format: SampleFormat,
+    ) -> Result<Stream, soapysdr::Error> {
+        Ok(match format {
+            SampleFormat::CF32 => Stream::CF32(device.rx_stream(channels)?),
+            SampleFormat::CS16 => Stream::CS16(device.rx_stream(channels)?),
+            SampleFormat::CS8 | SampleFormat::CU8 => Stream::CS8(device.rx_stream(channels)?),
+        })
+    }
+
+    fn mtu(&self) -> Result<usize, soapysdr::Error> {
+        match self {
+            Stream::CF32(s) => s.mtu(),
+            Stream::CS16(s) => s.mtu(),
+            Stream::CS8(s) => s.mtu(),
+        }
+    }
+
+    fn activate(&mut self) -> Result<(), soapysdr::Error> {
+        match self {
+            Stream::CF32(s) => s.activate(None),
+            Stream::CS16(s) => s.activate(None),
+            Stream::CS8(s) => s.activate(None),
+        }
+    }
+
+    fn deactivate(&mut self) -> Result<(), soapysdr::Error> {
+        match self {
+            Stream::CF32(s) => s.deactivate(None),
+            Stream::CS16(s) => s.deactivate(None),
+            Stream::CS8(s) => s.deactivate(None),
+        }
+    }
+}
+
+/// Call `f` with one slice per buffer, gathered on the stack for up to two channels.
+fn with_slices<T, R>(data: &mut [Vec<T>], f: impl FnOnce(&mut [&mut [T]]) -> R) -> R {
+    match data {
+        [a] => f(&mut [a.as_mut_slice()]),
+        [a, b] => f(&mut [a.as_mut_slice(), b.as_mut_slice()]),
+        _ => f(&mut data
+            .iter_mut()
+            .map(|b| b.as_mut_slice())
+            .collect::<Vec<_>>()),
+    }
+}
+
+pub struct SoapySource {
+    stream: Stream,
+    /// integer full scale of the stream
+    full_scale: f64,
+    /// kept to recreate the stream in `reopen`
+    device: Option<(Device, Vec<usize>)>,
+    mtu: usize,
+    nchannels: usize,
+    sample_rate: f64,
+    timeout_us: i64,
+    /// unix time minus hardware time, in ns, if the device has a hardware clock
+    time_offset_ns: Option<i64>,
+    last_time_ns: Option<i64>,
+}
+
+impl SoapySource {
+    pub fn new(
+        stream: RxStream<Complex<Ftype>>,
+        nchannels: usize,
+        sample_rate: f64,
+    ) -> Result<SoapySource, soapysdr::Error> {
+        SoapySource::with_stream(Stream::CF32(stream), 1.0, nchannels, sample_rate)
+    }
+
+    fn with_stream(
+        stream: Stream,
+        full_scale: f64,
+        nchannels: usize,
+        sample_rate: f64,
+    ) -> Result<SoapySource, soapysdr::Error> {
+        let mtu = stream.mtu()?;
+        Ok(SoapySource {
+            stream,
+            full_scale,
+            device: None,
+            mtu,
+            nchannels,
+            sample_rate,
+            timeout_us: 1_000_000,
+            time_offset_ns: None,
+            last_time_ns: None,
+        })
+    }
+
+    /// Integer format closest to what `channel` of `device` produces natively, with its
+    /// full scale, or `CF32` if there is none.
+    pub fn native_format(device: &Device, channel: usize) -> (SampleFormat, f64) {
+        match device.native_stream_format(Direction::Rx, channel) {
+            Ok((Format::CS16, full_scale)) => (SampleFormat::CS16, full_scale),
+            Ok((Format::CS8, full_scale)) | Ok((Format::CU8, full_scale)) => {
+                (SampleFormat::CS8, full_scale)
+            }
+            _ => (SampleFormat::CF32, 1.0),
+        }
+    }
+
+    /// Open a stream on `channels` of `device` in its native format, see `open_as`.
+    pub fn open(
+        device: &Device,
+        channels: &[usize],
+        sample_rate: f64,
+    ) -> Result<SoapySource, soapysdr::Error> {
+        let (format, _) = SoapySource::native_format(device, channels[0]);
+        SoapySource::open_as(device, channels, sample_rate, format)
+    }
+
+    /// Open a stream of `format` on `channels` of `device`, which is also used to reopen
+    /// it if needed. Integer samples are scaled to float in the channelizer thread, by the
+    /// full scale the driver reports if `format` is native and by that of the type otherwise.
+    pub fn open_as(
+        device: &Device,
+        channels: &[usize],
+        sample_rate: f64,
+        format: SampleFormat,
+    ) -> Result<SoapySource, soapysdr::Error> {
+        let full_scale = match (SoapySource::native_format(device, channels[0]), format) {
+            ((native, full_scale), format) if native == format => full_scale,
+            (_, SampleFormat::CF32) => 1.0,
+            (_, SampleFormat::CS16) => 32768.0,
+            (_, SampleFormat::CS8 | SampleFormat::CU8) => 128.0,
+        };
+        let stream = Stream::open(device, channels, format)?;
+        let mut source = SoapySource::with_stream(stream, full_scale, channels.len(), sample_rate)?;
+        source.device = Some((device.clone(), channels.to_vec()));
+        Ok(source)
+    }
+
+    /// Integer value the samples are divided by, 1 for `CF32`.
+    pub fn full_scale(&self) -> f64 {
+        self.full_scale
+    }
+
+    pub fn format(&self) -> SampleFormat {
+        match self.stream {
+            Stream::CF32(_) => SampleFormat::CF32,
+            Stream::CS16(_) => SampleFormat::CS16,
+            Stream::CS8(_) => SampleFormat::CS8,
+        }
+    }
+
+    fn sync_time(&mut self) {
+        self.time_offset_ns = self.device.as_ref().and_then(|(device, _)| {
+            if !device.has_hardware_time(None).unwrap_or(false) {
+                return None;
+            }
+            let hw = device.get_hardware_time(None).ok()?;
+            Some(chrono::Utc::now().timestamp_nanos_opt()? - hw)
+        });
+    }
+
+    /// the hardware clock is read after the samples arrived, so step back by their duration
+    fn stamp(&mut self, len: usize) {
+        self.last_time_ns = self.time_offset_ns.and_then(|offset| {
+            let (device, _) = self.device.as_ref()?;
+            let hw = device.get_hardware_time(None).ok()?;
+            Some(hw + offset - (len as f64 * 1e9 / self.sample_rate) as i64)
+        });
+    }
+}
+
+impl SampleSource for SoapySource {
+    fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    fn channels(&self) -> usize {
+        self.nchannels
+    }
+
+    fn activate(&mut self) -> Result<(), SourceError> {
+        self.stream.activate()?;
+        self.sync_time();
+        Ok(())
+    }
+
+    fn deactivate(&mut self) -> Result<(), SourceError> {
+        Ok(self.stream.deactivate()?)
+    }
+
+    fn reopen(&mut self) -> Result<(), SourceError> {
+        let _ = self.stream.deactivate();
+        if let Some((ref device, ref channels)) = self.device {
+            self.stream = Stream::open(device, channels, self.format())?;
+            self.mtu = self.stream.mtu()?;
+        }
+        self.activate()
+    }
+
+    /// Converts on the spot for integer streams, `run_daq` uses `read_samples` instead.
+    fn read(&mut self, buffers: &mut [&mut [Complex<Ftype>]]) -> Result<usize, SourceError> {
+        let len = match self.stream {
+            Stream::CF32(ref mut s) => s.read(buffers, self.timeout_us)?,
+            _ => {
+                let mut raw = self.new_samples();
+                raw.resize(buffers.len(), buffers[0].len());
+                let len = self.read_samples(&mut raw)?;
+                let mut scratch = vec![];
+                for (out, x) in buffers.iter_mut().zip(raw.to_float(&mut scratch)) {
+                    out[..len].copy_from_slice(&x[..len]);
+                }
+                return Ok(len);
+            }
+        };
+        self.stamp(len);
+        Ok(len)
+    }
+
+    fn new_samples(&self) -> RawSamples {
+        let scale = (1.0 / self.full_scale) as Ftype;
+        match self.stream {
+            Stream::CF32(_) => RawSamples::CF32(vec![]),
+            Stream::CS16(_) => RawSamples::CS16 {
+                data: vec![],
+                scale,
+            },
+            Stream::CS8(_) => RawSamples::CS8 {
+                data: vec![],
+                scale,
+            },
+        }
+    }
+
+    fn read_samples(&mut self, buffers: &mut RawSamples) -> Result<usize, SourceError> {
+        let timeout_us = self.timeout_us;
+        let len = match (&mut self.stream, buffers) {
+            (Stream::CF32(s), RawSamples::CF32(data)) => {
+                with_slices(data, |b| s.read(b, timeout_us))?
+            }
+            (Stream::CS16(s), RawSamples::CS16 { data, .. }) => {
+                with_slices(data, |b| s.read(b, timeout_us))?
+            }
+            (Stream::CS8(s), RawSamples::CS8 { data, .. }) => {
+                with_slices(data, |b| s.read(b, timeout_us))?
+            }
+            (_, x) => {
+                return Err(SourceError::Other(format!(
+                    "{} buffers for a {} stream",
+                    x.format(),
+                    self.format()
+                )));
+            }
+        };
+        self.stamp(len);
+        Ok(len)
+    }
+
+    fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    fn timestamp_ns(&self) -> Option<i64> {
+        self.last_time_ns
+    }
+}
+
+/// Paces a software source so that it delivers samples at `sample_rate`.
+pub struct Throttle {
+    sample_rate: f64,
+    t0: Option<std::time::Instant>,
+    nsamples: u64,
+}
+
+impl Throttle {
+    pub fn new(sample_rate: f64) -> Throttle {
+        Throttle {
+            sample_rate,
+            t0: None,
+            nsamples: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.t0 = None;
+        self.nsamples = 0;
+    }
+
+    /// Account for `n` more samples, sleeping until they are due.
+    pub fn wait(&mut self, n: usize) {
+        let t0 = *self.t0.get_or_insert_with(std::time::Instant::now);
+        self.nsamples += n as u64;
+        let due = t0 + std::time::Duration::from_secs_f64(self.nsamples as f64 / self.sample_rate);
+        let now = std::time::Instant::now();
+        if due > now {
+            std::thread::sleep(due - now);
+        }
+    }
+}
@@ -0,0 +1,307 @@
+use crossbeam::channel::{Receiver, Sender, bounded};
+use std::sync::{
+    Mutex,
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+
+/// Counters shared between the `run_daq` threads and whoever wants to watch them.
+#[derive(Default)]
+pub struct DaqStats {
+    samples: AtomicU64,
+    sample_rate: AtomicU64,
+    power_db: AtomicU64,
+    adc_peak: AtomicU64,
+    clip_fraction: AtomicU64,
+    clipped_buffers: AtomicU64,
+    dc_i: AtomicU64,
+    dc_q: AtomicU64,
+    iq_gain_db: AtomicU64,
+    iq_phase_deg: AtomicU64,
+    adc_histogram: Mutex<Vec<u64>>,
+    raw_queue: AtomicUsize,
+    spectrum_queue: AtomicUsize,
+    averaged_queue: AtomicUsize,
+    dropped_raw: AtomicU64,
+    dropped_record: AtomicU64,
+    dropped_spectra: AtomicU64,
+    dropped_averages: AtomicU64,
+    dropped_power: AtomicU64,
+    dropped_zoom: AtomicU64,
+    dropped_search: AtomicU64,
+    rejected_averages: AtomicU64,
+    tainted_spectra: AtomicU64,
+    flagged_channels: AtomicU64,
+    timeouts: AtomicU64,
+    overflows: AtomicU64,
+    stream_errors: AtomicU64,
+    reopens: AtomicU64,
+    recorded_files: AtomicU64,
+    recorder_failures: AtomicU64,
+    allocated_buffers: AtomicU64,
+    subscribers: Mutex<Vec<Sender<DaqStatsSnapshot>>>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DaqStatsSnapshot {
+    pub samples: u64,
+    /// measured since the start of acquisition, in samples per second
+    pub sample_rate: f64,
+    /// smoothed mean power of the raw samples
+    pub power_db: f64,
+    /// largest `|I|` or `|Q|` of the latest buffer, full scale is 1
+    pub adc_peak: f64,
+    /// fraction of the latest buffer at full scale
+    pub clip_fraction: f64,
+    /// buffers with more clipping than `AdcMonitor::warn_fraction`
+    pub clipped_buffers: u64,
+    /// `I` and `Q` values over `-1..1`, counted between the last two publications
+    pub adc_histogram: Vec<u64>,
+    /// estimated DC offset of the first input, full scale is 1
+    pub dc_i: f64,
+    pub dc_q: f64,
+    /// estimated amplitude of Q over I of the first input
+    pub iq_gain_db: f64,
+    /// estimated departure of Q from quadrature with I of the first input
+    pub iq_phase_deg: f64,
+    pub raw_queue: usize,
+    pub spectrum_queue: usize,
+    pub averaged_queue: usize,
+    pub dropped_raw: u64,
+    pub dropped_record: u64,
+    pub dropped_spectra: u64,
+    pub dropped_averages: u64,
+    pub dropped_power: u64,
+    pub dropped_zoom: u64,
+    /// search samples and candidates
+    pub dropped_search: u64,
+    /// averages discarded because some channel was not positive
+    pub rejected_averages: u64,
+    /// spectra computed from a PFB history with missing samples
+    pub tainted_spectra: u64,
+    /// channels of all averages flagged as RFI by spectral kurtosis
+    pub flagged_channels: u64,
+    pub timeouts: u64,
+    pub overflows: u64,
+    pub stream_errors: u64,
+    pub reopens: u64,
+    /// files started by the raw recorder
+    pub recorded_files: u64,
+    /// the raw recorder stopped on an I/O error
+    pub recorder_failures: u64,
+    /// buffers allocated because none could be recycled, flat in steady state
+    pub allocated_buffers: u64,
+}
+
+impl std::fmt::Display for DaqStatsSnapshot {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            fmt,
+            "{:.3} Msps Q={}/{}/{} pwr={:.2} dB peak={:.3} clip={:.2e} dc={:.1e}{:+.1e}i iq={:.2} dB/{:.2} deg dropped raw/rec/spec/avg/pwr/zoom/search={}/{}/{}/{}/{}/{}/{} rejected={} tainted={} flagged={} timeout/overflow/error/reopen={}/{}/{}/{} recorder files/failed={}/{} allocated={}",
+            self.sample_rate / 1e6,
+            self.raw_queue,
+            self.spectrum_queue,
+            self.averaged_queue,
+            self.power_db,
+            self.adc_peak,
+            self.clip_fraction,
+            self.dc_i,
+            self.dc_q,
+            self.iq_gain_db,
+            self.iq_phase_deg,
+            self.dropped_raw,
+            self.dropped_record,
+            self.dropped_spectra,
+            self.dropped_averages,
+            self.dropped_power,
+            self.dropped_zoom,
+            self.dropped_search,
+            self.rejected_averages,
+            self.tainted_spectra,
+            self.flagged_channels,
+            self.timeouts,
+            self.overflows,
+            self.stream_errors,
+            self.reopens,
+            self.recorded_files,
+            self.recorder_failures,
+            self.allocated_buffers
+        )
+    }
+}
+
+fn store_f64(a: &AtomicU64, v: f64) {
+    a.store(v.to_bits(), Ordering::Relaxed);
+}
+
+fn load_f64(a: &AtomicU64) -> f64 {
+    f64::from_bits(a.load(Ordering::Relaxed))
+}
+
+impl DaqStats {
+    pub fn snapshot(&self) -> DaqStatsSnapshot {
+        DaqStatsSnapshot {
+            samples: self.samples.load(Ordering::Relaxed),
+            sample_rate: load_f64(&self.sample_rate),
+            power_db: load_f64(&self.power_db),
+            adc_peak: load_f64(&self.adc_peak),
+            clip_fraction: load_f64(&self.clip_fraction),
+            clipped_buffers: self.clipped_buffers.load(Ordering::Relaxed),
+            adc_histogram: self.adc_histogram.lock().unwrap().clone(),
+            dc_i: load_f64(&self.dc_i),
+            dc_q: load_f64(&self.dc_q),
+            iq_gain_db: load_f64(&self.iq_gain_db),
+            iq_phase_deg: load_f64(&self.iq_phase_deg),
+            raw_queue: self.raw_queue.load(Ordering::Relaxed),
+            spectrum_queue: self.spectrum_queue.load(Ordering::Relaxed),
+            averaged_queue: self.averaged_queue.load(Ordering::Relaxed),
+            dropped_raw: self.dropped_raw.load(Ordering::Relaxed),
+            dropped_record: self.dropped_record.load(Ordering::Relaxed),
+            dropped_spectra: self.dropped_spectra.load(Ordering::Relaxed),
+            dropped_averages: self.dropped_averages.load(Ordering::Relaxed),
+            dropped_power: self.dropped_power.load(Ordering::Relaxed),
+            dropped_zoom: self.dropped_zoom.load(Ordering::Relaxed),
+            dropped_search: self.dropped_search.load(Ordering::Relaxed),
+            rejected_averages: self.rejected_averages.load(Ordering::Relaxed),
+            tainted_spectra: self.tainted_spectra.load(Ordering::Relaxed),
+            flagged_channels: self.flagged_channels.load(Ordering::Relaxed),
+            timeouts: self.timeouts.load(Ordering::Relaxed),
+            overflows: self.overflows.load(Ordering::Relaxed),
+            stream_errors: self.stream_errors.load(Ordering::Relaxed),
+            reopens: self.reopens.load(Ordering::Relaxed),
+            recorded_files: self.recorded_files.load(Ordering::Relaxed),
+            recorder_failures: self.recorder_failures.load(Ordering::Relaxed),
+            allocated_buffers: self.allocated_buffers.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Receive a snapshot every time the reader thread publishes one.
+    ///
+    /// Snapshots are skipped rather than queued if the receiver falls behind.
+    pub fn subscribe(&self) -> Receiver<DaqStatsSnapshot> {
+        let (tx, rx) = bounded(1);
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    pub fn publish(&self) {
+        let snapshot = self.snapshot();
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|tx| !matches!(tx.try_send(snapshot.clone()), Err(e) if e.is_disconnected()));
+    }
+
+    pub fn add_samples(&self, n: u64) -> u64 {
+        self.samples.fetch_add(n, Ordering::Relaxed) + n
+    }
+
+    pub fn set_sample_rate(&self, sps: f64) {
+        store_f64(&self.sample_rate, sps);
+    }
+
+    pub fn set_power(&self, pwr: f64) {
+        store_f64(&self.power_db, pwr.max(1e-30).log10() * 10.0);
+    }
+
+    pub fn set_adc(&self, peak: f64, clip_fraction: f64) {
+        store_f64(&self.adc_peak, peak);
+        store_f64(&self.clip_fraction, clip_fraction);
+    }
+
+    pub fn set_iq(&self, dc_i: f64, dc_q: f64, gain_db: f64, phase_deg: f64) {
+        store_f64(&self.dc_i, dc_i);
+        store_f64(&self.dc_q, dc_q);
+        store_f64(&self.iq_gain_db, gain_db);
+        store_f64(&self.iq_phase_deg, phase_deg);
+    }
+
+    pub fn set_adc_histogram(&self, histogram: &[u64]) {
+        let mut h = self.adc_histogram.lock().unwrap();
+        h.clear();
+        h.extend_from_slice(histogram);
+    }
+
+    pub fn clip(&self) {
+        self.clipped_buffers.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set_raw_queue(&self, n: usize) {
+        self.raw_queue.store(n, Ordering::Relaxed);
+    }
+
+    pub fn set_spectrum_queue(&self, n: usize) {
+        self.spectrum_queue.store(n, Ordering::Relaxed);
+    }
+
+    pub fn set_averaged_queue(&self, n: usize) {
+        self.averaged_queue.store(n, Ordering::Relaxed);
+    }
+
+    pub fn drop_raw(&self) {
+        self.dropped_raw.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn drop_record(&self) {
+        self.dropped_record.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn drop_spectrum(&self) {
+        self.dropped_spectra.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn drop_average(&self) {
+        self.dropped_averages.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn drop_power(&self) {
+        self.dropped_power.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn drop_zoom(&self) {
+        self.dropped_zoom.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn drop_search(&self) {
+        self.dropped_search.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn reject_average(&self) {
+        self.rejected_averages.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn taint_spectrum(&self) {
+        self.tainted_spectra.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn flag_channels(&self, n: u64) {
+        self.flagged_channels.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn timeout(&self) {
+        self.timeouts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn overflow(&self) {
+        self.overflows.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn stream_error(&self) {
+        self.stream_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn reopen(&self) {
+        self.reopens.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_file(&self) {
+        self.recorded_files.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn fail_recorder(&self) {
+        self.recorder_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn allocate_buffer(&self) {
+        self.allocated_buffers.fetch_add(1, Ordering::Relaxed);
+    }
+}
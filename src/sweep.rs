@@ -0,0 +1,211 @@
+use chrono::Utc;
+use crossbeam::channel::Receiver;
+use ndarray::{Array1, ArrayView1, s};
+
+use crate::{daq::Spectrum, pool::Pool, stats::DaqStats};
+
+type Ftype = f32;
+
+/// Steps the LO over `f_start..f_stop` and stitches the spectra into one panorama.
+///
+/// Every step drops `edge` of its band on each side, where the PFB response rolls off,
+/// and neighbouring steps share `overlap` of what is left, so `0 <= edge < 0.5` and
+/// `0 <= overlap < 1`.
+#[derive(Debug, Clone)]
+pub struct SweepPlan {
+    pub f_start: f64,
+    pub f_stop: f64,
+    pub sample_rate: f64,
+    pub edge: f64,
+    pub overlap: f64,
+    /// time after retuning during which spectra are discarded, in s
+    pub settle: f64,
+    /// integration time per step, in s
+    pub dwell: f64,
+}
+
+impl SweepPlan {
+    pub fn new(f_start: f64, f_stop: f64, sample_rate: f64) -> SweepPlan {
+        SweepPlan {
+            f_start,
+            f_stop,
+            sample_rate,
+            edge: 0.125,
+            overlap: 0.1,
+            settle: 0.05,
+            dwell: 0.5,
+        }
+    }
+
+    /// `Err` with the reason if the steps cannot cover the band with these settings.
+    pub fn check(&self) -> Result<(), String> {
+        if !(0.0..0.5).contains(&self.edge) {
+            return Err(format!("edge must be in [0, 0.5), not {}", self.edge));
+        }
+        if !(0.0..1.0).contains(&self.overlap) {
+            return Err(format!("overlap must be in [0, 1), not {}", self.overlap));
+        }
+        Ok(())
+    }
+
+    /// LO frequencies of the steps
+    pub fn steps(&self) -> Vec<f64> {
+        if let Err(e) = self.check() {
+            panic!("{e}");
+        }
+        let usable = self.sample_rate * (1.0 - 2.0 * self.edge);
+        let step = usable * (1.0 - self.overlap);
+        let n = ((self.f_stop - self.f_start - usable) / step)
+            .ceil()
+            .max(0.0) as usize
+            + 1;
+        (0..n)
+            .map(|i| self.f_start + usable / 2.0 + step * i as f64)
+            .collect()
+    }
+
+    /// number of panorama bins for spectra of `nch` channels
+    pub fn nbins(&self, nch: usize) -> usize {
+        ((self.f_stop - self.f_start) / (self.sample_rate / nch as f64)).round() as usize
+    }
+
+    /// One pass over all steps, `tune` retunes the receiver and the first IF of the
+    /// spectra arriving on `rx` is stitched, then handed back to `pool`. `stats` are those
+    /// of the pipeline feeding `rx`, its sample count tells which spectra started after
+    /// retuning. `None` once `rx` is disconnected.
+    pub fn run(
+        &self,
+        rx: &Receiver<Spectrum>,
+        stats: &DaqStats,
+        pool: &Pool<Spectrum>,
+        mut tune: impl FnMut(f64),
+    ) -> Option<Panorama> {
+        let mut panorama: Option<Panorama> = None;
+        for fc in self.steps() {
+            tune(fc);
+            // samples read up to now may predate the retune, and so may spectra still
+            // queued or averaging
+            let settled = stats.snapshot().samples + (self.settle * self.sample_rate) as u64;
+            let wanted = (self.dwell * self.sample_rate) as u64;
+            let mut acc: Option<Array1<Ftype>> = None;
+            let mut nsamples = 0;
+            while nsamples < wanted.max(1) {
+                let x = rx.recv().ok()?;
+                if x.sample_index < settled || x.tainted {
+                    pool.put(x);
+                    continue;
+                }
+                let nch = x.data.len() / x.nifs;
+                let data = x.data.slice(s![..nch]).map(|&v| v * x.nsamples as Ftype);
+                match acc {
+                    Some(ref mut a) => *a += &data,
+                    None => acc = Some(data),
+                }
+                nsamples += x.nsamples;
+                pool.put(x);
+            }
+            let acc = acc.unwrap() / nsamples as Ftype;
+            panorama
+                .get_or_insert_with(|| Panorama::new(self, acc.len()))
+                .add(fc, acc.view(), self.edge);
+        }
+        panorama
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Panorama {
+    /// centre frequency of the first bin, in Hz
+    pub f_start: f64,
+    /// bin width, in Hz
+    pub df: f64,
+    /// completion time of the sweep, in ns since the unix epoch
+    pub time_ns: i64,
+    data: Array1<Ftype>,
+    weight: Array1<Ftype>,
+}
+
+impl Panorama {
+    pub fn new(plan: &SweepPlan, nch: usize) -> Panorama {
+        let df = plan.sample_rate / nch as f64;
+        let nbins = plan.nbins(nch);
+        Panorama {
+            f_start: plan.f_start,
+            df,
+            time_ns: 0,
+            data: Array1::zeros(nbins),
+            weight: Array1::zeros(nbins),
+        }
+    }
+
+    /// Add a spectrum in increasing frequency centred on `fc`, overlapping bins are averaged.
+    pub fn add(&mut self, fc: f64, spectrum: ArrayView1<Ftype>, edge: f64) {
+        let nch = spectrum.len();
+        let skip = (edge * nch as f64).round() as usize;
+        for ch in skip..nch - skip {
+            let f = fc + (ch as f64 - nch as f64 / 2.0) * self.df;
+            let bin = ((f - self.f_start) / self.df).round();
+            if bin >= 0.0 && (bin as usize) < self.data.len() {
+                self.data[bin as usize] += spectrum[ch];
+                self.weight[bin as usize] += 1.0;
+            }
+        }
+        self.time_ns = Utc::now().timestamp_nanos_opt().unwrap();
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn spectrum(&self) -> Array1<Ftype> {
+        let mut x = &self.data / &self.weight.mapv(|w| w.max(1.0));
+        // bins no step reached take their lower neighbour, or the first reached bin at
+        // the bottom of the band, so that dB plots stay finite
+        if let Some(first) = self.weight.iter().position(|&w| w > 0.0) {
+            let x0 = x[first];
+            x.slice_mut(s![..first]).fill(x0);
+        }
+        for i in 1..x.len() {
+            if self.weight[i] == 0.0 {
+                x[i] = x[i - 1];
+            }
+        }
+        x
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps_cover_band() {
+        let plan = SweepPlan::new(88e6, 108e6, 6e6);
+        let steps = plan.steps();
+        let usable = 6e6 * 0.75;
+        assert_eq!(steps[0] - usable / 2.0, 88e6);
+        assert!(steps[steps.len() - 1] + usable / 2.0 >= 108e6);
+        assert!(steps.windows(2).all(|w| w[1] - w[0] == usable * 0.9));
+    }
+
+    #[test]
+    fn check() {
+        let plan = SweepPlan::new(88e6, 108e6, 6e6);
+        assert!(plan.check().is_ok());
+        for (edge, overlap) in [(0.5, 0.1), (-0.1, 0.1), (0.1, 1.0), (0.1, -0.5)] {
+            assert!(
+                SweepPlan {
+                    edge,
+                    overlap,
+                    ..plan.clone()
+                }
+                .check()
+                .is_err()
+            );
+        }
+    }
+}
@@ -0,0 +1,391 @@
+use num::Complex;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use rand_distr::{Exp, StandardNormal};
+use std::f64::consts::PI;
+
+use crate::source::{SampleSource, SourceError, Throttle};
+
+type Ftype = f32;
+
+/// Dispersion constant in s MHz^2 pc^-1 cm^3
+pub const KDM: f64 = 4.148808e3;
+
+/// One additive component of the synthetic signal.
+///
+/// Frequencies are baseband offsets in Hz, times in s, amplitudes in ADC units.
+#[derive(Debug, Clone)]
+pub enum Signal {
+    Noise {
+        sigma: f64,
+    },
+    Tone {
+        freq: f64,
+        ampl: f64,
+    },
+    Chirp {
+        f_start: f64,
+        f_stop: f64,
+        period: f64,
+        ampl: f64,
+    },
+    /// Periodic pulse dispersed with `dm` (pc cm^-3), arriving first at the top of the band.
+    Pulse {
+        period: f64,
+        width: f64,
+        dm: f64,
+        ampl: f64,
+    },
+    /// Broadband bursts at Poisson distributed times, `rate` bursts per second.
+    Rfi {
+        rate: f64,
+        duration: f64,
+        ampl: f64,
+    },
+}
+
+impl std::str::FromStr for Signal {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(':');
+        let kind = parts.next().unwrap_or_default();
+        let values = parts
+            .map(|x| {
+                x.parse::<f64>()
+                    .map_err(|e| format!("invalid number '{x}' in '{s}': {e}"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let expect = |n: usize, usage: &str| {
+            if values.len() == n {
+                Ok(())
+            } else {
+                Err(format!("'{s}' should be {usage}"))
+            }
+        };
+
+        match kind {
+            "noise" => {
+                expect(1, "noise:<sigma>")?;
+                Ok(Signal::Noise { sigma: values[0] })
+            }
+            "tone" => {
+                expect(2, "tone:<freq>:<ampl>")?;
+                Ok(Signal::Tone {
+                    freq: values[0],
+                    ampl: values[1],
+                })
+            }
+            "chirp" => {
+                expect(4, "chirp:<f_start>:<f_stop>:<period>:<ampl>")?;
+                Ok(Signal::Chirp {
+                    f_start: values[0],
+                    f_stop: values[1],
+                    period: values[2],
+                    ampl: values[3],
+                })
+            }
+            "pulse" => {
+                expect(4, "pulse:<period>:<width>:<dm>:<ampl>")?;
+                Ok(Signal::Pulse {
+                    period: values[0],
+                    width: values[1],
+                    dm: values[2],
+                    ampl: values[3],
+                })
+            }
+            "rfi" => {
+                expect(3, "rfi:<rate>:<duration>:<ampl>")?;
+                Ok(Signal::Rfi {
+                    rate: values[0],
+                    duration: values[1],
+                    ampl: values[2],
+                })
+            }
+            _ => Err(format!(
+                "unknown signal '{kind}', can be noise, tone, chirp, pulse or rfi"
+            )),
+        }
+    }
+}
+
+#[derive(Default)]
+struct SignalState {
+    phase: f64,
+    lowpass: Complex<f64>,
+    next_burst: u64,
+    burst_end: u64,
+}
+
+pub struct SynthSource {
+    signals: Vec<(Signal, SignalState)>,
+    sample_rate: f64,
+    center_freq: f64,
+    mtu: usize,
+    nchannels: usize,
+    sample_index: u64,
+    rng: StdRng,
+    throttle: Option<Throttle>,
+}
+
+impl SynthSource {
+    pub fn new(signals: Vec<Signal>, sample_rate: f64, center_freq: f64) -> SynthSource {
+        let mut source = SynthSource {
+            signals: signals
+                .into_iter()
+                .map(|s| (s, SignalState::default()))
+                .collect(),
+            sample_rate,
+            center_freq,
+            mtu: 65536,
+            nchannels: 1,
+            sample_index: 0,
+            rng: StdRng::seed_from_u64(0),
+            throttle: None,
+        };
+        source.schedule_first_bursts();
+        source
+    }
+
+    /// Bursts start after a wait like any other, rather than all at sample 0.
+    fn schedule_first_bursts(&mut self) {
+        let fs = self.sample_rate;
+        for (signal, state) in &mut self.signals {
+            if let Signal::Rfi { rate, .. } = *signal {
+                let wait = Exp::new(rate).map_or(f64::INFINITY, |d| self.rng.sample(d));
+                state.next_burst = (wait * fs) as u64;
+            }
+        }
+    }
+
+    /// Deliver samples no faster than `sample_rate`, like a real receiver.
+    pub fn throttled(mut self, realtime: bool) -> Self {
+        self.throttle = realtime.then(|| Throttle::new(self.sample_rate));
+        self
+    }
+
+    /// Noise is independent between channels, all other signals are common to all of them.
+    pub fn with_channels(mut self, nchannels: usize) -> Self {
+        self.nchannels = nchannels;
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self.schedule_first_bursts();
+        self
+    }
+
+    fn generate(
+        &mut self,
+        buffers: &mut [&mut [Complex<Ftype>]],
+        len: usize,
+    ) -> Result<(), SourceError> {
+        let fs = self.sample_rate;
+        let f_top = (self.center_freq + fs / 2.0) / 1e6;
+        let f_bottom = (self.center_freq - fs / 2.0) / 1e6;
+
+        let Some((buf, others)) = buffers.split_first_mut() else {
+            return Err(SourceError::Other("no buffer to read into".to_string()));
+        };
+        let buf = &mut buf[..len];
+        buf.iter_mut().for_each(|x| *x = Complex::default());
+
+        for (signal, state) in &mut self.signals {
+            let rng = &mut self.rng;
+            for (i, x) in buf.iter_mut().enumerate() {
+                let n = self.sample_index + i as u64;
+                let t = n as f64 / fs;
+                let v = match *signal {
+                    Signal::Noise { .. } => continue,
+                    Signal::Tone { freq, ampl } => {
+                        state.phase = (state.phase + 2.0 * PI * freq / fs) % (2.0 * PI);
+                        Complex::from_polar(ampl, state.phase)
+                    }
+                    Signal::Chirp {
+                        f_start,
+                        f_stop,
+                        period,
+                        ampl,
+                    } => {
+                        let f = f_start + (f_stop - f_start) * (t % period) / period;
+                        state.phase = (state.phase + 2.0 * PI * f / fs) % (2.0 * PI);
+                        Complex::from_polar(ampl, state.phase)
+                    }
+                    Signal::Pulse {
+                        period,
+                        width,
+                        dm,
+                        ampl,
+                    } => {
+                        // the pulse occupies [f_lead, f_trail] (MHz) at time tau after
+                        // its leading edge passed the top of the band
+                        let tau = t % period;
+                        let (f_lead, f_trail) = if dm > 0.0 {
+                            let freq_at =
+                                |tau: f64| (tau.max(0.0) / (KDM * dm) + f_top.powi(-2)).powf(-0.5);
+                            (freq_at(tau).max(f_bottom), freq_at(tau - width))
+                        } else if tau < width {
+                            (f_bottom, f_top)
+                        } else {
+                            (f_bottom, f_bottom)
+                        };
+                        if f_trail > f_bottom {
+                            let bw = ((f_trail - f_lead) * 1e6).max(fs * 1e-4);
+                            let a = (-2.0 * PI * bw / fs).exp();
+                            state.lowpass = state.lowpass * a + gauss(rng) * (1.0 - a);
+                            let f_mid = (f_lead + f_trail) / 2.0 * 1e6 - self.center_freq;
+                            state.phase = (state.phase + 2.0 * PI * f_mid / fs) % (2.0 * PI);
+                            let gain = ampl * ((1.0 + a) / (1.0 - a)).sqrt();
+                            state.lowpass * Complex::from_polar(gain, state.phase)
+                        } else {
+                            Complex::default()
+                        }
+                    }
+                    Signal::Rfi {
+                        rate,
+                        duration,
+                        ampl,
+                    } => {
+                        if n >= state.next_burst {
+                            let wait = Exp::new(rate).map_or(f64::INFINITY, |d| rng.sample(d));
+                            state.burst_end = n + (duration * fs) as u64;
+                            state.next_burst = state.burst_end.saturating_add((wait * fs) as u64);
+                        }
+                        if n < state.burst_end {
+                            gauss(rng) * ampl
+                        } else {
+                            Complex::default()
+                        }
+                    }
+                };
+                *x += Complex::new(v.re as Ftype, v.im as Ftype);
+            }
+        }
+
+        others
+            .iter_mut()
+            .for_each(|b| b[..len].copy_from_slice(buf));
+        for (signal, _) in &self.signals {
+            if let Signal::Noise { sigma } = *signal {
+                for x in buffers.iter_mut().flat_map(|b| b[..len].iter_mut()) {
+                    let v = gauss(&mut self.rng) * sigma;
+                    *x += Complex::new(v.re as Ftype, v.im as Ftype);
+                }
+            }
+        }
+        self.sample_index += len as u64;
+        Ok(())
+    }
+}
+
+/// Complex gaussian with unit total power
+fn gauss(rng: &mut StdRng) -> Complex<f64> {
+    Complex::new(rng.sample(StandardNormal), rng.sample(StandardNormal))
+        * std::f64::consts::FRAC_1_SQRT_2
+}
+
+impl SampleSource for SynthSource {
+    fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    fn channels(&self) -> usize {
+        self.nchannels
+    }
+
+    fn activate(&mut self) -> Result<(), SourceError> {
+        if let Some(ref mut t) = self.throttle {
+            t.reset();
+        }
+        Ok(())
+    }
+
+    fn deactivate(&mut self) -> Result<(), SourceError> {
+        Ok(())
+    }
+
+    fn read(&mut self, buffers: &mut [&mut [Complex<Ftype>]]) -> Result<usize, SourceError> {
+        let len = buffers
+            .iter()
+            .map(|b| b.len())
+            .min()
+            .unwrap_or(0)
+            .min(self.mtu);
+        self.generate(buffers, len)?;
+        if let Some(ref mut t) = self.throttle {
+            t.wait(len);
+        }
+        Ok(len)
+    }
+
+    fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(source: &mut SynthSource, len: usize) -> Vec<Complex<Ftype>> {
+        let mut buf = vec![Complex::default(); len];
+        let mut n = 0;
+        while n < len {
+            n += source.read(&mut [&mut buf[n..]]).unwrap();
+        }
+        buf
+    }
+
+    #[test]
+    fn tone_frequency() {
+        let (fs, freq) = (1e6, -123e3);
+        let mut source = SynthSource::new(vec![Signal::Tone { freq, ampl: 0.5 }], fs, 100e6);
+        let x = read(&mut source, 1000);
+        assert!(x.iter().all(|x| (x.norm() - 0.5).abs() < 1e-5));
+        for w in x.windows(2) {
+            let step = (w[1] * w[0].conj()).arg() as f64;
+            assert!((step - 2.0 * PI * freq / fs).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn no_buffers() {
+        let mut source = SynthSource::new(vec![Signal::Noise { sigma: 1.0 }], 1e6, 100e6);
+        assert!(source.read(&mut []).is_err());
+    }
+
+    #[test]
+    fn noise_level() {
+        let sigma = 0.1;
+        let mut source = SynthSource::new(vec![Signal::Noise { sigma }], 1e6, 100e6);
+        let x = read(&mut source, 100_000);
+        let power = x.iter().map(|x| x.norm_sqr() as f64).sum::<f64>() / x.len() as f64;
+        assert!(
+            (power / (sigma * sigma) - 1.0).abs() < 0.02,
+            "power {power}"
+        );
+        let mean = x.iter().sum::<Complex<Ftype>>() / x.len() as Ftype;
+        assert!(mean.norm() < 1e-3);
+    }
+
+    #[test]
+    fn burst_rate() {
+        // bursts follow each other after their duration plus an exponential wait
+        let (fs, rate, duration) = (1e5, 100.0, 1e-3);
+        let signals = vec![Signal::Rfi {
+            rate,
+            duration,
+            ampl: 1.0,
+        }];
+        let mut source = SynthSource::new(signals, fs, 100e6).with_seed(3);
+        let secs = 10.0;
+        let x = read(&mut source, (secs * fs) as usize);
+        let on = x.iter().map(|x| x.norm() > 0.0).collect::<Vec<_>>();
+        let bursts = on.windows(2).filter(|w| !w[0] && w[1]).count() as f64;
+        let expected = secs / (duration + 1.0 / rate);
+        assert!((bursts / expected - 1.0).abs() < 0.1, "{bursts} bursts");
+        let duty = on.iter().filter(|&&x| x).count() as f64 / on.len() as f64;
+        assert!((duty / (expected * duration / secs) - 1.0).abs() < 0.1);
+    }
+}
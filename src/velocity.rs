@@ -0,0 +1,649 @@
+//! Radial velocities of spectral lines and averaging onto a fixed velocity grid.
+//!
+//! `channelize` labels its frequency axes with the velocities of a `VelocityFrame`.
+//! Outside the topocentric frame the correction for the motion of the observer needs the
+//! site and the pointing, either J2000 coordinates or the azimuth and elevation of a dish
+//! left in place, and is good to about 0.03 km/s.
+//!
+//! A `GridAverage` interpolates every average onto fixed velocity channels and keeps a
+//! running average weighted by integration time. A saved one is carried on with as long as
+//! it was made with the same rest frequency, convention, frame and grid, so that several
+//! observations add up; retuning stops it.
+
+use ndarray::s;
+use std::io::Write;
+
+use crate::daq::Spectrum;
+
+/// speed of light in km/s
+pub const C: f64 = 299_792.458;
+/// rest frequency of the HI hyperfine line in Hz
+pub const HI_FREQ: f64 = 1_420_405_751.768;
+
+/// How a frequency shift is expressed as a velocity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Convention {
+    /// `c (1 - f / f0)`
+    #[default]
+    Radio,
+    /// `c (f0 / f - 1)`
+    Optical,
+}
+
+impl Convention {
+    /// velocity in km/s of `freq` for a line at `rest_freq`
+    pub fn velocity(self, freq: f64, rest_freq: f64) -> f64 {
+        match self {
+            Convention::Radio => C * (1.0 - freq / rest_freq),
+            Convention::Optical => C * (rest_freq / freq - 1.0),
+        }
+    }
+
+    /// frequency of a line at `rest_freq` moving at `velocity` km/s
+    pub fn freq(self, velocity: f64, rest_freq: f64) -> f64 {
+        match self {
+            Convention::Radio => rest_freq * (1.0 - velocity / C),
+            Convention::Optical => rest_freq / (1.0 + velocity / C),
+        }
+    }
+}
+
+impl std::str::FromStr for Convention {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "radio" => Ok(Convention::Radio),
+            "optical" => Ok(Convention::Optical),
+            _ => Err(format!(
+                "unknown velocity convention '{s}', can be radio or optical"
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Convention {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Convention::Radio => write!(fmt, "radio"),
+            Convention::Optical => write!(fmt, "optical"),
+        }
+    }
+}
+
+/// Rest frame velocities are measured in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Frame {
+    /// as observed, no correction
+    #[default]
+    Topocentric,
+    Barycentric,
+    /// barycentric plus the standard solar motion
+    Lsr,
+}
+
+impl std::str::FromStr for Frame {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "topo" => Ok(Frame::Topocentric),
+            "bary" => Ok(Frame::Barycentric),
+            "lsr" => Ok(Frame::Lsr),
+            _ => Err(format!(
+                "unknown velocity frame '{s}', can be topo, bary or lsr"
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Frame {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Frame::Topocentric => write!(fmt, "topo"),
+            Frame::Barycentric => write!(fmt, "bary"),
+            Frame::Lsr => write!(fmt, "lsr"),
+        }
+    }
+}
+
+/// Observer, `<lat>:<lon>[:<height>]` in degrees, longitude east of Greenwich, and m.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Site {
+    pub lat: f64,
+    pub lon: f64,
+    pub height: f64,
+}
+
+impl std::str::FromStr for Site {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s
+            .split(':')
+            .map(|x| {
+                x.parse::<f64>()
+                    .map_err(|e| format!("bad value '{x}' in '{s}': {e}"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        match values[..] {
+            [lat, lon] => Ok(Site {
+                lat,
+                lon,
+                height: 0.0,
+            }),
+            [lat, lon, height] => Ok(Site { lat, lon, height }),
+            _ => Err(format!("expected <lat>:<lon>[:<height>], got '{s}'")),
+        }
+    }
+}
+
+impl std::fmt::Display for Site {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(fmt, "{}:{}:{}", self.lat, self.lon, self.height)
+    }
+}
+
+/// Direction observed, in degrees.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pointing {
+    /// `radec:<ra>:<dec>`, J2000
+    RaDec { ra: f64, dec: f64 },
+    /// `azel:<az>:<el>`, azimuth from north through east, for a dish left in place while
+    /// the sky drifts by
+    AzEl { az: f64, el: f64 },
+}
+
+impl std::str::FromStr for Pointing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(':');
+        let kind = parts.next().unwrap();
+        let values = parts
+            .map(|x| {
+                x.parse::<f64>()
+                    .map_err(|e| format!("bad value '{x}' in '{s}': {e}"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        match (kind, &values[..]) {
+            ("radec", &[ra, dec]) => Ok(Pointing::RaDec { ra, dec }),
+            ("azel", &[az, el]) => Ok(Pointing::AzEl { az, el }),
+            _ => Err(format!(
+                "expected radec:<ra>:<dec> or azel:<az>:<el>, got '{s}'"
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Pointing {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Pointing::RaDec { ra, dec } => write!(fmt, "radec:{ra}:{dec}"),
+            Pointing::AzEl { az, el } => write!(fmt, "azel:{az}:{el}"),
+        }
+    }
+}
+
+/// Turns topocentric frequencies into velocities in a `Frame`.
+///
+/// The correction is the velocity of the observer towards the source relative to the
+/// frame: the rotation of the Earth, its orbit from a low precision solar ephemeris, and
+/// for the LSR the standard solar motion of 20 km/s towards RA 18h03m50.29s,
+/// Dec +30d00m16.8s (J2000). J2000 directions are precessed to the equator of date the
+/// other terms are worked out in. Nutation, the Earth's motion about the Earth-Moon
+/// barycentre and the Sun's about the solar system barycentre are ignored, which keeps
+/// it within about 0.03 km/s.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VelocityFrame {
+    /// in Hz
+    pub rest_freq: f64,
+    pub convention: Convention,
+    pub frame: Frame,
+    /// needed for any but the topocentric frame
+    pub site: Option<Site>,
+    pub pointing: Option<Pointing>,
+}
+
+impl VelocityFrame {
+    /// Topocentric radio velocities of the line at `rest_freq`.
+    pub fn new(rest_freq: f64) -> VelocityFrame {
+        VelocityFrame {
+            rest_freq,
+            convention: Convention::default(),
+            frame: Frame::default(),
+            site: None,
+            pointing: None,
+        }
+    }
+
+    pub fn check(&self) -> Result<(), String> {
+        if self.frame != Frame::Topocentric && (self.site.is_none() || self.pointing.is_none()) {
+            return Err(format!(
+                "the {} frame needs the site and the pointing",
+                self.frame
+            ));
+        }
+        Ok(())
+    }
+
+    /// velocity of the observer towards the source relative to the frame at `time_ns`
+    /// (ns since the unix epoch), in km/s
+    pub fn correction(&self, time_ns: i64) -> f64 {
+        let (Some(site), Some(pointing)) = (self.site, self.pointing) else {
+            return 0.0;
+        };
+        if self.frame == Frame::Topocentric {
+            return 0.0;
+        }
+        let jd = time_ns as f64 / 86400e9 + 2440587.5;
+        let lst = (gmst(jd) + site.lon).to_radians();
+        let lat = site.lat.to_radians();
+        let source = match pointing {
+            Pointing::RaDec { ra, dec } => precess(unit(ra.to_radians(), dec.to_radians()), jd),
+            Pointing::AzEl { az, el } => {
+                let (ha, dec) = hadec(az.to_radians(), el.to_radians(), lat);
+                unit(lst - ha, dec)
+            }
+        };
+
+        // Earth's rotation
+        let r = (6378.137 + site.height / 1e3) * lat.cos();
+        let omega = 7.2921159e-5;
+        let mut v = [-omega * r * lst.sin(), omega * r * lst.cos(), 0.0];
+        // Earth's orbit
+        let orbit = earth_velocity(jd);
+        v.iter_mut().zip(orbit).for_each(|(a, b)| *a += b);
+        if self.frame == Frame::Lsr {
+            let sun = precess(
+                unit(270.9595_f64.to_radians(), 30.0047_f64.to_radians()),
+                jd,
+            );
+            v.iter_mut().zip(sun).for_each(|(a, b)| *a += 20.0 * b);
+        }
+        v.iter().zip(source).map(|(a, b)| a * b).sum()
+    }
+
+    /// velocity in the frame of topocentric frequency `freq`, given the `correction`
+    pub fn velocity(&self, freq: f64, correction: f64) -> f64 {
+        self.convention
+            .velocity(freq / (1.0 + correction / C), self.rest_freq)
+    }
+
+    /// inverse of `velocity`
+    pub fn freq(&self, velocity: f64, correction: f64) -> f64 {
+        self.convention.freq(velocity, self.rest_freq) * (1.0 + correction / C)
+    }
+
+    /// e.g. `lsr radio km/s`
+    pub fn unit(&self) -> String {
+        format!("{} {} km/s", self.frame, self.convention)
+    }
+}
+
+/// unit vector towards `ra`, `dec` in radians
+fn unit(ra: f64, dec: f64) -> [f64; 3] {
+    [dec.cos() * ra.cos(), dec.cos() * ra.sin(), dec.sin()]
+}
+
+/// Greenwich mean sidereal time at Julian date `jd`, in degrees
+fn gmst(jd: f64) -> f64 {
+    (280.46061837 + 360.98564736629 * (jd - 2451545.0)).rem_euclid(360.0)
+}
+
+/// hour angle and declination of azimuth `az` and elevation `el` seen from latitude
+/// `lat`, all in radians
+fn hadec(az: f64, el: f64, lat: f64) -> (f64, f64) {
+    let dec = (lat.sin() * el.sin() + lat.cos() * el.cos() * az.cos()).asin();
+    let ha = (-az.sin() * el.cos()).atan2(lat.cos() * el.sin() - lat.sin() * el.cos() * az.cos());
+    (ha, dec)
+}
+
+/// J2000 direction `x` in the mean equator and equinox of Julian date `jd`, with the
+/// IAU 1976 precession angles
+fn precess(x: [f64; 3], jd: f64) -> [f64; 3] {
+    let t = (jd - 2451545.0) / 36525.0;
+    let arcsec = |a: f64| (a / 3600.0).to_radians();
+    let zeta = arcsec(t * (2306.2181 + t * (0.30188 + t * 0.017998)));
+    let z = arcsec(t * (2306.2181 + t * (1.09468 + t * 0.018203)));
+    let theta = arcsec(t * (2004.3109 - t * (0.42665 + t * 0.041833)));
+    let (sz, cz) = zeta.sin_cos();
+    let (s, c) = z.sin_cos();
+    let (st, ct) = theta.sin_cos();
+    let p = [
+        [cz * ct * c - sz * s, -sz * ct * c - cz * s, -st * c],
+        [cz * ct * s + sz * c, -sz * ct * s + cz * c, -st * s],
+        [cz * st, -sz * st, ct],
+    ];
+    p.map(|row| row.iter().zip(x).map(|(a, b)| a * b).sum())
+}
+
+/// heliocentric velocity of the Earth at Julian date `jd`, equatorial, in km/s
+fn earth_velocity(jd: f64) -> [f64; 3] {
+    // geocentric position of the Sun in AU, from the Astronomical Almanac's low
+    // precision formulae
+    let sun = |jd: f64| {
+        let n = jd - 2451545.0;
+        let l = (280.460 + 0.9856474 * n).to_radians();
+        let g = (357.528 + 0.9856003 * n).to_radians();
+        let lambda = l + (1.915 * g.sin() + 0.020 * (2.0 * g).sin()).to_radians();
+        let r = 1.00014 - 0.01671 * g.cos() - 0.00014 * (2.0 * g).cos();
+        let eps = (23.439 - 0.0000004 * n).to_radians();
+        [
+            r * lambda.cos(),
+            r * lambda.sin() * eps.cos(),
+            r * lambda.sin() * eps.sin(),
+        ]
+    };
+    let (before, after) = (sun(jd - 0.5), sun(jd + 0.5));
+    // the Earth moves opposite to the Sun's apparent motion, AU per day to km/s
+    let au_per_day = 149_597_870.7 / 86400.0;
+    [0, 1, 2].map(|i| -(after[i] - before[i]) * au_per_day)
+}
+
+/// Velocity channels, `<start>:<stop>:<step>` in km/s.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VelocityGrid {
+    pub start: f64,
+    pub step: f64,
+    pub n: usize,
+}
+
+impl VelocityGrid {
+    pub fn velocity(&self, i: usize) -> f64 {
+        self.start + i as f64 * self.step
+    }
+}
+
+impl std::str::FromStr for VelocityGrid {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s
+            .split(':')
+            .map(|x| {
+                x.parse::<f64>()
+                    .map_err(|e| format!("bad value '{x}' in '{s}': {e}"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let [start, stop, step] = values[..] else {
+            return Err(format!("expected <start>:<stop>:<step>, got '{s}'"));
+        };
+        if step <= 0.0 || stop < start {
+            return Err(format!("empty velocity grid '{s}'"));
+        }
+        Ok(VelocityGrid {
+            start,
+            step,
+            n: ((stop - start) / step + 1e-9).floor() as usize + 1,
+        })
+    }
+}
+
+impl std::fmt::Display for VelocityGrid {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            fmt,
+            "{}:{}:{}",
+            self.start,
+            self.velocity(self.n - 1),
+            self.step
+        )
+    }
+}
+
+/// Spectra interpolated onto a `VelocityGrid` in a `VelocityFrame` and averaged,
+/// weighted by integration time, possibly over several observations.
+#[derive(Debug, Clone)]
+pub struct GridAverage {
+    pub frame: VelocityFrame,
+    pub grid: VelocityGrid,
+    /// of the spectra averaged, as in `Spectrum::unit`
+    pub unit: Option<String>,
+    sum: Vec<f64>,
+    weight: Vec<f64>,
+}
+
+impl GridAverage {
+    pub fn new(frame: VelocityFrame, grid: VelocityGrid) -> GridAverage {
+        GridAverage {
+            frame,
+            grid,
+            unit: None,
+            sum: vec![0.0; grid.n],
+            weight: vec![0.0; grid.n],
+        }
+    }
+
+    /// weight of the best covered grid channel, 0 if nothing has been added
+    pub fn weight(&self) -> f64 {
+        self.weight.iter().cloned().fold(0.0, f64::max)
+    }
+
+    /// Add the first IF of `x`, tuned to `freq`, counting `weight`, e.g. its integration
+    /// time. Grid channels outside the band or next to a flagged channel are left alone.
+    pub fn add(
+        &mut self,
+        x: &Spectrum,
+        freq: f64,
+        sample_rate: f64,
+        weight: f64,
+    ) -> Result<(), String> {
+        let unit = x.unit;
+        if self.weight() > 0.0 && self.unit.as_deref() != unit {
+            return Err(format!(
+                "cannot average {} spectra with {} ones",
+                unit.unwrap_or("uncalibrated"),
+                self.unit.as_deref().unwrap_or("uncalibrated")
+            ));
+        }
+        self.unit = unit.map(|u| u.to_string());
+        let nch = x.data.len() / x.nifs;
+        let spectrum = x.data.slice(s![..nch]);
+        let df = sample_rate / nch as f64;
+        let f_lo = freq - sample_rate / 2.0;
+        let correction = self.frame.correction(x.time_ns);
+        for i in 0..self.grid.n {
+            let f = self.frame.freq(self.grid.velocity(i), correction);
+            // fractional channel, channel `c` being centred at `f_lo + c df`
+            let p = (f - f_lo) / df;
+            if p < 0.0 || p > (nch - 1) as f64 {
+                continue;
+            }
+            let c = (p as usize).min(nch.saturating_sub(2));
+            let c1 = (c + 1).min(nch - 1);
+            if x.flags.as_ref().is_some_and(|f| f[c] || f[c1]) {
+                continue;
+            }
+            let a = p - c as f64;
+            let x = spectrum[c] as f64 * (1.0 - a) + spectrum[c1] as f64 * a;
+            self.sum[i] += x * weight;
+            self.weight[i] += weight;
+        }
+        Ok(())
+    }
+
+    /// average per grid channel, NaN where nothing was added
+    pub fn mean(&self) -> Vec<f64> {
+        self.sum
+            .iter()
+            .zip(&self.weight)
+            .map(|(&s, &w)| if w > 0.0 { s / w } else { f64::NAN })
+            .collect()
+    }
+
+    /// Lines of `<velocity> <average> <weight>` after a commented header.
+    pub fn save(&self, path: &str) -> std::io::Result<()> {
+        let mut f = std::io::BufWriter::new(std::fs::File::create(path)?);
+        writeln!(f, "# rest_freq = {}", self.frame.rest_freq)?;
+        writeln!(f, "# convention = {}", self.frame.convention)?;
+        writeln!(f, "# frame = {}", self.frame.frame)?;
+        writeln!(f, "# grid = {}", self.grid)?;
+        if let Some(ref u) = self.unit {
+            writeln!(f, "# unit = {u}")?;
+        }
+        for (i, (m, w)) in self.mean().iter().zip(&self.weight).enumerate() {
+            writeln!(f, "{:.4} {m:e} {w:e}", self.grid.velocity(i))?;
+        }
+        f.flush()
+    }
+
+    /// Carry on from an average saved with the same rest frequency, convention, frame
+    /// and grid, e.g. by an earlier observation.
+    pub fn resume(&mut self, path: &str) -> Result<(), String> {
+        let s = std::fs::read_to_string(path).map_err(|e| format!("failed to read {path}: {e}"))?;
+        let mut header = std::collections::HashMap::new();
+        let mut rows = vec![];
+        for line in s.lines() {
+            if let Some(h) = line.strip_prefix('#') {
+                if let Some((k, v)) = h.split_once('=') {
+                    header.insert(k.trim(), v.trim());
+                }
+                continue;
+            }
+            let values = line
+                .split_whitespace()
+                .map(|x| x.parse::<f64>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| format!("{path}: bad line '{line}': {e}"))?;
+            if let [_, m, w] = values[..] {
+                rows.push((m, w));
+            }
+        }
+        let expect = |k: &str, v: String| match header.get(k) {
+            Some(&h) if h == v => Ok(()),
+            h => Err(format!(
+                "{path}: {k} is {}, not {v}",
+                h.unwrap_or(&"missing")
+            )),
+        };
+        expect("rest_freq", self.frame.rest_freq.to_string())?;
+        expect("convention", self.frame.convention.to_string())?;
+        expect("frame", self.frame.frame.to_string())?;
+        expect("grid", self.grid.to_string())?;
+        if rows.len() != self.grid.n {
+            return Err(format!(
+                "{path}: {} channels, not {}",
+                rows.len(),
+                self.grid.n
+            ));
+        }
+        self.unit = header.get("unit").map(|u| u.to_string());
+        for (i, (m, w)) in rows.into_iter().enumerate() {
+            if w > 0.0 {
+                self.sum[i] = m * w;
+                self.weight[i] = w;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Julian date 2446896.30625, 1987 April 10 19:21 UT, examples 12.b and 13.b of
+    /// Meeus, Astronomical Algorithms
+    const MEEUS_JD: f64 = 2446896.30625;
+
+    fn lsr(pointing: Pointing) -> VelocityFrame {
+        VelocityFrame {
+            frame: Frame::Lsr,
+            site: Some(Site {
+                lat: 52.0,
+                lon: 6.6,
+                height: 0.0,
+            }),
+            pointing: Some(pointing),
+            ..VelocityFrame::new(HI_FREQ)
+        }
+    }
+
+    #[test]
+    fn sidereal_time() {
+        // 13h10m46.3668s at 0h UT and 8h34m57.0896s at 19:21 UT (examples 12.a and 12.b)
+        assert!((gmst(2446895.5) - 197.693195).abs() < 1e-4);
+        assert!((gmst(MEEUS_JD) - 128.7378734).abs() < 1e-4);
+    }
+
+    #[test]
+    fn azel_to_radec() {
+        // Venus from the USNO in Washington (example 13.b): azimuth 68.0337 deg from the
+        // south, elevation 15.1249 deg, hour angle 64.352133 deg, RA 23h09m16.641s,
+        // Dec -6d43m11.61s, the RA from apparent rather than mean sidereal time
+        let (lat, lon) = (38.921389_f64, -77.065556);
+        let (ha, dec) = hadec(
+            (68.0337_f64 + 180.0).to_radians(),
+            15.1249_f64.to_radians(),
+            lat.to_radians(),
+        );
+        assert!((ha.to_degrees() - 64.352133).abs() < 1e-3);
+        assert!((dec.to_degrees() + 6.719892).abs() < 1e-3);
+        let ra = (gmst(MEEUS_JD) + lon - ha.to_degrees()).rem_euclid(360.0);
+        assert!((ra - 347.319338).abs() < 2e-3);
+    }
+
+    #[test]
+    fn precession() {
+        // theta Persei, with its proper motion applied, to 2028 November 13.19 TD
+        // (example 21.b)
+        let [x, y, z] = precess(
+            unit(41.054063_f64.to_radians(), 49.227750_f64.to_radians()),
+            2462088.69,
+        );
+        assert!((y.atan2(x).to_degrees() - 41.547214).abs() < 1e-4);
+        assert!((z.asin().to_degrees() - 49.348483).abs() < 1e-4);
+    }
+
+    #[test]
+    fn orbit() {
+        // 30.29 km/s at perihelion, 2000 January 3, and 29.29 km/s at aphelion, July 4
+        let speed = |jd| earth_velocity(jd).iter().map(|v| v * v).sum::<f64>().sqrt();
+        assert!((speed(2451546.7) - 30.29).abs() < 0.02);
+        assert!((speed(2451729.5) - 29.29).abs() < 0.02);
+    }
+
+    #[test]
+    fn standard_solar_motion() {
+        // 20 km/s towards RA 18h03m50.29s, Dec +30d00m16.8s on top of the barycentric
+        // correction, 2026-10-18 00:00 UT
+        let time_ns = 1_792_281_600_000_000_000;
+        for (pointing, solar) in [
+            (
+                Pointing::RaDec {
+                    ra: 270.9595,
+                    dec: 30.0047,
+                },
+                20.0,
+            ),
+            (
+                Pointing::RaDec {
+                    ra: 90.9595,
+                    dec: -30.0047,
+                },
+                -20.0,
+            ),
+            (
+                Pointing::RaDec {
+                    ra: 0.9595,
+                    dec: 0.0,
+                },
+                0.0,
+            ),
+        ] {
+            let lsr = lsr(pointing);
+            let bary = VelocityFrame {
+                frame: Frame::Barycentric,
+                ..lsr.clone()
+            };
+            let d = lsr.correction(time_ns) - bary.correction(time_ns);
+            assert!((d - solar).abs() < 0.01, "{d} {solar}");
+        }
+        let topo = VelocityFrame {
+            frame: Frame::Topocentric,
+            ..lsr(Pointing::AzEl { az: 0.0, el: 90.0 })
+        };
+        assert_eq!(topo.correction(time_ns), 0.0);
+    }
+}
@@ -0,0 +1,277 @@
+//! Second-stage channelization of selected coarse channels.
+//!
+//! The complex output of each selected coarse channel goes through a channelizer of its
+//! own, of which only the fine channels inside the coarse channel are kept, half of them
+//! for the 2x oversampled PFB. Fine channels are `sample_rate / hop / nch` wide, e.g.
+//! 23 Hz for 512 coarse channels at 6 Msps and 1024 fine channels. `channelize` selects
+//! the channels with the mouse and does not zoom while sweeping.
+
+use ndarray::{Array1, Array2};
+use num::Complex;
+use std::f64::consts::PI;
+
+use crate::{
+    channelizer::{Backend, Channelizer},
+    daq::Spectrum,
+    radiometer::ChannelRange,
+};
+
+type Ftype = f32;
+
+/// Second-stage channelization of some coarse channels of the first input.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ZoomConfig {
+    /// coarse channels, numbered as in the spectra, i.e. in increasing frequency
+    pub range: ChannelRange,
+    /// fine channels per coarse channel, before those outside it are trimmed
+    pub nch: usize,
+    pub tap_per_ch: usize,
+    pub backend: Backend,
+    /// fine spectra per average
+    pub n_average: usize,
+}
+
+impl ZoomConfig {
+    pub fn new(range: ChannelRange, nch: usize, n_average: usize) -> ZoomConfig {
+        ZoomConfig {
+            range,
+            nch,
+            tap_per_ch: 4,
+            backend: Backend::default(),
+            n_average,
+        }
+    }
+}
+
+/// Averaged fine spectrum of the coarse channels of a `ZoomConfig`.
+#[derive(Debug, Clone)]
+pub struct ZoomSpectrum {
+    /// power of the fine channels in increasing frequency, `sample_index` and `time_ns`
+    /// are those of the coarse row the average started at
+    pub spectrum: Spectrum,
+    pub range: ChannelRange,
+    /// frequency of the first fine channel relative to the centre frequency, in Hz
+    pub freq_offset: f64,
+    /// fine channel spacing in Hz
+    pub channel_bandwidth: f64,
+}
+
+/// Runs a fine `Channelizer` on each of the selected coarse channels and joins their
+/// outputs into one spectrum.
+///
+/// The coarse outputs of either backend are referred to the start of their frame, so
+/// channel `k` turns by `k * hop / nch` cycles per row; that is taken out before the
+/// fine stage. Coarse channels are `oversampling` times as far apart in time as needed,
+/// so only the middle `nch / oversampling` fine channels of each fall inside it and are
+/// kept, leaving one fine spectrum without overlaps or gaps.
+pub struct Zoom {
+    config: ZoomConfig,
+    fine: Vec<Channelizer>,
+    /// FFT column of each selected coarse channel
+    columns: Vec<usize>,
+    coarse_nch: usize,
+    coarse_hop: usize,
+    sample_rate: f64,
+    /// `row * coarse_hop` modulo `coarse_nch` of the next coarse row
+    phase: usize,
+    keep: usize,
+    freq_offset: f64,
+    channel_bandwidth: f64,
+    series: Vec<Complex<Ftype>>,
+    sum: Array1<Ftype>,
+    n: usize,
+    sample_index: u64,
+    time_ns: i64,
+    tainted: bool,
+}
+
+impl Zoom {
+    /// `nch` and `hop` are those of the coarse `Channelizer`.
+    pub fn new(config: ZoomConfig, nch: usize, hop: usize, sample_rate: f64) -> Zoom {
+        let range = config.range;
+        assert!(range.last < nch, "zoom range {range} beyond {nch} channels");
+        let fine = (range.first..=range.last)
+            .map(|_| Channelizer::new(config.backend, config.nch, config.tap_per_ch))
+            .collect::<Vec<_>>();
+        let oversampling = nch as f64 / hop as f64;
+        let keep = ((config.nch as f64 / oversampling).round() as usize).max(1);
+        let coarse_width = sample_rate / nch as f64;
+        let channel_bandwidth = sample_rate / hop as f64 / config.nch as f64;
+        Zoom {
+            columns: (range.first..=range.last)
+                .map(|c| (c + nch / 2) % nch)
+                .collect(),
+            fine,
+            coarse_nch: nch,
+            coarse_hop: hop,
+            sample_rate,
+            phase: 0,
+            keep,
+            // centre of coarse channel `first`, less what is kept of it below the centre
+            freq_offset: -sample_rate / 2.0
+                + range.first as f64 * coarse_width
+                + ((config.nch - keep) / 2) as f64 * channel_bandwidth
+                - (config.nch / 2) as f64 * channel_bandwidth,
+            channel_bandwidth,
+            series: vec![],
+            sum: Array1::zeros(keep * range.count()),
+            n: 0,
+            sample_index: 0,
+            time_ns: 0,
+            tainted: false,
+            config,
+        }
+    }
+
+    pub fn config(&self) -> &ZoomConfig {
+        &self.config
+    }
+
+    /// fine channels in each output
+    pub fn nch(&self) -> usize {
+        self.sum.len()
+    }
+
+    /// Feed the coarse rows of one chunk, in FFT order as they come out of the
+    /// `Channelizer`, returning the averages they complete. `sample_index` and `time_ns`
+    /// are those of the first row; an average starts at the first coarse row of its first
+    /// fine row, which may have been pushed with an earlier chunk.
+    pub fn push(
+        &mut self,
+        rows: &Array2<Complex<Ftype>>,
+        sample_index: u64,
+        time_ns: i64,
+        tainted: bool,
+    ) -> Vec<ZoomSpectrum> {
+        let nrows = rows.nrows();
+        let (nch, hop) = (self.coarse_nch, self.coarse_hop);
+        let fine_nch = self.config.nch;
+        let lo = (fine_nch - self.keep) / 2;
+        // coarse rows held back by the fine stage, the first fine row starts with them
+        let pending = self.fine[0].buffered();
+        let fine_hop = self.fine[0].hop();
+        let mut fine_rows = Vec::with_capacity(self.fine.len());
+        for (fine, &k) in self.fine.iter_mut().zip(&self.columns) {
+            self.series.clear();
+            self.series.extend((0..nrows).map(|m| {
+                let turns = (k * ((self.phase + m * hop) % nch)) % nch;
+                let (s, c) = (-2.0 * PI * turns as f64 / nch as f64).sin_cos();
+                rows[(m, k)] * Complex::new(c as Ftype, s as Ftype)
+            }));
+            fine_rows.push(fine.analyze(&self.series));
+        }
+        self.phase = (self.phase + nrows * hop) % nch;
+
+        let mut out = vec![];
+        for i in 0..fine_rows[0].nrows() {
+            if self.n == 0 {
+                let offset = (i * fine_hop * hop) as i64 - (pending * hop) as i64;
+                self.sample_index = sample_index.saturating_add_signed(offset);
+                self.time_ns = time_ns + (offset as f64 * 1e9 / self.sample_rate) as i64;
+                self.tainted = false;
+            }
+            self.tainted |= tainted;
+            for (s, x) in fine_rows.iter().enumerate() {
+                for j in 0..self.keep {
+                    let f = (lo + j + fine_nch / 2) % fine_nch;
+                    self.sum[s * self.keep + j] += x[(i, f)].norm_sqr();
+                }
+            }
+            self.n += 1;
+            if self.n == self.config.n_average {
+                out.push(self.take());
+            }
+        }
+        out
+    }
+
+    fn take(&mut self) -> ZoomSpectrum {
+        let n = std::mem::take(&mut self.n);
+        let data = self.sum.mapv(|x| x / n as Ftype);
+        self.sum.fill(0.0);
+        let fine_hop = self.fine[0].hop() as u64;
+        ZoomSpectrum {
+            spectrum: Spectrum {
+                data,
+                nifs: 1,
+                tainted: self.tainted,
+                sample_index: self.sample_index,
+                nsamples: n as u64 * fine_hop * self.coarse_hop as u64,
+                time_ns: self.time_ns,
+                flags: None,
+                unit: None,
+            },
+            range: self.config.range,
+            freq_offset: self.freq_offset,
+            channel_bandwidth: self.channel_bandwidth,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channelizer::Window;
+
+    const RECT: Backend = Backend::Welch {
+        window: Window::Rect,
+        overlap: 0.0,
+    };
+
+    fn config(channel: usize, n_average: usize) -> ZoomConfig {
+        ZoomConfig {
+            backend: RECT,
+            ..ZoomConfig::new(
+                ChannelRange {
+                    first: channel,
+                    last: channel,
+                },
+                4,
+                n_average,
+            )
+        }
+    }
+
+    #[test]
+    fn tone_lands_in_its_fine_channel() {
+        let sample_rate = 8e3;
+        let freq = 1250.0;
+        let x = (0..8 * 64)
+            .map(|i| Complex::from_polar(1.0, (2.0 * PI * freq * i as f64 / sample_rate) as Ftype))
+            .collect::<Vec<_>>();
+        let rows = Channelizer::new(RECT, 8, 4).analyze(&x);
+        // coarse channel 5 spans 500 to 1500 Hz
+        let mut zoom = Zoom::new(config(5, 16), 8, 8, sample_rate);
+        let out = zoom.push(&rows, 0, 0, false);
+        assert_eq!(out.len(), 1);
+        let x = &out[0];
+        assert_eq!(x.spectrum.data.len(), 4);
+        let peak = (0..4)
+            .max_by(|&a, &b| x.spectrum.data[a].total_cmp(&x.spectrum.data[b]))
+            .unwrap();
+        assert_eq!(x.freq_offset + peak as f64 * x.channel_bandwidth, freq);
+    }
+
+    #[test]
+    fn timing_across_chunks() {
+        let sample_rate = 8e3;
+        let mut zoom = Zoom::new(config(2, 1), 8, 8, sample_rate);
+        let mut out = vec![];
+        let mut first = 0;
+        // fine rows take 4 coarse rows of 8 samples, so most straddle two chunks
+        for nrows in [3, 5, 7, 1, 6, 2] {
+            let time_ns = (first as f64 * 1e9 / sample_rate) as i64;
+            out.extend(zoom.push(&Array2::zeros((nrows, 8)), first, time_ns, false));
+            first += nrows as u64 * 8;
+        }
+        assert_eq!(out.len(), 6);
+        for (i, x) in out.iter().enumerate() {
+            let x = &x.spectrum;
+            assert_eq!((x.sample_index, x.nsamples), (i as u64 * 32, 32));
+            assert_eq!(
+                x.time_ns,
+                (x.sample_index as f64 * 1e9 / sample_rate) as i64
+            );
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use ndarray::Array1;
+use soapy_spec_acc::{
+    calibration::{CalMethod, CalSetup, CalSolution},
+    daq::{DaqConfig, DaqEvent, run_daq},
+    synth::{Signal, SynthSource},
+};
+
+#[test]
+fn tone_lands_in_its_channel() {
+    let nch = 64;
+    let sample_rate = 1e6;
+    let signals = vec![
+        Signal::Noise { sigma: 0.01 },
+        Signal::Tone {
+            freq: 250e3,
+            ampl: 0.1,
+        },
+    ];
+    let source = SynthSource::new(signals, sample_rate, 100e6).with_seed(1);
+    let daq = run_daq(source, DaqConfig::new(nch, 4, 16)).unwrap();
+    // spectra run from -fs/2 up, channel nch/2 is the centre frequency
+    let expected = nch / 2 + (250e3 / (sample_rate / nch as f64)) as usize;
+    for _ in 0..4 {
+        let x = daq.rx_averaged[0].recv().unwrap();
+        assert_eq!(x.data.len(), nch);
+        let peak = (0..nch)
+            .max_by(|&a, &b| x.data[a].total_cmp(&x.data[b]))
+            .unwrap();
+        assert_eq!(peak, expected);
+    }
+    assert!(daq.join().is_ok());
+}
+
+#[test]
+fn calibration_of_another_setup_is_dropped() {
+    let sample_rate = 1e6;
+    let source = SynthSource::new(vec![Signal::Noise { sigma: 0.1 }], sample_rate, 100e6);
+    let daq = run_daq(source, DaqConfig::new(64, 4, 16)).unwrap();
+    let setup = CalSetup {
+        freq: 100e6,
+        sample_rate,
+        nch: 32,
+        gains: String::new(),
+    };
+    let c = CalSolution::solve(CalMethod::Bandpass, setup, &[Array1::ones(32)]).unwrap();
+    *daq.calibration.lock().unwrap() = Some(c);
+    loop {
+        if let DaqEvent::CalibrationDropped(e) = daq.rx_events.recv().unwrap() {
+            assert!(e.contains("32 channels"), "{e}");
+            break;
+        }
+    }
+    assert!(daq.calibration.lock().unwrap().is_none());
+    assert!(daq.rx_averaged[0].recv().unwrap().unit.is_none());
+    assert!(daq.join().is_ok());
+}